@@ -0,0 +1,45 @@
+use crate::trace::{EventRecord, TraceSummary};
+use trace_recorder_parser::streaming::{Error, RecorderData};
+
+pub use context::{ContextAnalyzer, ContextInterval, ContextStats, MigrationStats};
+pub use counters::{EventTypeCounter, UserEventChannelCounter};
+pub use folded::FoldedStackAnalyzer;
+pub use gaps::{DroppedEventAnalyzer, DroppedEventGap};
+pub use stack::{StackAnalyzer, StackStats};
+pub use task_state::{
+    is_blocking, is_failed, BlockReason, TaskState, TaskStateAnalyzer, TaskStateStats,
+    TaskStateTracker, WaitStats,
+};
+
+mod context;
+mod counters;
+mod folded;
+mod gaps;
+mod stack;
+mod task_state;
+
+/// Consumer of the event stream, driven by [`TraceReader::run`](crate::trace::TraceReader::run).
+///
+/// All callbacks have empty default implementations, so an analyzer only
+/// needs to implement what it's interested in.
+pub trait Analyzer {
+    /// Called with the bytes of the PSF header as recorded when a session starts,
+    /// before `on_header` or `on_restart`
+    fn on_session_header(&mut self, _data: &[u8]) {}
+
+    /// Called once with the recorder data of the first session, before any events
+    fn on_header(&mut self, _rd: &RecorderData) {}
+
+    /// Called for every event
+    fn on_event(&mut self, _rd: &RecorderData, _record: &EventRecord) {}
+
+    /// Called for every event that couldn't be decoded, with its file offset
+    fn on_error(&mut self, _rd: &RecorderData, _offset: u64, _error: &Error) {}
+
+    /// Called when the trace stream restarted, with the recorder data of the new session.
+    /// Event timestamps start over from zero after a restart.
+    fn on_restart(&mut self, _rd: &RecorderData) {}
+
+    /// Called once at the end of the stream
+    fn finish(&mut self, _rd: &RecorderData, _summary: &TraceSummary) {}
+}
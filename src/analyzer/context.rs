@@ -0,0 +1,225 @@
+use crate::analyzer::Analyzer;
+use crate::event::{ContextHandle, ContextSwitch, CoreId};
+use crate::time::DurationTicks;
+use crate::trace::{EventRecord, TraceSummary};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use trace_recorder_parser::{
+    streaming::RecorderData,
+    time::Timestamp,
+    types::{ObjectHandle, Priority},
+};
+use tracing::warn;
+
+/// Tracks the active context (task or ISR) of each core, and the runtime
+/// and migration stats of the contexts
+#[derive(Clone, Debug, Default)]
+pub struct ContextAnalyzer {
+    /// Context stats, per core
+    pub stats: BTreeMap<CoreId, HashMap<ContextHandle, ContextStats>>,
+
+    /// Core migrations of the tasks
+    pub migrations: HashMap<ContextHandle, MigrationStats>,
+
+    /// Context currently running on each core
+    pub active_contexts: BTreeMap<CoreId, ContextHandle>,
+
+    /// Every running instance, only recorded when created with [`ContextAnalyzer::with_intervals`]
+    pub intervals: Vec<ContextInterval>,
+
+    record_intervals: bool,
+    /// When the active context of each core was switched in, recording time
+    active_since: BTreeMap<CoreId, Timestamp>,
+    last_trace_time: Option<Timestamp>,
+}
+
+/// A span of time a context was running on a core
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct ContextInterval {
+    pub core_id: CoreId,
+    pub context: ContextHandle,
+
+    /// Time since the start of the recording, earlier sessions included
+    pub start: Timestamp,
+    pub end: Timestamp,
+}
+
+impl ContextAnalyzer {
+    /// Also keep every running instance, for timelines
+    pub fn with_intervals() -> Self {
+        Self {
+            record_intervals: true,
+            ..Default::default()
+        }
+    }
+
+    /// Close the running instance of each core at the given time, contexts switched in by
+    /// the last event have no known runtime and are left out
+    fn close_intervals(&mut self, end: Timestamp) {
+        let since = std::mem::take(&mut self.active_since);
+        for (core_id, start) in since.into_iter().filter(|(_, start)| *start < end) {
+            if let Some(context) = self.active_contexts.get(&core_id) {
+                self.intervals.push(ContextInterval {
+                    core_id,
+                    context: *context,
+                    start,
+                    end,
+                });
+            }
+        }
+    }
+}
+
+impl Analyzer for ContextAnalyzer {
+    fn on_header(&mut self, rd: &RecorderData) {
+        // Make sure every core gets stats, even if it never switched contexts
+        for core_id in 0..rd.header.num_cores.max(1) {
+            self.stats.entry(core_id as CoreId).or_default();
+        }
+    }
+
+    fn on_event(&mut self, _rd: &RecorderData, record: &EventRecord) {
+        self.last_trace_time = Some(record.trace_time);
+        if record.dropped_events.is_some() {
+            // The switches in the gap are lost, the running instances can't be trusted
+            for (core_id, context) in self.active_contexts.iter() {
+                if let Some(stats) = self.stats.get_mut(core_id).and_then(|s| s.get_mut(context)) {
+                    stats.dropped_event_gaps += 1;
+                }
+            }
+        }
+        if let Some(switch) = ContextSwitch::of(&record.event) {
+            let (contex_switch_handle, prio) = (switch.context, switch.priority);
+            let timestamp = record.timestamp;
+            let core_id = record.core_id;
+            let active_context = self
+                .active_contexts
+                .entry(core_id)
+                .or_insert(ContextHandle::Task(ObjectHandle::NO_TASK));
+            if contex_switch_handle != *active_context {
+                let core_context_stats = self.stats.entry(core_id).or_default();
+
+                // Update runtime stats for the previous context being switched out
+                if let Some(prev_ctx_stats) = core_context_stats.get_mut(active_context) {
+                    prev_ctx_stats.switch_out(timestamp);
+                }
+
+                // Same for the new context being switched in
+                let ctx_stats = core_context_stats
+                    .entry(contex_switch_handle)
+                    .or_insert_with(|| ContextStats::new(timestamp));
+                ctx_stats.switch_in(timestamp, prio);
+
+                if self.record_intervals {
+                    if let Some(start) = self.active_since.insert(core_id, record.trace_time) {
+                        self.intervals.push(ContextInterval {
+                            core_id,
+                            context: *active_context,
+                            start,
+                            end: record.trace_time,
+                        });
+                    }
+                }
+
+                if let ContextHandle::Task(_) = contex_switch_handle {
+                    self.migrations
+                        .entry(contex_switch_handle)
+                        .or_default()
+                        .switch_in(core_id);
+                }
+
+                *active_context = contex_switch_handle;
+            }
+        }
+    }
+
+    fn on_restart(&mut self, _rd: &RecorderData) {
+        if let (true, Some(end)) = (self.record_intervals, self.last_trace_time) {
+            self.close_intervals(end);
+        }
+        self.active_contexts.clear();
+    }
+
+    fn finish(&mut self, _rd: &RecorderData, _summary: &TraceSummary) {
+        if let (true, Some(end)) = (self.record_intervals, self.last_trace_time) {
+            self.close_intervals(end);
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ContextStats {
+    /// Priorities observed
+    pub priorities: HashSet<Priority>,
+
+    /// When the context was last switched in
+    pub last_timestamp: Timestamp,
+
+    /// Total time the context has been in the running state
+    pub total_runtime: DurationTicks,
+
+    /// Duration (in ticks) of each instance the context was in the running state
+    pub running_instances: Vec<f64>,
+
+    /// Number of times the context was switched in
+    pub count: u64,
+
+    /// Number of running instances that overlapped dropped events, their runtime is unreliable
+    pub dropped_event_gaps: u64,
+}
+
+impl ContextStats {
+    pub fn new(last_timestamp: Timestamp) -> Self {
+        Self {
+            priorities: Default::default(),
+            last_timestamp,
+            total_runtime: DurationTicks::zero(),
+            running_instances: Default::default(),
+            count: 0,
+            dropped_event_gaps: 0,
+        }
+    }
+
+    /// Called when this context is switched in
+    pub fn switch_in(&mut self, last_timestamp: Timestamp, prio: Priority) {
+        self.last_timestamp = last_timestamp;
+        self.count += 1;
+        self.priorities.insert(prio);
+    }
+
+    /// Called when this context is switched out
+    pub fn switch_out(&mut self, timestamp: Timestamp) {
+        if timestamp < self.last_timestamp {
+            warn!("Stats timestamp went backwards");
+        } else {
+            let diff = timestamp - self.last_timestamp;
+            self.total_runtime += diff;
+            self.last_timestamp = timestamp;
+            self.running_instances.push(diff.get_raw() as f64);
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MigrationStats {
+    /// Cores the task was observed running on
+    pub cores: BTreeSet<CoreId>,
+
+    /// Core the task was last switched in on
+    pub last_core: Option<CoreId>,
+
+    /// Number of times the task was switched in on a different core than last time
+    pub migrations: u64,
+}
+
+impl MigrationStats {
+    /// Called when the task is switched in on the given core
+    pub fn switch_in(&mut self, core_id: CoreId) {
+        if let Some(last_core) = self.last_core {
+            if last_core != core_id {
+                self.migrations += 1;
+            }
+        }
+        self.last_core = Some(core_id);
+        self.cores.insert(core_id);
+    }
+}
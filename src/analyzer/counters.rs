@@ -0,0 +1,41 @@
+use crate::analyzer::Analyzer;
+use crate::event::TraceEvent;
+use crate::trace::EventRecord;
+use std::collections::BTreeMap;
+use trace_recorder_parser::streaming::{
+    event::{Event, EventType},
+    RecorderData,
+};
+
+/// Counts the observed events by type
+#[derive(Clone, Debug, Default)]
+pub struct EventTypeCounter {
+    pub counts: BTreeMap<EventType, u64>,
+    pub total: u64,
+}
+
+impl Analyzer for EventTypeCounter {
+    fn on_event(&mut self, _rd: &RecorderData, record: &EventRecord) {
+        *self.counts.entry(record.event_type).or_insert(0) += 1;
+        self.total += 1;
+    }
+}
+
+/// Counts the user events by channel
+#[derive(Clone, Debug, Default)]
+pub struct UserEventChannelCounter {
+    pub counts: BTreeMap<String, u64>,
+}
+
+impl Analyzer for UserEventChannelCounter {
+    fn on_event(&mut self, _rd: &RecorderData, record: &EventRecord) {
+        let user_event_channel = match &record.event {
+            TraceEvent::Parsed(Event::User(ev)) => Some(ev.channel.to_string()),
+            TraceEvent::ElfUser(ev) => Some(ev.channel.clone()),
+            _ => None,
+        };
+        if let Some(channel) = user_event_channel {
+            *self.counts.entry(channel).or_insert(0) += 1;
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use crate::analyzer::Analyzer;
+use crate::elf::FirmwareElf;
+use crate::event::{ContextSwitch, CoreContext, CoreId, TraceEvent};
+use crate::report::object_name;
+use crate::trace::EventRecord;
+use std::collections::BTreeMap;
+use trace_recorder_parser::{
+    streaming::{event::Event, RecorderData},
+    time::Timestamp,
+};
+
+/// Accumulates the CPU time of each `core;ISR-nesting;task` stack, in the folded-stack
+/// format used by flamegraph tools.
+///
+/// Time is accounted the same way as [`ContextStats::total_runtime`](crate::analyzer::ContextStats::total_runtime),
+/// from one context switch to the next. The current user-event interval, when an interval
+/// channel is set, and the current state machine states are appended to the stacks.
+#[derive(Clone, Debug, Default)]
+pub struct FoldedStackAnalyzer<'a> {
+    /// CPU time, in ticks, of each folded stack
+    pub stacks: BTreeMap<String, u64>,
+
+    elf: Option<&'a FirmwareElf>,
+    interval_channel: Option<String>,
+    interval: Option<String>,
+    /// Current state of each state machine
+    states: BTreeMap<String, String>,
+    cores: BTreeMap<CoreId, CoreStack>,
+}
+
+#[derive(Clone, Debug)]
+struct CoreStack {
+    context: CoreContext,
+    /// Start of the current segment, recording time
+    since: Timestamp,
+}
+
+impl<'a> FoldedStackAnalyzer<'a> {
+    pub fn new(elf: Option<&'a FirmwareElf>) -> Self {
+        Self {
+            elf,
+            ..Default::default()
+        }
+    }
+
+    /// User events on this channel name the current interval, with their formatted string
+    pub fn set_interval_channel(&mut self, channel: &str) {
+        self.interval_channel = Some(channel.to_owned());
+    }
+
+    /// Attribute the time since the last change on the core to its current stack
+    fn close_segment(&mut self, rd: &RecorderData, core_id: CoreId, now: Timestamp) {
+        let Some(core) = self.cores.get_mut(&core_id) else {
+            return;
+        };
+        let since = std::mem::replace(&mut core.since, now);
+        if now <= since || core.context == CoreContext::default() {
+            return;
+        }
+
+        let mut frames = vec![format!("core {core_id}")];
+        let context = &core.context;
+        frames.extend(context.isrs.iter().map(|h| object_name(rd, self.elf, *h)));
+        frames.extend(context.task.map(|h| object_name(rd, self.elf, h)));
+        frames.extend(self.interval.clone());
+        frames.extend(
+            self.states
+                .iter()
+                .map(|(sm, state)| format!("{sm}:{state}")),
+        );
+        let stack = frames
+            .iter()
+            .map(|f| f.replace(';', "_"))
+            .collect::<Vec<_>>()
+            .join(";");
+        *self.stacks.entry(stack).or_insert(0) += (now - since).get_raw();
+    }
+
+    fn close_all_segments(&mut self, rd: &RecorderData, now: Timestamp) {
+        let core_ids: Vec<CoreId> = self.cores.keys().copied().collect();
+        for core_id in core_ids {
+            self.close_segment(rd, core_id, now);
+        }
+    }
+}
+
+impl Analyzer for FoldedStackAnalyzer<'_> {
+    fn on_event(&mut self, rd: &RecorderData, record: &EventRecord) {
+        let now = record.trace_time;
+        let core_id = record.core_id;
+
+        if let Some(switch) = ContextSwitch::of(&record.event) {
+            let running = self.cores.get(&core_id).map(|c| c.context.running());
+            if running == Some(switch.context) {
+                return;
+            }
+            self.close_segment(rd, core_id, now);
+            let core = self.cores.entry(core_id).or_insert(CoreStack {
+                context: CoreContext::default(),
+                since: now,
+            });
+            core.context.switch(&switch);
+            return;
+        }
+
+        match &record.event {
+            TraceEvent::Parsed(Event::StateMachineStateChange(ev)) => {
+                self.close_all_segments(rd, now);
+                self.states
+                    .insert(ev.name.to_string(), ev.state.to_string());
+            }
+            TraceEvent::Parsed(Event::User(ev))
+                if Some(ev.channel.to_string()) == self.interval_channel =>
+            {
+                self.close_all_segments(rd, now);
+                self.interval = Some(ev.formatted_string.to_string());
+            }
+            TraceEvent::ElfUser(ev) if Some(&ev.channel) == self.interval_channel.as_ref() => {
+                self.close_all_segments(rd, now);
+                self.interval = Some(ev.formatted_string.clone());
+            }
+            _ => (),
+        }
+    }
+
+    fn on_restart(&mut self, _rd: &RecorderData) {
+        // The running contexts are unknown until the next switches, same as ContextAnalyzer
+        self.cores.clear();
+        self.interval = None;
+        self.states.clear();
+    }
+}
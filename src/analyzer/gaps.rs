@@ -0,0 +1,71 @@
+use crate::analyzer::Analyzer;
+use crate::event::{ContextHandle, ContextSwitch, CoreId};
+use crate::trace::EventRecord;
+use std::collections::BTreeMap;
+use trace_recorder_parser::{streaming::RecorderData, time::Timestamp};
+
+/// Keeps every gap in the event counter, where the recorder dropped events
+#[derive(Clone, Debug, Default)]
+pub struct DroppedEventAnalyzer {
+    pub gaps: Vec<DroppedEventGap>,
+
+    /// Trace time and event counter of the previous event
+    last: Option<(Timestamp, u16)>,
+    /// Context running on each core
+    active_contexts: BTreeMap<CoreId, ContextHandle>,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct DroppedEventGap {
+    /// Core of the first event after the gap
+    pub core_id: CoreId,
+
+    /// Time of the last event before the gap, since the start of the recording
+    pub start: Timestamp,
+    /// Time of the first event after the gap
+    pub end: Timestamp,
+
+    /// Event counters of the events around the gap
+    pub count_before: u16,
+    pub count_after: u16,
+
+    /// Number of events lost
+    pub dropped: u64,
+
+    /// Context running on the core when the events were lost, if known
+    pub context: Option<ContextHandle>,
+}
+
+impl DroppedEventAnalyzer {
+    /// Total events lost
+    pub fn total_dropped(&self) -> u64 {
+        self.gaps.iter().map(|g| g.dropped).sum()
+    }
+}
+
+impl Analyzer for DroppedEventAnalyzer {
+    fn on_event(&mut self, _rd: &RecorderData, record: &EventRecord) {
+        if let (Some(dropped), Some((start, count_before))) = (record.dropped_events, self.last) {
+            self.gaps.push(DroppedEventGap {
+                core_id: record.core_id,
+                start,
+                end: record.trace_time,
+                count_before,
+                count_after: record.event_count,
+                dropped,
+                context: self.active_contexts.get(&record.core_id).copied(),
+            });
+        }
+        self.last = Some((record.trace_time, record.event_count));
+
+        if let Some(switch) = ContextSwitch::of(&record.event) {
+            self.active_contexts.insert(record.core_id, switch.context);
+        }
+    }
+
+    fn on_restart(&mut self, _rd: &RecorderData) {
+        // The event counter starts over, it's not a gap
+        self.last = None;
+        self.active_contexts.clear();
+    }
+}
@@ -0,0 +1,46 @@
+use crate::analyzer::Analyzer;
+use crate::event::TraceEvent;
+use crate::trace::EventRecord;
+use std::collections::HashMap;
+use trace_recorder_parser::{
+    streaming::{event::Event, RecorderData},
+    types::ObjectHandle,
+};
+
+/// Tracks the stack low marks reported by UNUSED_STACK events
+#[derive(Clone, Debug, Default)]
+pub struct StackAnalyzer {
+    pub stats: HashMap<ObjectHandle, StackStats>,
+}
+
+impl Analyzer for StackAnalyzer {
+    fn on_event(&mut self, _rd: &RecorderData, record: &EventRecord) {
+        if let TraceEvent::Parsed(Event::UnusedStack(stack_event)) = &record.event {
+            let stats = self
+                .stats
+                .entry(stack_event.handle)
+                .or_insert_with(|| StackStats::new(stack_event.low_mark));
+            stats.update(stack_event.low_mark);
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct StackStats {
+    pub low_mark_min: u32,
+    pub low_mark_max: u32,
+}
+
+impl StackStats {
+    pub fn new(low_mark: u32) -> Self {
+        Self {
+            low_mark_min: low_mark,
+            low_mark_max: low_mark,
+        }
+    }
+
+    pub fn update(&mut self, low_mark: u32) {
+        self.low_mark_min = self.low_mark_min.min(low_mark);
+        self.low_mark_max = self.low_mark_max.max(low_mark);
+    }
+}
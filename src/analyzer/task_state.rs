@@ -0,0 +1,423 @@
+use crate::analyzer::Analyzer;
+use crate::event::{event_object_handle, ContextHandle, ContextSwitch, CoreId, TraceEvent};
+use crate::trace::{EventRecord, TraceSummary};
+use std::{collections::BTreeMap, fmt};
+use trace_recorder_parser::{
+    streaming::{
+        event::{Event, EventType},
+        RecorderData,
+    },
+    time::Timestamp,
+    types::ObjectHandle,
+};
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum TaskState {
+    Running,
+    Ready,
+    Blocked,
+    Suspended,
+}
+
+impl fmt::Display for TaskState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TaskState::Running => "Running",
+            TaskState::Ready => "Ready",
+            TaskState::Blocked => "Blocked",
+            TaskState::Suspended => "Suspended",
+        })
+    }
+}
+
+/// What a task blocked on: the blocking event and its kernel object, none for delays
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct BlockReason {
+    pub event_type: EventType,
+    pub object: Option<ObjectHandle>,
+}
+
+impl BlockReason {
+    /// Kind of kernel object waited on
+    pub fn kind(&self) -> &'static str {
+        use EventType::*;
+        match self.event_type {
+            TaskDelay | TaskDelayUntil => "Delay",
+            TaskNotifyWaitBlock => "Notification",
+            QueueSendBlock | QueueReceiveBlock | QueuePeekBlock | QueueSendFrontBlock => "Queue",
+            MutexGiveBlock | MutexTakeBlock | MutexTakeRecursiveBlock => "Mutex",
+            SemaphoreGiveBlock | SemaphoreTakeBlock | SemaphorePeekBlock => "Semaphore",
+            EventGroupSyncBlock | EventGroupWaitBitsBlock => "Event group",
+            MessageBufferSendBlock | MessageBufferReceiveBlock => "Message buffer",
+            _ => "Other",
+        }
+    }
+}
+
+/// Infers the scheduling state of each task from the kernel events.
+///
+/// A task is running from its switch-in, ready once created, made ready or preempted,
+/// blocked when it gets switched out after a blocking call or a delay, and suspended
+/// by vTaskSuspend until resumed.
+#[derive(Clone, Debug, Default)]
+pub struct TaskStateTracker {
+    states: BTreeMap<ObjectHandle, TaskState>,
+    running: BTreeMap<CoreId, ObjectHandle>,
+    /// State of the running task once it gets switched out
+    pending: BTreeMap<CoreId, (TaskState, Option<BlockReason>)>,
+    /// What each blocked task is waiting on
+    block_reasons: BTreeMap<ObjectHandle, BlockReason>,
+}
+
+impl TaskStateTracker {
+    pub fn state(&self, task: ObjectHandle) -> Option<TaskState> {
+        self.states.get(&task).copied()
+    }
+
+    pub fn states(&self) -> &BTreeMap<ObjectHandle, TaskState> {
+        &self.states
+    }
+
+    /// Task running on the core
+    pub fn running(&self, core_id: CoreId) -> Option<ObjectHandle> {
+        self.running.get(&core_id).copied()
+    }
+
+    /// What the task is waiting on, while it's blocked
+    pub fn block_reason(&self, task: ObjectHandle) -> Option<BlockReason> {
+        if self.state(task) == Some(TaskState::Blocked) {
+            self.block_reasons.get(&task).copied()
+        } else {
+            None
+        }
+    }
+
+    /// Forget everything, the states are unknown after a trace restart
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Update the states with the event, returns the tasks that changed state
+    pub fn update(&mut self, record: &EventRecord) -> Vec<(ObjectHandle, TaskState)> {
+        let mut changes = Vec::new();
+        let core_id = record.core_id;
+        if let Some(switch) = ContextSwitch::of(&record.event) {
+            if let ContextHandle::Task(task) = switch.context {
+                self.switch_in(core_id, task, &mut changes);
+            }
+            return changes;
+        }
+        let ev = match &record.event {
+            TraceEvent::Parsed(ev) => ev,
+            _ => return changes,
+        };
+
+        match ev {
+            Event::TaskCreate(ev) => {
+                if !self.states.contains_key(&ev.handle) {
+                    self.set(ev.handle, TaskState::Ready, &mut changes);
+                }
+            }
+            Event::TaskReady(ev) => {
+                if self.state(ev.handle) != Some(TaskState::Running) {
+                    self.set(ev.handle, TaskState::Ready, &mut changes);
+                }
+            }
+            Event::Unknown(base) => {
+                let param = base.parameters().first().copied();
+                let delay = Some(BlockReason {
+                    event_type: record.event_type,
+                    object: None,
+                });
+                match record.event_type {
+                    // Delaying for zero ticks only yields
+                    EventType::TaskDelay if param != Some(0) => {
+                        self.pending.insert(core_id, (TaskState::Blocked, delay));
+                    }
+                    EventType::TaskDelayUntil => {
+                        self.pending.insert(core_id, (TaskState::Blocked, delay));
+                    }
+                    EventType::TaskSuspend => {
+                        // The handle is the suspended task, the calling task when 0
+                        let running = self.running.get(&core_id).copied();
+                        match param.and_then(|p| self.task(p)).or(running) {
+                            Some(task) if Some(task) == running => {
+                                self.pending.insert(core_id, (TaskState::Suspended, None));
+                            }
+                            Some(task) => self.set(task, TaskState::Suspended, &mut changes),
+                            None => (),
+                        }
+                    }
+                    EventType::TaskResume | EventType::TaskResumeFromIsr => {
+                        if let Some(task) = param.and_then(|p| self.task(p)) {
+                            if self.state(task) == Some(TaskState::Suspended) {
+                                self.set(task, TaskState::Ready, &mut changes);
+                            }
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            _ => {
+                if is_blocking(record.event_type) {
+                    let reason = BlockReason {
+                        event_type: record.event_type,
+                        object: event_object_handle(ev),
+                    };
+                    self.pending
+                        .insert(core_id, (TaskState::Blocked, Some(reason)));
+                }
+            }
+        }
+        changes
+    }
+
+    /// The task is switched in, the previous one leaves the running state
+    fn switch_in(
+        &mut self,
+        core_id: CoreId,
+        task: ObjectHandle,
+        changes: &mut Vec<(ObjectHandle, TaskState)>,
+    ) {
+        let pending = self.pending.remove(&core_id);
+        if let Some(prev) = self.running.insert(core_id, task) {
+            if prev != task && self.state(prev) == Some(TaskState::Running) {
+                let (state, reason) = pending.unwrap_or((TaskState::Ready, None));
+                if let Some(reason) = reason {
+                    self.block_reasons.insert(prev, reason);
+                }
+                self.set(prev, state, changes);
+            }
+        }
+        if task != ObjectHandle::NO_TASK {
+            self.set(task, TaskState::Running, changes);
+        }
+    }
+
+    fn set(
+        &mut self,
+        task: ObjectHandle,
+        state: TaskState,
+        changes: &mut Vec<(ObjectHandle, TaskState)>,
+    ) {
+        if self.states.insert(task, state) != Some(state) {
+            changes.push((task, state));
+        }
+    }
+
+    /// Known task with this raw handle
+    fn task(&self, raw: u32) -> Option<ObjectHandle> {
+        self.states.keys().copied().find(|h| u32::from(*h) == raw)
+    }
+}
+
+/// Tracks the state of each task, the time spent in each state and what it waited on
+#[derive(Clone, Debug, Default)]
+pub struct TaskStateAnalyzer {
+    pub stats: BTreeMap<ObjectHandle, TaskStateStats>,
+
+    tracker: TaskStateTracker,
+    /// Current state of each task and when it was entered, recording time
+    since: BTreeMap<ObjectHandle, (TaskState, Timestamp)>,
+    /// What each blocked task is waiting on
+    waiting: BTreeMap<ObjectHandle, BlockReason>,
+    /// Tasks woken up from a wait on a kernel object, until the outcome of the call is known
+    woken: BTreeMap<ObjectHandle, BlockReason>,
+    last_trace_time: Option<Timestamp>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TaskStateStats {
+    /// Time spent in each state, from the first known state of the task
+    pub time: BTreeMap<TaskState, Timestamp>,
+    /// Number of state changes
+    pub transitions: u64,
+    /// Waits of the task, per reason
+    pub waits: BTreeMap<BlockReason, WaitStats>,
+}
+
+#[derive(Clone, Debug)]
+pub struct WaitStats {
+    /// Number of times the task blocked
+    pub count: u64,
+    /// Time spent blocked
+    pub total: Timestamp,
+    /// Longest wait
+    pub max: Timestamp,
+    /// Waits on a kernel object that ended with the call failing
+    pub timeouts: u64,
+}
+
+impl Default for WaitStats {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            total: Timestamp::zero(),
+            max: Timestamp::zero(),
+            timeouts: 0,
+        }
+    }
+}
+
+impl WaitStats {
+    fn add_time(&mut self, time: Timestamp) {
+        self.total += time;
+        self.max = self.max.max(time);
+    }
+}
+
+impl TaskStateStats {
+    pub fn time_in(&self, state: TaskState) -> Timestamp {
+        self.time
+            .get(&state)
+            .copied()
+            .unwrap_or_else(Timestamp::zero)
+    }
+
+    /// Time in any known state
+    pub fn total_time(&self) -> Timestamp {
+        self.time.values().copied().sum()
+    }
+
+    /// What the task blocked on most often, and how many times
+    pub fn most_frequent_block_reason(&self) -> Option<(BlockReason, u64)> {
+        self.waits
+            .iter()
+            .max_by_key(|(reason, w)| (w.count, std::cmp::Reverse(**reason)))
+            .map(|(reason, w)| (*reason, w.count))
+    }
+}
+
+impl TaskStateAnalyzer {
+    /// Account the time of the current states up to the given time
+    fn close_states(&mut self, now: Timestamp) {
+        for (task, (state, since)) in self.since.iter_mut() {
+            if now > *since {
+                let stats = self.stats.entry(*task).or_default();
+                *stats.time.entry(*state).or_insert_with(Timestamp::zero) += now - *since;
+                if let Some(reason) = self.waiting.get(task) {
+                    stats
+                        .waits
+                        .entry(*reason)
+                        .or_default()
+                        .add_time(now - *since);
+                }
+            }
+            *since = now;
+        }
+    }
+
+    /// A woken task's next call on the object it waited on tells if the wait timed out
+    fn check_outcome(&mut self, record: &EventRecord) {
+        let Some(task) = self.tracker.running(record.core_id) else {
+            return;
+        };
+        let Some(reason) = self.woken.get(&task).copied() else {
+            return;
+        };
+        let TraceEvent::Parsed(ev) = &record.event else {
+            return;
+        };
+        let failed = match ev {
+            Event::Unknown(base) if is_failed(record.event_type) => {
+                let object = base.parameters().first().copied();
+                reason.event_type == EventType::TaskNotifyWaitBlock
+                    || object == reason.object.map(u32::from)
+            }
+            _ if event_object_handle(ev) == reason.object => false,
+            _ => return,
+        };
+        self.woken.remove(&task);
+        if failed {
+            let stats = self.stats.entry(task).or_default();
+            stats.waits.entry(reason).or_default().timeouts += 1;
+        }
+    }
+}
+
+impl Analyzer for TaskStateAnalyzer {
+    fn on_event(&mut self, _rd: &RecorderData, record: &EventRecord) {
+        let now = record.trace_time;
+        self.last_trace_time = Some(now);
+        for (task, state) in self.tracker.update(record) {
+            let stats = self.stats.entry(task).or_default();
+            if let Some((prev, since)) = self.since.insert(task, (state, now)) {
+                *stats.time.entry(prev).or_insert_with(Timestamp::zero) += now - since;
+                stats.transitions += 1;
+                if let Some(reason) = self.waiting.remove(&task) {
+                    stats.waits.entry(reason).or_default().add_time(now - since);
+                    if reason.object.is_some() {
+                        self.woken.insert(task, reason);
+                    }
+                }
+            }
+            if let Some(reason) = self.tracker.block_reason(task) {
+                stats.waits.entry(reason).or_default().count += 1;
+                self.waiting.insert(task, reason);
+                self.woken.remove(&task);
+            }
+        }
+        self.check_outcome(record);
+    }
+
+    fn on_restart(&mut self, _rd: &RecorderData) {
+        // The states are unknown until the next events of the new session
+        if let Some(now) = self.last_trace_time {
+            self.close_states(now);
+        }
+        self.since.clear();
+        self.waiting.clear();
+        self.woken.clear();
+        self.tracker.reset();
+    }
+
+    fn finish(&mut self, _rd: &RecorderData, _summary: &TraceSummary) {
+        if let Some(now) = self.last_trace_time {
+            self.close_states(now);
+        }
+    }
+}
+
+/// Events recorded when a kernel object call fails, after a timeout if it blocked
+pub fn is_failed(event_type: EventType) -> bool {
+    use EventType::*;
+    matches!(
+        event_type,
+        TaskNotifyWaitFailed
+            | QueueSendFailed
+            | QueueReceiveFailed
+            | QueuePeekFailed
+            | MutexGiveFailed
+            | MutexTakeFailed
+            | SemaphoreGiveFailed
+            | SemaphoreTakeFailed
+            | SemaphorePeekFailed
+            | EventGroupSyncFailed
+            | EventGroupWaitBitsFailed
+            | MessageBufferSendFailed
+            | MessageBufferReceiveFailed
+    )
+}
+
+/// Events recorded when the calling task blocks on a kernel object
+pub fn is_blocking(event_type: EventType) -> bool {
+    use EventType::*;
+    matches!(
+        event_type,
+        TaskNotifyWaitBlock
+            | QueueSendBlock
+            | QueueReceiveBlock
+            | QueuePeekBlock
+            | QueueSendFrontBlock
+            | MutexGiveBlock
+            | MutexTakeBlock
+            | MutexTakeRecursiveBlock
+            | SemaphoreGiveBlock
+            | SemaphoreTakeBlock
+            | SemaphorePeekBlock
+            | EventGroupSyncBlock
+            | EventGroupWaitBitsBlock
+            | MessageBufferSendBlock
+            | MessageBufferReceiveBlock
+    )
+}
@@ -0,0 +1,546 @@
+use crate::report::{ContextReport, TraceReport};
+use crate::schema::EventSchema;
+use crate::time::parse_duration;
+use serde::Deserialize;
+use std::{fmt, fs, path::Path, time::Duration};
+use trace_recorder_parser::streaming::event::{EventId, EventType};
+
+/// Declarative assertions over the statistics of a recording.
+///
+/// ```toml
+/// [[rule]]
+/// description = "IDLE has headroom"
+/// context = "IDLE"
+/// metric = "cpu_percent"
+/// gt = 40
+///
+/// [[rule]]
+/// metric = "dropped_events"
+/// eq = 0
+///
+/// [[rule]]
+/// context = "Comms"
+/// metric = "max_runtime"
+/// lt = "2ms"
+///
+/// [[rule]]
+/// event = "QUEUE_SEND_FAILED"
+/// metric = "event_count"
+/// eq = 0
+///
+/// # Every task that reported its stack usage
+/// [[rule]]
+/// context = "*"
+/// metric = "stack_low_mark"
+/// gt = 64
+/// ```
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rules {
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Shown in the report instead of the generated description
+    #[serde(default)]
+    pub description: Option<String>,
+
+    /// Context symbol the metric applies to, `*` for all contexts
+    #[serde(default)]
+    pub context: Option<String>,
+
+    /// Event type name the metric applies to
+    #[serde(default)]
+    pub event: Option<String>,
+
+    pub metric: Metric,
+
+    #[serde(default)]
+    pub lt: Option<Value>,
+    #[serde(default)]
+    pub le: Option<Value>,
+    #[serde(default)]
+    pub gt: Option<Value>,
+    #[serde(default)]
+    pub ge: Option<Value>,
+    #[serde(default)]
+    pub eq: Option<Value>,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    // Per context
+    CpuPercent,
+    SwitchCount,
+    MinRuntime,
+    P50Runtime,
+    P90Runtime,
+    P99Runtime,
+    MaxRuntime,
+    /// Lowest UNUSED_STACK low mark, in the unit reported by the recorder (words on FreeRTOS)
+    StackLowMark,
+
+    // Per event type
+    EventCount,
+    /// Events per second
+    EventRate,
+
+    // Whole recording
+    TotalEvents,
+    DroppedEvents,
+    Restarts,
+}
+
+impl Metric {
+    fn scope(self) -> Scope {
+        use Metric::*;
+        match self {
+            CpuPercent | SwitchCount | MinRuntime | P50Runtime | P90Runtime | P99Runtime
+            | MaxRuntime | StackLowMark => Scope::Context,
+            EventCount | EventRate => Scope::Event,
+            TotalEvents | DroppedEvents | Restarts => Scope::Trace,
+        }
+    }
+
+    fn is_duration(self) -> bool {
+        use Metric::*;
+        matches!(
+            self,
+            MinRuntime | P50Runtime | P90Runtime | P99Runtime | MaxRuntime
+        )
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Metric::*;
+        f.write_str(match self {
+            CpuPercent => "CPU %",
+            SwitchCount => "switch count",
+            MinRuntime => "min runtime",
+            P50Runtime => "P50 runtime",
+            P90Runtime => "P90 runtime",
+            P99Runtime => "P99 runtime",
+            MaxRuntime => "max runtime",
+            StackLowMark => "stack low mark",
+            EventCount => "count",
+            EventRate => "rate (/s)",
+            TotalEvents => "total events",
+            DroppedEvents => "dropped events",
+            Restarts => "trace restarts",
+        })
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Scope {
+    Context,
+    Event,
+    Trace,
+}
+
+/// Rule operand, a number or a duration like `2ms`, `500us` or `1.5s`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Number(f64),
+    Text(String),
+}
+
+impl Value {
+    /// Numeric value, durations are converted to nanoseconds
+    fn resolve(&self, metric: Metric) -> Result<f64, String> {
+        match self {
+            Value::Number(n) if metric.is_duration() => Err(format!(
+                "{metric} needs a duration with a unit, like \"{n}ms\""
+            )),
+            Value::Number(n) => Ok(*n),
+            Value::Text(s) if metric.is_duration() => {
+                parse_duration(s).map(|d| d.as_nanos() as f64)
+            }
+            Value::Text(s) => s
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| format!("invalid number '{s}'")),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(n) => n.fmt(f),
+            Value::Text(s) => s.fmt(f),
+        }
+    }
+}
+
+/// Outcome of evaluating a rule, once per matching context for wildcard rules
+#[derive(Clone, Debug)]
+pub struct CheckResult {
+    pub description: String,
+    pub passed: bool,
+
+    /// Observed value, or why the rule couldn't be evaluated
+    pub actual: String,
+}
+
+impl Rules {
+    /// Load a rules file, event names are checked against the parser's event types and the schema
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        schema: &EventSchema,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(path)?;
+        Self::parse(&content, schema)
+    }
+
+    fn parse(content: &str, schema: &EventSchema) -> Result<Self, Box<dyn std::error::Error>> {
+        let rules: Rules = toml::from_str(content)?;
+        for (idx, rule) in rules.rules.iter().enumerate() {
+            rule.validate(schema)
+                .map_err(|e| format!("Rule {}: {e}", idx + 1))?;
+        }
+        Ok(rules)
+    }
+
+    pub fn evaluate(&self, report: &TraceReport) -> Vec<CheckResult> {
+        self.rules
+            .iter()
+            .flat_map(|rule| rule.evaluate(report))
+            .collect()
+    }
+}
+
+impl Rule {
+    fn comparisons(&self) -> impl Iterator<Item = (&'static str, &Value)> {
+        [
+            ("<", &self.lt),
+            ("<=", &self.le),
+            (">", &self.gt),
+            (">=", &self.ge),
+            ("==", &self.eq),
+        ]
+        .into_iter()
+        .filter_map(|(op, v)| v.as_ref().map(|v| (op, v)))
+    }
+
+    fn validate(&self, schema: &EventSchema) -> Result<(), String> {
+        if self.comparisons().next().is_none() {
+            return Err("needs at least one of lt, le, gt, ge or eq".to_owned());
+        }
+        for (_, v) in self.comparisons() {
+            v.resolve(self.metric)?;
+        }
+        match self.metric.scope() {
+            Scope::Context if self.context.is_none() => {
+                Err(format!("{} needs a context", self.metric))
+            }
+            Scope::Event => match self.event.as_deref() {
+                None => Err(format!("{} needs an event type", self.metric)),
+                Some(event) if !is_event_name(event, schema) => {
+                    Err(format!("unknown event type '{event}'"))
+                }
+                Some(_) => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn describe(&self, context: Option<&str>) -> String {
+        if let Some(d) = &self.description {
+            return match context {
+                Some(ctx) if self.context.as_deref() == Some("*") => format!("{d} ({ctx})"),
+                _ => d.clone(),
+            };
+        }
+        let subject = match self.metric.scope() {
+            Scope::Context => format!("{} {}", context.unwrap_or_default(), self.metric),
+            Scope::Event => format!(
+                "{} {}",
+                self.event.as_deref().unwrap_or_default(),
+                self.metric
+            ),
+            Scope::Trace => self.metric.to_string(),
+        };
+        let conditions: Vec<String> = self
+            .comparisons()
+            .map(|(op, v)| format!("{op} {v}"))
+            .collect();
+        format!("{subject} {}", conditions.join(" and "))
+    }
+
+    fn evaluate(&self, report: &TraceReport) -> Vec<CheckResult> {
+        match self.metric.scope() {
+            Scope::Context => {
+                let pattern = self.context.as_deref().unwrap_or_default();
+                if pattern == "*" {
+                    let results: Vec<CheckResult> = report
+                        .contexts
+                        .iter()
+                        .filter_map(|(sym, ctx)| {
+                            // Stack rules only apply to contexts that reported their stack usage
+                            let value = self.context_value(report, ctx)?;
+                            Some(self.check(Some(sym), value))
+                        })
+                        .collect();
+                    if !results.is_empty() {
+                        results
+                    } else if report.contexts.is_empty() {
+                        vec![self.missing(Some(pattern), "no contexts")]
+                    } else {
+                        vec![self.missing(Some(pattern), self.unavailable())]
+                    }
+                } else {
+                    match report.contexts.get(pattern) {
+                        Some(ctx) => match self.context_value(report, ctx) {
+                            Some(value) => vec![self.check(Some(pattern), value)],
+                            None => vec![self.missing(Some(pattern), self.unavailable())],
+                        },
+                        None => vec![self.missing(Some(pattern), "context not found")],
+                    }
+                }
+            }
+            Scope::Event => {
+                let event = self.event.as_deref().unwrap_or_default();
+                let value = match self.metric {
+                    Metric::EventRate => report.event_rate(event),
+                    _ => report.event_types.get(event).copied().unwrap_or(0) as f64,
+                };
+                vec![self.check(None, value)]
+            }
+            Scope::Trace => {
+                let value = match self.metric {
+                    Metric::TotalEvents => report.total_events,
+                    Metric::DroppedEvents => report.dropped_events,
+                    _ => report.restarts,
+                };
+                vec![self.check(None, value as f64)]
+            }
+        }
+    }
+
+    /// Value of the metric, durations in nanoseconds
+    fn context_value(&self, report: &TraceReport, ctx: &ContextReport) -> Option<f64> {
+        let ns = |ticks: f64| report.duration(ticks).map(|d| d.as_nanos() as f64);
+        match self.metric {
+            Metric::CpuPercent => Some(ctx.cpu_percent),
+            Metric::SwitchCount => Some(ctx.count as f64),
+            Metric::MinRuntime => ns(ctx.runtime.min),
+            Metric::P50Runtime => ns(ctx.runtime.p50),
+            Metric::P90Runtime => ns(ctx.runtime.p90),
+            Metric::P99Runtime => ns(ctx.runtime.p99),
+            Metric::MaxRuntime => ns(ctx.runtime.max),
+            Metric::StackLowMark => ctx.stack_low_mark.map(f64::from),
+            _ => None,
+        }
+    }
+
+    /// Why a context has no value for the metric
+    fn unavailable(&self) -> &'static str {
+        if self.metric.is_duration() {
+            "timer frequency unknown"
+        } else {
+            "no stack data"
+        }
+    }
+
+    fn check(&self, context: Option<&str>, actual: f64) -> CheckResult {
+        let passed = self.comparisons().all(|(op, v)| {
+            // Validated when the rules were loaded
+            let expected = v.resolve(self.metric).unwrap_or(f64::NAN);
+            match op {
+                "<" => actual < expected,
+                "<=" => actual <= expected,
+                ">" => actual > expected,
+                ">=" => actual >= expected,
+                _ => actual == expected,
+            }
+        });
+        let actual = if self.metric.is_duration() {
+            format!("{:?}", Duration::from_nanos(actual as u64))
+        } else if actual.fract() == 0.0 {
+            format!("{actual:.0}")
+        } else {
+            format!("{actual:.02}")
+        };
+        CheckResult {
+            description: self.describe(context),
+            passed,
+            actual,
+        }
+    }
+
+    fn missing(&self, context: Option<&str>, why: &str) -> CheckResult {
+        CheckResult {
+            description: self.describe(context),
+            passed: false,
+            actual: why.to_owned(),
+        }
+    }
+}
+
+/// Whether the name is an event type the report can count
+fn is_event_name(name: &str, schema: &EventSchema) -> bool {
+    schema.has_name(name)
+        || (0..=0x0F_FF).any(|id| EventType::from(EventId(id)).to_string() == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> Result<Rules, String> {
+        Rules::parse(content, &EventSchema::default()).map_err(|e| e.to_string())
+    }
+
+    fn report() -> TraceReport {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/freertos_le.psf"
+        );
+        TraceReport::load(path, None, None, None).unwrap()
+    }
+
+    fn evaluate(content: &str, report: &TraceReport) -> Vec<(String, bool, String)> {
+        parse(content)
+            .unwrap()
+            .evaluate(report)
+            .into_iter()
+            .map(|r| (r.description, r.passed, r.actual))
+            .collect()
+    }
+
+    #[test]
+    fn rule_validation() {
+        let rules = parse(
+            r#"
+            [[rule]]
+            context = "Control"
+            metric = "max_runtime"
+            lt = "2ms"
+
+            [[rule]]
+            event = "TASK_DELAY"
+            metric = "event_count"
+            ge = 1
+            "#,
+        )
+        .unwrap();
+        assert_eq!(rules.rules.len(), 2);
+
+        let err = |content: &str| parse(content).unwrap_err();
+        assert!(err("[[rule]]\nmetric = \"restarts\"").contains("needs at least one of"));
+        assert!(
+            err("[[rule]]\ncontext = \"IDLE\"\nmetric = \"max_runtime\"\nlt = 2")
+                .contains("needs a duration")
+        );
+        assert!(err("[[rule]]\nmetric = \"cpu_percent\"\ngt = 1").contains("needs a context"));
+        assert!(err("[[rule]]\nmetric = \"event_count\"\neq = 0").contains("needs an event"));
+        assert!(
+            err("[[rule]]\nevent = \"QUEUE_SEND_FAIL\"\nmetric = \"event_count\"\neq = 0")
+                .contains("Rule 1: unknown event type 'QUEUE_SEND_FAIL'")
+        );
+        assert!(err("[[rule]]\nmetric = \"restarts\"\neq = 0\nne = 1").contains("unknown field"));
+    }
+
+    #[test]
+    fn context_rules() {
+        let report = report();
+        let results = evaluate(
+            r#"
+            [[rule]]
+            context = "*"
+            metric = "stack_low_mark"
+            gt = 64
+
+            [[rule]]
+            context = "Control"
+            metric = "switch_count"
+            lt = 1
+
+            [[rule]]
+            context = "Missing"
+            metric = "cpu_percent"
+            gt = 0
+            "#,
+            &report,
+        );
+        assert_eq!(
+            results,
+            vec![
+                (
+                    "Control stack low mark > 64".to_owned(),
+                    true,
+                    "120".to_owned()
+                ),
+                ("Control switch count < 1".to_owned(), false, "7".to_owned()),
+                (
+                    "Missing CPU % > 0".to_owned(),
+                    false,
+                    "context not found".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn wildcard_without_values_fails() {
+        let rule = "[[rule]]\ncontext = \"*\"\nmetric = \"stack_low_mark\"\ngt = 64";
+        let mut report = report();
+        for ctx in report.contexts.values_mut() {
+            ctx.stack_low_mark = None;
+        }
+        assert_eq!(
+            evaluate(rule, &report),
+            vec![(
+                "* stack low mark > 64".to_owned(),
+                false,
+                "no stack data".to_owned()
+            )]
+        );
+
+        report.contexts.clear();
+        assert_eq!(
+            evaluate(rule, &report),
+            vec![(
+                "* stack low mark > 64".to_owned(),
+                false,
+                "no contexts".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn event_and_trace_rules() {
+        let report = report();
+        let results = evaluate(
+            r#"
+            [[rule]]
+            event = "TASK_DELAY"
+            metric = "event_count"
+            ge = 1
+
+            [[rule]]
+            event = "QUEUE_SEND_FAILED"
+            metric = "event_count"
+            eq = 0
+
+            [[rule]]
+            description = "Nothing lost"
+            metric = "dropped_events"
+            eq = 0
+            "#,
+            &report,
+        );
+        assert_eq!(
+            results.iter().map(|r| r.1).collect::<Vec<_>>(),
+            vec![true, true, true]
+        );
+        assert_eq!(results[2].0, "Nothing lost");
+    }
+}
@@ -0,0 +1,133 @@
+use clap::Args;
+use std::{path::PathBuf, str::FromStr, time::Duration};
+use trace_recorder_parser::streaming::RecorderData;
+use trace_recorder_printer::{
+    elf::FirmwareElf,
+    schema::EventSchema,
+    time::{parse_duration, TrcTimeExt},
+};
+
+pub mod anonymize;
+#[cfg(feature = "parquet")]
+pub mod columnar;
+pub mod compare;
+pub mod ctf;
+pub mod generate;
+pub mod html;
+pub mod merge;
+pub mod split;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod validate;
+pub mod vcd;
+
+/// Options controlling how events get decoded, shared by the subcommands
+#[derive(Args, Debug, Clone)]
+pub struct DecodeOpts {
+    /// Custom printf event ID
+    #[clap(long, value_parser=clap_num::maybe_hex::<u16>)]
+    pub custom_printf_event_id: Option<u16>,
+
+    /// Firmware ELF file used to resolve unnamed object handles, addresses
+    /// and user event format strings
+    #[clap(long, value_name = "firmware.elf")]
+    pub elf: Option<PathBuf>,
+
+    /// Custom event schema (TOML or YAML) declaring the ID, argument layout
+    /// and display template of application specific events
+    #[clap(long, value_name = "schema.toml")]
+    pub event_schema: Option<PathBuf>,
+
+    /// Skip corrupted data up to the next plausible event instead of decoding it,
+    /// the skipped byte ranges are reported with their file offsets
+    #[clap(long)]
+    pub resync: bool,
+}
+
+impl DecodeOpts {
+    pub fn load_elf(&self) -> Result<Option<FirmwareElf>, Box<dyn std::error::Error>> {
+        self.elf.as_ref().map(FirmwareElf::load).transpose()
+    }
+
+    pub fn load_schema(&self) -> Result<EventSchema, Box<dyn std::error::Error>> {
+        Ok(self
+            .event_schema
+            .as_ref()
+            .map(EventSchema::load)
+            .transpose()?
+            .unwrap_or_default())
+    }
+}
+
+/// A point in the recording, in ticks or as a time since its start
+#[derive(Copy, Clone, Debug)]
+pub enum TimePoint {
+    Ticks(u64),
+    Time(Duration),
+}
+
+impl FromStr for TimePoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.parse::<u64>() {
+            Ok(ticks) => Ok(TimePoint::Ticks(ticks)),
+            Err(_) => parse_duration(s).map(TimePoint::Time),
+        }
+    }
+}
+
+impl TimePoint {
+    /// Ticks since the start of the recording
+    pub fn ticks(self, rd: &RecorderData) -> Result<u64, String> {
+        match self {
+            TimePoint::Ticks(t) => Ok(t),
+            TimePoint::Time(d) => rd.convert_duration(d).ok_or_else(|| {
+                format!("Can't convert {d:?} to ticks, the timer frequency is unknown")
+            }),
+        }
+    }
+}
+
+/// A `start..end` time range, either end can be left open, e.g. `2ms..5ms`, `..1s` or `1000..`
+#[derive(Copy, Clone, Debug)]
+pub struct TimeRange {
+    pub start: Option<TimePoint>,
+    pub end: Option<TimePoint>,
+}
+
+impl FromStr for TimeRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once("..")
+            .ok_or_else(|| format!("invalid time range '{s}', expected start..end"))?;
+        let point = |p: &str| {
+            if p.trim().is_empty() {
+                Ok(None)
+            } else {
+                p.parse().map(Some)
+            }
+        };
+        Ok(Self {
+            start: point(start)?,
+            end: point(end)?,
+        })
+    }
+}
+
+impl TimeRange {
+    /// Start and end ticks, open ends are bounded by `[0, total]`
+    pub fn ticks(&self, rd: &RecorderData, total: u64) -> Result<(u64, u64), String> {
+        let start = self.start.map(|p| p.ticks(rd)).transpose()?.unwrap_or(0);
+        let end = self.end.map(|p| p.ticks(rd)).transpose()?.unwrap_or(total);
+        if end <= start {
+            return Err("The time range is empty".to_owned());
+        }
+        Ok((start, end))
+    }
+}
@@ -0,0 +1,467 @@
+use crate::cli::DecodeOpts;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    path::PathBuf,
+};
+use trace_recorder_parser::{
+    streaming::{Error, RecorderData},
+    types::{ObjectClass, ObjectHandle},
+};
+use trace_recorder_printer::{
+    analyzer::Analyzer,
+    elf::FirmwareElf,
+    event::{event_object_handle, TraceEvent},
+    psf::{EventLayout, RawEvent, SessionHeader, SYSTEM_HEAP_SYMBOL},
+    schema::EventSchema,
+    trace::{EventRecord, TraceReader, TraceSummary},
+};
+
+/// Replace the object names, user event strings and optionally the addresses of a capture
+/// with pseudonyms. Timing and structure are kept, the pseudonyms go to a mapping file.
+#[derive(Args, Debug, Clone)]
+pub struct AnonymizeOpts {
+    #[clap(flatten)]
+    pub decode: DecodeOpts,
+
+    /// Also replace the object handles and heap block addresses
+    #[clap(long)]
+    pub addresses: bool,
+
+    /// Mapping of the original names and addresses to their pseudonyms (TOML), keep it local.
+    /// An existing mapping is reused and extended, captures of the same firmware get the
+    /// same pseudonyms.
+    #[clap(long, value_name = "mapping.toml")]
+    pub mapping: PathBuf,
+
+    /// Output streaming data file (psf)
+    #[clap(long, value_name = "out.psf")]
+    pub output: PathBuf,
+
+    /// Path to streaming data file (psf)
+    #[clap(value_parser)]
+    pub path: PathBuf,
+}
+
+/// Kernel object names, kept as they don't identify the product
+const KEPT_NAMES: &[&str] = &["IDLE", "Tmr Svc", SYSTEM_HEAP_SYMBOL];
+
+/// Pseudonym prefix of a channel and of a registered format string
+const CHANNEL: &str = "Channel";
+const FORMAT: &str = "Format";
+
+/// First address pseudonym, the next ones follow in steps of `ADDRESS_STEP`
+const FIRST_ADDRESS: u32 = 0x1000_0000;
+const ADDRESS_STEP: u32 = 0x10;
+
+/// Original names, strings and addresses to their pseudonyms
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Mapping {
+    #[serde(default)]
+    names: BTreeMap<String, String>,
+    #[serde(default)]
+    strings: BTreeMap<String, String>,
+    #[serde(default)]
+    addresses: BTreeMap<String, String>,
+}
+
+struct Pseudonyms {
+    mapping: Mapping,
+    /// Last number used by each prefix
+    numbers: HashMap<String, u32>,
+    next_address: u32,
+    /// Longest symbol of the entry table, the pseudonyms of strings stay within it
+    max_len: usize,
+}
+
+impl Pseudonyms {
+    fn load(path: &PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
+        let mapping: Mapping = if path.exists() {
+            toml::from_str(&fs::read_to_string(path)?)?
+        } else {
+            Mapping::default()
+        };
+
+        let mut numbers = HashMap::new();
+        for p in mapping.names.values().chain(mapping.strings.values()) {
+            let prefix: String = p.chars().take_while(|c| c.is_ascii_alphabetic()).collect();
+            let digits: String = p[prefix.len()..]
+                .chars()
+                .take_while(|c| c.is_ascii_digit())
+                .collect();
+            if let Ok(n) = digits.parse::<u32>() {
+                let last = numbers.entry(prefix).or_insert(0);
+                *last = n.max(*last);
+            }
+        }
+        let next_address = mapping
+            .addresses
+            .values()
+            .filter_map(|a| parse_address(a))
+            .max()
+            .map(|a| a + ADDRESS_STEP)
+            .unwrap_or(FIRST_ADDRESS);
+
+        Ok(Self {
+            mapping,
+            numbers,
+            next_address,
+            max_len: usize::MAX,
+        })
+    }
+
+    fn save(&self, path: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+        let mut content = String::from(
+            "# Pseudonyms of an anonymized capture, this file reveals the original names\n\n",
+        );
+        content.push_str(&toml::to_string_pretty(&self.mapping)?);
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    fn number(&mut self, prefix: &str) -> u32 {
+        let n = self.numbers.entry(prefix.to_owned()).or_insert(0);
+        *n += 1;
+        *n
+    }
+
+    /// Pseudonym of an object name, `kind` is the prefix of new pseudonyms
+    fn name(&mut self, name: &str, kind: &str) -> String {
+        if name.is_empty() || KEPT_NAMES.contains(&name) {
+            return name.to_owned();
+        }
+        if kind == FORMAT || name.contains('%') {
+            return self.format(name);
+        }
+        if let Some(p) = self.mapping.names.get(name) {
+            return p.clone();
+        }
+        let p = format!("{kind}{}", self.number(kind));
+        self.mapping.names.insert(name.to_owned(), p.clone());
+        p
+    }
+
+    /// Pseudonym of a format string, keeping its conversion specifications so the arguments
+    /// still format
+    fn format(&mut self, format: &str) -> String {
+        if let Some(p) = self.mapping.strings.get(format) {
+            return p.clone();
+        }
+        let specs = specifiers(format);
+        let n = self.numbers.get(FORMAT).unwrap_or(&0) + 1;
+        let tagged = format!("{FORMAT}{n} {}", specs.join(" "));
+        let p = if tagged.trim_end().len() <= self.max_len {
+            self.number(FORMAT);
+            tagged.trim_end().to_owned()
+        } else if specs.join(" ").len() <= self.max_len {
+            specs.join(" ")
+        } else {
+            specs.concat()
+        };
+        self.mapping.strings.insert(format.to_owned(), p.clone());
+        p
+    }
+
+    fn address(&mut self, address: u32) -> u32 {
+        let key = format!("0x{address:08X}");
+        if let Some(p) = self
+            .mapping
+            .addresses
+            .get(&key)
+            .and_then(|a| parse_address(a))
+        {
+            return p;
+        }
+        let p = self.next_address;
+        self.next_address += ADDRESS_STEP;
+        self.mapping.addresses.insert(key, format!("0x{p:08X}"));
+        p
+    }
+}
+
+fn parse_address(s: &str) -> Option<u32> {
+    u32::from_str_radix(s.trim_start_matches("0x"), 16).ok()
+}
+
+/// Conversion specifications of a printf format string, `%%` excluded
+fn specifiers(format: &str) -> Vec<&str> {
+    let mut specs = Vec::new();
+    let mut rest = format;
+    while let Some(start) = rest.find('%') {
+        let s = &rest[start..];
+        let end = s[1..]
+            .char_indices()
+            .find(|(_, c)| !"-+ #0123456789.hlzjt".contains(*c))
+            .map(|(i, c)| 1 + i + c.len_utf8())
+            .unwrap_or(s.len());
+        if &s[..end] != "%%" {
+            specs.push(&s[..end]);
+        }
+        rest = &s[end..];
+    }
+    specs
+}
+
+/// First pass, learns what the handles refer to
+#[derive(Default)]
+struct Survey {
+    custom_printf_event_id: Option<u16>,
+    resync: bool,
+    /// Pseudonym prefix of the handles
+    kinds: HashMap<u32, String>,
+    /// Object handles of the capture
+    handles: BTreeSet<u32>,
+    error: Option<Box<dyn std::error::Error>>,
+}
+
+impl Survey {
+    fn add_entries(&mut self, rd: &RecorderData) {
+        for (handle, entry) in rd.entry_table.entries().iter() {
+            if *handle == ObjectHandle::NO_TASK {
+                continue;
+            }
+            let handle = u32::from(*handle);
+            self.handles.insert(handle);
+            if let Some(class) = entry.class {
+                self.kinds
+                    .entry(handle)
+                    .or_insert_with(|| class.to_string());
+            }
+        }
+    }
+}
+
+impl Analyzer for Survey {
+    fn on_event(&mut self, rd: &RecorderData, record: &EventRecord) {
+        if let TraceEvent::Parsed(event) = &record.event {
+            use trace_recorder_parser::streaming::event::Event::*;
+            let class = match event {
+                TaskCreate(_) | TaskReady(_) | TaskBegin(_) | TaskResume(_) | TaskActivate(_)
+                | TaskPriority(_) => Some(ObjectClass::Task),
+                IsrDefine(_) | IsrBegin(_) | IsrResume(_) => Some(ObjectClass::Isr),
+                _ => None,
+            };
+            if let Some(handle) = event_object_handle(event).filter(|h| *h != ObjectHandle::NO_TASK)
+            {
+                let handle = u32::from(handle);
+                self.handles.insert(handle);
+                if let Some(class) = class {
+                    self.kinds.insert(handle, class.to_string());
+                }
+            }
+        }
+
+        let raw = match RawEvent::parse(&record.data, rd.header.endianness) {
+            Ok(raw) => raw,
+            Err(e) => {
+                self.error.get_or_insert(e.into());
+                return;
+            }
+        };
+        let layout = EventLayout::of(record.event_type, &raw, self.custom_printf_event_id);
+        if matches!(
+            layout,
+            EventLayout::UserEvent { .. }
+                | EventLayout::FixedUserEvent
+                | EventLayout::CustomPrintf { .. }
+        ) {
+            if let Some(channel) = raw.param(0) {
+                self.handles.insert(channel);
+                self.kinds.insert(channel, CHANNEL.to_owned());
+            }
+        }
+        if let (EventLayout::FixedUserEvent, Some(format)) = (layout, raw.param(1)) {
+            self.handles.insert(format);
+            self.kinds.insert(format, FORMAT.to_owned());
+        }
+    }
+
+    fn on_error(&mut self, _rd: &RecorderData, offset: u64, error: &Error) {
+        // Dropping the event would change the structure of the recording, with --resync
+        // the corrupted data is skipped on purpose and reported
+        if !self.resync {
+            self.error.get_or_insert_with(|| {
+                format!("Can't anonymize the event at offset {offset}: {error}").into()
+            });
+        }
+    }
+
+    fn on_restart(&mut self, rd: &RecorderData) {
+        self.add_entries(rd);
+    }
+
+    fn finish(&mut self, rd: &RecorderData, _summary: &TraceSummary) {
+        self.add_entries(rd);
+    }
+}
+
+/// Second pass, writes the capture with the pseudonyms
+struct Anonymizer<'a> {
+    opts: &'a AnonymizeOpts,
+    survey: Survey,
+    pseudonyms: Pseudonyms,
+    out: BufWriter<File>,
+    events: u64,
+    /// First error, reported after the reading is done
+    error: Option<Box<dyn std::error::Error>>,
+}
+
+impl Anonymizer<'_> {
+    /// Pseudonym prefix of a handle
+    fn kind(&self, handle: u32) -> String {
+        self.survey
+            .kinds
+            .get(&handle)
+            .cloned()
+            .unwrap_or_else(|| "Object".to_owned())
+    }
+
+    fn address(&mut self, address: u32) -> u32 {
+        if self.opts.addresses && self.survey.handles.contains(&address) {
+            self.pseudonyms.address(address)
+        } else {
+            address
+        }
+    }
+
+    fn write_header(&mut self, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut header = SessionHeader::parse(data)?;
+        self.pseudonyms.max_len = header.symbol_size.saturating_sub(1);
+        for i in 0..header.entries.len() {
+            let address = header.entries[i].address;
+            let symbol = header.entries[i].symbol();
+            let kind = self.kind(address);
+            header.entries[i].symbol = self.pseudonyms.name(&symbol, &kind).into_bytes();
+            header.entries[i].address = self.address(address);
+        }
+        header.write(&mut self.out)?;
+        Ok(())
+    }
+
+    fn write_event(
+        &mut self,
+        rd: &RecorderData,
+        record: &EventRecord,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut raw = RawEvent::parse(&record.data, rd.header.endianness)?;
+        let layout = EventLayout::of(record.event_type, &raw, self.survey.custom_printf_event_id);
+        for word in layout.handle_words(&raw, &rd.entry_table) {
+            if let Some(v) = raw.param(word) {
+                let p = self.address(v);
+                raw.set_param(word, p);
+            }
+        }
+        match layout {
+            EventLayout::Name { string } => {
+                let kind = self.kind(raw.param(0).unwrap_or(0));
+                let name = self.pseudonyms.name(&raw.string_at(string * 4), &kind);
+                raw.set_string_at(string * 4, &name);
+                raw.num_params = raw.body.len() / 4;
+            }
+            EventLayout::UserEvent { string } => {
+                let format = self.pseudonyms.format(&raw.string_at(string * 4));
+                raw.set_string_at(string * 4, &format);
+                raw.num_params = raw.body.len() / 4;
+            }
+            EventLayout::CustomPrintf { args } => {
+                let offset = 8 + 4 * args;
+                let format = self.pseudonyms.format(&raw.string_at(offset));
+                let len = raw.set_string_at(offset, &format);
+                raw.set_u16_at(6, len as u16);
+            }
+            EventLayout::MemoryBlock => {
+                if let Some(block) = raw.param(0).filter(|b| self.opts.addresses && *b != 0) {
+                    let p = self.pseudonyms.address(block);
+                    raw.set_param(0, p);
+                }
+            }
+            EventLayout::FixedUserEvent | EventLayout::Other { .. } => (),
+        }
+        raw.write(&mut self.out)?;
+        self.events += 1;
+        Ok(())
+    }
+}
+
+impl Analyzer for Anonymizer<'_> {
+    fn on_session_header(&mut self, data: &[u8]) {
+        if self.error.is_none() {
+            if let Err(e) = self.write_header(data) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    fn on_event(&mut self, rd: &RecorderData, record: &EventRecord) {
+        if self.error.is_none() {
+            if let Err(e) = self.write_event(rd, record) {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+fn reader<'a>(
+    opts: &AnonymizeOpts,
+    elf: Option<&'a FirmwareElf>,
+    schema: &'a EventSchema,
+) -> Result<TraceReader<'a, BufReader<File>>, Box<dyn std::error::Error>> {
+    let f = File::open(&opts.path)?;
+    let mut reader = TraceReader::new(BufReader::new(f))?;
+    if let Some(elf) = elf {
+        reader.set_elf(elf);
+    }
+    reader.set_event_schema(schema);
+    reader.set_capture_data(true);
+    if let Some(custom_printf_event_id) = opts.decode.custom_printf_event_id {
+        reader.set_custom_printf_event_id(custom_printf_event_id);
+    }
+    reader.set_resync(opts.decode.resync);
+    Ok(reader)
+}
+
+pub fn run(opts: &AnonymizeOpts) -> Result<(), Box<dyn std::error::Error>> {
+    let elf = opts.decode.load_elf()?;
+    let schema = opts.decode.load_schema()?;
+
+    let mut survey = Survey {
+        custom_printf_event_id: opts.decode.custom_printf_event_id,
+        resync: opts.decode.resync,
+        ..Default::default()
+    };
+    reader(opts, elf.as_ref(), &schema)?.run(&mut [&mut survey])?;
+    if let Some(e) = survey.error.take() {
+        return Err(e);
+    }
+
+    let mut anonymizer = Anonymizer {
+        opts,
+        survey,
+        pseudonyms: Pseudonyms::load(&opts.mapping)?,
+        out: BufWriter::new(File::create(&opts.output)?),
+        events: 0,
+        error: None,
+    };
+    let summary = reader(opts, elf.as_ref(), &schema)?.run(&mut [&mut anonymizer])?;
+    if let Some(e) = anonymizer.error.take() {
+        return Err(e);
+    }
+    anonymizer.out.flush()?;
+    anonymizer.pseudonyms.save(&opts.mapping)?;
+    println!(
+        "Wrote {} events to {}, pseudonyms in {}",
+        anonymizer.events,
+        opts.output.display(),
+        opts.mapping.display()
+    );
+    if !summary.skipped.is_empty() {
+        println!(
+            "Skipped {} bytes of corrupted data, not written",
+            summary.skipped.iter().map(|r| r.len).sum::<u64>()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,325 @@
+use arrow_array::{
+    builder::{
+        Int64Builder, StringBuilder, UInt16Builder, UInt32Builder, UInt64Builder, UInt8Builder,
+    },
+    ArrayRef, RecordBatch,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use std::{fs, fs::File, path::Path, sync::Arc};
+use trace_recorder_parser::streaming::{
+    event::{Event, EventId},
+    RecorderData,
+};
+use trace_recorder_printer::{
+    analyzer::{Analyzer, ContextInterval},
+    elf::FirmwareElf,
+    event::{event_object_handle, ContextHandle, TraceEvent},
+    report::object_name,
+    schema::EventSchema,
+    time::TrcTimeExt,
+    trace::{EventRecord, TraceSummary},
+};
+
+const BATCH_SIZE: usize = 64 * 1024;
+
+/// Writes the decoded events to `events.parquet` and the context running intervals to
+/// `context_intervals.parquet` in the output directory.
+///
+/// Times are ticks and nanoseconds (null when the timer frequency is unknown) since the
+/// start of the recording, restarted sessions included.
+pub struct ParquetExporter<'a> {
+    schema: &'a EventSchema,
+    elf: Option<&'a FirmwareElf>,
+    dir: &'a Path,
+
+    session: u32,
+    /// Start time of each session
+    session_starts: Vec<u64>,
+    last_time: u64,
+    events: EventColumns,
+    writer: Option<ArrowWriter<File>>,
+
+    /// First error, reported by [`ParquetExporter::close`]
+    error: Option<Box<dyn std::error::Error>>,
+}
+
+#[derive(Default)]
+struct EventColumns {
+    session: UInt32Builder,
+    timestamp_ns: Int64Builder,
+    ticks: UInt64Builder,
+    core_id: UInt8Builder,
+    event_type: StringBuilder,
+    event_id: UInt16Builder,
+    event_count: UInt16Builder,
+    handle: UInt32Builder,
+    symbol: StringBuilder,
+    priority: UInt32Builder,
+    user_channel: StringBuilder,
+    user_message: StringBuilder,
+    dropped_before: UInt64Builder,
+    text: StringBuilder,
+    len: usize,
+}
+
+fn events_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("session", DataType::UInt32, false),
+        Field::new("timestamp_ns", DataType::Int64, true),
+        Field::new("ticks", DataType::UInt64, false),
+        Field::new("core_id", DataType::UInt8, false),
+        Field::new("event_type", DataType::Utf8, false),
+        Field::new("event_id", DataType::UInt16, false),
+        Field::new("event_count", DataType::UInt16, false),
+        Field::new("handle", DataType::UInt32, true),
+        Field::new("symbol", DataType::Utf8, true),
+        Field::new("priority", DataType::UInt32, true),
+        Field::new("user_channel", DataType::Utf8, true),
+        Field::new("user_message", DataType::Utf8, true),
+        Field::new("dropped_before", DataType::UInt64, true),
+        Field::new("text", DataType::Utf8, false),
+    ]))
+}
+
+fn intervals_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("session", DataType::UInt32, false),
+        Field::new("core_id", DataType::UInt8, false),
+        Field::new("handle", DataType::UInt32, false),
+        Field::new("symbol", DataType::Utf8, false),
+        Field::new("kind", DataType::Utf8, false),
+        Field::new("start_ticks", DataType::UInt64, false),
+        Field::new("end_ticks", DataType::UInt64, false),
+        Field::new("duration_ticks", DataType::UInt64, false),
+        Field::new("start_ns", DataType::Int64, true),
+        Field::new("end_ns", DataType::Int64, true),
+        Field::new("duration_ns", DataType::Int64, true),
+    ]))
+}
+
+impl EventColumns {
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        self.len = 0;
+        vec![
+            Arc::new(self.session.finish()),
+            Arc::new(self.timestamp_ns.finish()),
+            Arc::new(self.ticks.finish()),
+            Arc::new(self.core_id.finish()),
+            Arc::new(self.event_type.finish()),
+            Arc::new(self.event_id.finish()),
+            Arc::new(self.event_count.finish()),
+            Arc::new(self.handle.finish()),
+            Arc::new(self.symbol.finish()),
+            Arc::new(self.priority.finish()),
+            Arc::new(self.user_channel.finish()),
+            Arc::new(self.user_message.finish()),
+            Arc::new(self.dropped_before.finish()),
+            Arc::new(self.text.finish()),
+        ]
+    }
+}
+
+impl<'a> ParquetExporter<'a> {
+    /// Create the output directory and the events file
+    pub fn create(
+        dir: &'a Path,
+        schema: &'a EventSchema,
+        elf: Option<&'a FirmwareElf>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        fs::create_dir_all(dir)?;
+        let writer = new_writer(&dir.join("events.parquet"), events_schema())?;
+        Ok(Self {
+            schema,
+            elf,
+            dir,
+            session: 0,
+            session_starts: vec![0],
+            last_time: 0,
+            events: EventColumns::default(),
+            writer: Some(writer),
+            error: None,
+        })
+    }
+
+    /// Flush the events and write the context intervals
+    pub fn close(
+        mut self,
+        rd: &RecorderData,
+        intervals: &[ContextInterval],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.flush()?;
+        if let Some(writer) = self.writer.take() {
+            writer.close()?;
+        }
+
+        let ns = |ticks: u64| rd.convert_ticks(ticks).map(|ns| ns as i64);
+        let mut writer = new_writer(
+            &self.dir.join("context_intervals.parquet"),
+            intervals_schema(),
+        )?;
+        for chunk in intervals.chunks(BATCH_SIZE) {
+            let mut session = UInt32Builder::new();
+            let mut core_id = UInt8Builder::new();
+            let mut handle = UInt32Builder::new();
+            let mut symbol = StringBuilder::new();
+            let mut kind = StringBuilder::new();
+            let mut start_ticks = UInt64Builder::new();
+            let mut end_ticks = UInt64Builder::new();
+            let mut duration_ticks = UInt64Builder::new();
+            let mut start_ns = Int64Builder::new();
+            let mut end_ns = Int64Builder::new();
+            let mut duration_ns = Int64Builder::new();
+            for i in chunk.iter() {
+                let (start, end) = (i.start.get_raw(), i.end.get_raw());
+                let s = self.session_starts.partition_point(|s| *s <= start);
+                session.append_value(s.saturating_sub(1) as u32);
+                core_id.append_value(i.core_id);
+                let h = i.context.object_handle();
+                handle.append_value(u32::from(h));
+                symbol.append_value(object_name(rd, self.elf, h));
+                kind.append_value(match i.context {
+                    ContextHandle::Task(_) => "Task",
+                    ContextHandle::Isr(_) => "ISR",
+                });
+                start_ticks.append_value(start);
+                end_ticks.append_value(end);
+                duration_ticks.append_value(end - start);
+                start_ns.append_option(ns(start));
+                end_ns.append_option(ns(end));
+                duration_ns.append_option(ns(end - start));
+            }
+            let columns: Vec<ArrayRef> = vec![
+                Arc::new(session.finish()),
+                Arc::new(core_id.finish()),
+                Arc::new(handle.finish()),
+                Arc::new(symbol.finish()),
+                Arc::new(kind.finish()),
+                Arc::new(start_ticks.finish()),
+                Arc::new(end_ticks.finish()),
+                Arc::new(duration_ticks.finish()),
+                Arc::new(start_ns.finish()),
+                Arc::new(end_ns.finish()),
+                Arc::new(duration_ns.finish()),
+            ];
+            writer.write(&RecordBatch::try_new(intervals_schema(), columns)?)?;
+        }
+        writer.close()?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.events.len == 0 {
+            return Ok(());
+        }
+        let batch = RecordBatch::try_new(events_schema(), self.events.finish())?;
+        if let Some(writer) = self.writer.as_mut() {
+            writer.write(&batch)?;
+        }
+        Ok(())
+    }
+
+    fn append_event(&mut self, rd: &RecorderData, record: &EventRecord) {
+        let time = record.trace_time.get_raw();
+        self.last_time = time;
+
+        let handle = match &record.event {
+            TraceEvent::Parsed(ev) => event_object_handle(ev),
+            _ => None,
+        };
+        let priority = match &record.event {
+            TraceEvent::Parsed(
+                Event::TaskCreate(ev)
+                | Event::TaskReady(ev)
+                | Event::TaskPriority(ev)
+                | Event::TaskPriorityInherit(ev)
+                | Event::TaskPriorityDisinherit(ev)
+                | Event::TaskBegin(ev)
+                | Event::TaskResume(ev)
+                | Event::TaskActivate(ev),
+            ) => Some(u32::from(ev.priority)),
+            TraceEvent::Parsed(
+                Event::IsrDefine(ev) | Event::IsrBegin(ev) | Event::IsrResume(ev),
+            ) => Some(u32::from(ev.priority)),
+            _ => None,
+        };
+        let (user_channel, user_message) = match &record.event {
+            TraceEvent::Parsed(Event::User(ev)) => (
+                Some(ev.channel.to_string()),
+                Some(ev.formatted_string.to_string()),
+            ),
+            TraceEvent::ElfUser(ev) => {
+                (Some(ev.channel.clone()), Some(ev.formatted_string.clone()))
+            }
+            _ => (None, None),
+        };
+        let event_type = self
+            .schema
+            .name(record.event_type)
+            .map(|n| n.to_owned())
+            .unwrap_or_else(|| record.event_type.to_string());
+
+        let c = &mut self.events;
+        c.session.append_value(self.session);
+        c.timestamp_ns
+            .append_option(rd.convert_ticks(time).map(|ns| ns as i64));
+        c.ticks.append_value(time);
+        c.core_id.append_value(record.core_id);
+        c.event_type.append_value(event_type);
+        c.event_id.append_value(EventId::from(record.event_type).0);
+        c.event_count.append_value(record.event_count);
+        c.handle.append_option(handle.map(u32::from));
+        c.symbol
+            .append_option(handle.map(|h| object_name(rd, self.elf, h)));
+        c.priority.append_option(priority);
+        c.user_channel.append_option(user_channel);
+        c.user_message.append_option(user_message);
+        c.dropped_before.append_option(record.dropped_events);
+        c.text.append_value(record.event.to_string());
+        c.len += 1;
+    }
+}
+
+impl Analyzer for ParquetExporter<'_> {
+    fn on_event(&mut self, rd: &RecorderData, record: &EventRecord) {
+        if self.error.is_some() {
+            return;
+        }
+        self.append_event(rd, record);
+        if self.events.len >= BATCH_SIZE {
+            if let Err(e) = self.flush() {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    fn on_restart(&mut self, _rd: &RecorderData) {
+        self.session += 1;
+        self.session_starts.push(self.last_time);
+    }
+
+    fn finish(&mut self, _rd: &RecorderData, _summary: &TraceSummary) {
+        if self.error.is_none() {
+            if let Err(e) = self.flush() {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+fn new_writer(
+    path: &Path,
+    schema: SchemaRef,
+) -> Result<ArrowWriter<File>, Box<dyn std::error::Error>> {
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    Ok(ArrowWriter::try_new(
+        File::create(path)?,
+        schema,
+        Some(props),
+    )?)
+}
@@ -0,0 +1,328 @@
+use crate::cli::DecodeOpts;
+use clap::Args;
+use comfy_table::modifiers::UTF8_ROUND_CORNERS;
+use comfy_table::presets::UTF8_FULL;
+use comfy_table::*;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use trace_recorder_printer::report::{ContextReport, TraceReport};
+
+/// Compare two captures, e.g. before and after a firmware change
+#[derive(Args, Debug, Clone)]
+pub struct CompareOpts {
+    #[clap(flatten)]
+    pub decode: DecodeOpts,
+
+    /// Highlight CPU usage changes larger than this many percentage points
+    #[clap(long, default_value_t = 1.0, value_name = "points")]
+    pub cpu_threshold: f64,
+
+    /// Highlight execution time changes larger than this percentage
+    #[clap(long, default_value_t = 10.0, value_name = "percent")]
+    pub runtime_threshold: f64,
+
+    /// Highlight event rate changes larger than this percentage
+    #[clap(long, default_value_t = 10.0, value_name = "percent")]
+    pub rate_threshold: f64,
+
+    /// Highlight stack low mark decreases larger than this
+    #[clap(long, default_value_t = 16, value_name = "words")]
+    pub stack_threshold: u32,
+
+    /// Baseline streaming data file (psf)
+    #[clap(value_parser)]
+    pub baseline: PathBuf,
+
+    /// Candidate streaming data file (psf)
+    #[clap(value_parser)]
+    pub candidate: PathBuf,
+}
+
+type PercentileFn = fn(&ContextReport) -> f64;
+
+/// How a change compares to its threshold
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Change {
+    Within,
+    Better,
+    Worse,
+    /// Beyond the threshold, but not inherently good or bad
+    Notable,
+}
+
+impl Change {
+    fn cell(self, text: String) -> Cell {
+        let cell = Cell::new(text);
+        match self {
+            Change::Within => cell,
+            Change::Better => cell.fg(Color::Green),
+            Change::Worse => cell.fg(Color::Red).add_attribute(Attribute::Bold),
+            Change::Notable => cell.fg(Color::Yellow),
+        }
+    }
+}
+
+pub fn run(opts: &CompareOpts) -> Result<(), Box<dyn std::error::Error>> {
+    let elf = opts.decode.load_elf()?;
+    let schema = opts.decode.load_schema()?;
+    let load = |path: &PathBuf| {
+        TraceReport::load(
+            path,
+            elf.as_ref(),
+            Some(&schema),
+            opts.decode.custom_printf_event_id,
+        )
+    };
+    let base = load(&opts.baseline)?;
+    let cand = load(&opts.candidate)?;
+
+    println!("Baseline: {}", opts.baseline.display());
+    println!("Candidate: {}", opts.candidate.display());
+    println!();
+
+    let mut flagged = Vec::new();
+
+    // Contexts, lined up by symbol
+    let symbols: BTreeSet<&String> = base.contexts.keys().chain(cand.contexts.keys()).collect();
+    let mut rows = Vec::new();
+    for sym in symbols {
+        let b = base.contexts.get(sym);
+        let c = cand.contexts.get(sym);
+        let kind = b.or(c).map(|ctx| ctx.kind.to_string()).unwrap_or_default();
+
+        let (Some(b), Some(c)) = (b, c) else {
+            let which = if b.is_some() { "baseline" } else { "candidate" };
+            flagged.push(format!("{sym}: only in the {which}"));
+            let ctx = b.or(c).unwrap();
+            let mut row = vec![
+                Change::Notable.cell(sym.clone()),
+                Cell::new(kind),
+                Change::Notable.cell(format!("only in {which} ({:.02})", ctx.cpu_percent)),
+            ];
+            row.extend((0..5).map(|_| Cell::new("")));
+            rows.push(row);
+            continue;
+        };
+
+        let cpu_delta = c.cpu_percent - b.cpu_percent;
+        let cpu_change = if cpu_delta.abs() <= opts.cpu_threshold {
+            Change::Within
+        } else if cpu_delta > 0.0 {
+            Change::Worse
+        } else {
+            Change::Better
+        };
+        if cpu_change != Change::Within {
+            flagged.push(format!(
+                "{sym}: CPU {:.02}% -> {:.02}% ({cpu_delta:+.02})",
+                b.cpu_percent, c.cpu_percent
+            ));
+        }
+
+        let mut row = vec![
+            Cell::new(sym),
+            Cell::new(kind),
+            cpu_change.cell(format!(
+                "{:.02} -> {:.02} ({cpu_delta:+.02})",
+                b.cpu_percent, c.cpu_percent
+            )),
+        ];
+
+        let percentiles: [(&str, PercentileFn); 4] = [
+            ("P50", |ctx| ctx.runtime.p50),
+            ("P90", |ctx| ctx.runtime.p90),
+            ("P99", |ctx| ctx.runtime.p99),
+            ("Max", |ctx| ctx.runtime.max),
+        ];
+        for (name, get) in percentiles {
+            let (bv, cv) = (get(b), get(c));
+            let delta = relative_change(bv, cv);
+            let change = match delta {
+                Some(d) if d.abs() > opts.runtime_threshold => {
+                    if d > 0.0 {
+                        Change::Worse
+                    } else {
+                        Change::Better
+                    }
+                }
+                _ => Change::Within,
+            };
+            let text = format!(
+                "{} -> {}{}",
+                fmt_ticks(&base, bv),
+                fmt_ticks(&cand, cv),
+                fmt_relative(delta)
+            );
+            if change != Change::Within {
+                flagged.push(format!("{sym}: {name} execution time {text}"));
+            }
+            row.push(change.cell(text));
+        }
+
+        let stack = match (b.stack_low_mark, c.stack_low_mark) {
+            (Some(bl), Some(cl)) => {
+                let delta = i64::from(cl) - i64::from(bl);
+                let change = if delta.unsigned_abs() <= u64::from(opts.stack_threshold) {
+                    Change::Within
+                } else if delta < 0 {
+                    Change::Worse
+                } else {
+                    Change::Better
+                };
+                let text = format!("{bl} -> {cl} ({delta:+})");
+                if change != Change::Within {
+                    flagged.push(format!("{sym}: stack low mark {text}"));
+                }
+                change.cell(text)
+            }
+            (Some(bl), None) => Cell::new(format!("{bl} -> ")),
+            (None, Some(cl)) => Cell::new(format!(" -> {cl}")),
+            (None, None) => Cell::new(""),
+        };
+        row.push(stack);
+        rows.push(row);
+    }
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec![
+            "Symbol", "Type", "CPU %", "P50", "P90", "P99", "Max", "Stack LM",
+        ])
+        .add_rows(rows);
+    for c in table.column_iter_mut() {
+        c.set_cell_alignment(CellAlignment::Right);
+    }
+    table
+        .column_mut(0)
+        .unwrap()
+        .set_cell_alignment(CellAlignment::Left);
+    println!("{table}");
+    println!();
+
+    // Event rates, lined up by type name
+    let rate_unit = if base.total_seconds().is_some() && cand.total_seconds().is_some() {
+        "/s"
+    } else {
+        "/tick"
+    };
+    let types: BTreeSet<&String> = base
+        .event_types
+        .keys()
+        .chain(cand.event_types.keys())
+        .collect();
+    let mut rows = Vec::new();
+    for t in types {
+        let (br, cr) = (base.event_rate(t), cand.event_rate(t));
+        let delta = relative_change(br, cr);
+        let change = match delta {
+            Some(d) if d.abs() <= opts.rate_threshold => Change::Within,
+            Some(_) => Change::Notable,
+            // New event type
+            None if cr > 0.0 => Change::Notable,
+            None => Change::Within,
+        };
+        if change != Change::Within {
+            flagged.push(format!(
+                "{t}: rate {br:.03}{rate_unit} -> {cr:.03}{rate_unit}{}",
+                fmt_relative(delta)
+            ));
+        }
+        rows.push(vec![
+            Cell::new(base.event_types.get(t).copied().unwrap_or(0)),
+            Cell::new(cand.event_types.get(t).copied().unwrap_or(0)),
+            Cell::new(format!("{br:.03}")),
+            Cell::new(format!("{cr:.03}")),
+            change.cell(
+                delta
+                    .map(|d| format!("{d:+.01}%"))
+                    .unwrap_or_else(|| "new".to_owned()),
+            ),
+            Cell::new(t),
+        ]);
+    }
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec![
+            "Baseline Count".to_owned(),
+            "Candidate Count".to_owned(),
+            format!("Baseline {rate_unit}"),
+            format!("Candidate {rate_unit}"),
+            "Δ".to_owned(),
+            "Type".to_owned(),
+        ])
+        .add_rows(rows);
+    for c in table.column_iter_mut() {
+        c.set_cell_alignment(CellAlignment::Right);
+    }
+    table
+        .column_mut(5)
+        .unwrap()
+        .set_cell_alignment(CellAlignment::Left);
+    println!("{table}");
+    println!();
+
+    if cand.dropped_events > base.dropped_events {
+        flagged.push(format!(
+            "Dropped events: {} -> {}",
+            base.dropped_events, cand.dropped_events
+        ));
+    }
+    println!(
+        "Total events: {} -> {}",
+        base.total_events, cand.total_events
+    );
+    println!(
+        "Dropped events: {} -> {}",
+        base.dropped_events, cand.dropped_events
+    );
+    println!("Trace restarts: {} -> {}", base.restarts, cand.restarts);
+    println!(
+        "Total time: {} -> {}",
+        fmt_ticks(&base, base.total_time.get_raw() as f64),
+        fmt_ticks(&cand, cand.total_time.get_raw() as f64)
+    );
+    println!();
+
+    if flagged.is_empty() {
+        println!("No changes beyond the thresholds");
+    } else {
+        println!("Changes beyond the thresholds:");
+        for f in flagged.iter() {
+            println!("  - {f}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Change from `base` to `cand` in percent, None when the baseline is zero
+fn relative_change(base: f64, cand: f64) -> Option<f64> {
+    if base == 0.0 {
+        if cand == 0.0 {
+            Some(0.0)
+        } else {
+            None
+        }
+    } else {
+        Some(100.0 * (cand - base) / base)
+    }
+}
+
+fn fmt_relative(delta: Option<f64>) -> String {
+    match delta {
+        Some(d) => format!(" ({d:+.01}%)"),
+        None => " (new)".to_owned(),
+    }
+}
+
+fn fmt_ticks(report: &TraceReport, ticks: f64) -> String {
+    match report.duration(ticks) {
+        Some(d) => format!("{d:?}"),
+        None => format!("{ticks:.0} ticks"),
+    }
+}
@@ -0,0 +1,636 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+    fs::{self, File},
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+use trace_recorder_parser::{
+    streaming::{
+        event::{Event, EventType},
+        RecorderData,
+    },
+    time::Ticks,
+    types::ObjectHandle,
+};
+use trace_recorder_printer::{
+    analyzer::Analyzer,
+    elf::FirmwareElf,
+    event::{CoreId, TraceEvent},
+    report::object_name,
+    schema::{EventSchema, FieldValue},
+    trace::{EventRecord, TraceSummary},
+};
+
+const CTF_MAGIC: u32 = 0xC1FC_1FC1;
+
+/// Packet header (magic, stream_id) and context (timestamp_begin, timestamp_end,
+/// content_size, packet_size, events_discarded, cpu_id) sizes in bytes
+const PACKET_HEADER_SIZE: u64 = 8;
+const PACKET_CONTEXT_SIZE: u64 = 44;
+
+/// Writes a CTF 1.8 trace directory: a `metadata` file and one binary stream file per core.
+///
+/// Each stream is a single packet, its context is filled in once the reading is done.
+/// Event classes are declared as they are first seen, one per event type, with the
+/// fields of the parser's event structure. Timestamps are the ticks since the start of
+/// the recording, restarted sessions included.
+pub struct CtfExporter<'a> {
+    schema: &'a EventSchema,
+    elf: Option<&'a FirmwareElf>,
+    dir: &'a Path,
+
+    classes: Vec<EventClass>,
+    class_ids: HashMap<(EventType, String), u32>,
+    streams: BTreeMap<CoreId, Stream>,
+
+    /// First error, reported by [`CtfExporter::close`]
+    error: Option<Box<dyn std::error::Error>>,
+}
+
+struct EventClass {
+    name: String,
+    fields: Vec<(String, FieldType)>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum FieldType {
+    U32,
+    I64,
+    U64,
+    F64,
+    Str,
+    /// A `<name>_len` count followed by that many strings
+    StrSeq,
+}
+
+enum Value {
+    U32(u32),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Str(String),
+    StrSeq(Vec<String>),
+}
+
+impl Value {
+    fn field_type(&self) -> FieldType {
+        match self {
+            Value::U32(_) => FieldType::U32,
+            Value::I64(_) => FieldType::I64,
+            Value::U64(_) => FieldType::U64,
+            Value::F64(_) => FieldType::F64,
+            Value::Str(_) => FieldType::Str,
+            Value::StrSeq(_) => FieldType::StrSeq,
+        }
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Value::U32(v) => buf.extend_from_slice(&v.to_le_bytes()),
+            Value::I64(v) => buf.extend_from_slice(&v.to_le_bytes()),
+            Value::U64(v) => buf.extend_from_slice(&v.to_le_bytes()),
+            Value::F64(v) => buf.extend_from_slice(&v.to_le_bytes()),
+            Value::Str(s) => encode_str(s, buf),
+            Value::StrSeq(v) => {
+                buf.extend_from_slice(&(v.len() as u32).to_le_bytes());
+                v.iter().for_each(|s| encode_str(s, buf));
+            }
+        }
+    }
+}
+
+fn encode_str(s: &str, buf: &mut Vec<u8>) {
+    // CTF strings are null-terminated
+    buf.extend(s.bytes().filter(|b| *b != 0));
+    buf.push(0);
+}
+
+struct Stream {
+    file: BufWriter<File>,
+    /// Bytes written, packet header included
+    len: u64,
+    begin: Option<u64>,
+    end: u64,
+    discarded: u64,
+}
+
+impl<'a> CtfExporter<'a> {
+    /// Create the output directory, removing the streams of a previous conversion
+    pub fn create(
+        dir: &'a Path,
+        schema: &'a EventSchema,
+        elf: Option<&'a FirmwareElf>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        fs::create_dir_all(dir)?;
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default();
+            if name == "metadata" || name.starts_with("stream_") {
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(Self {
+            schema,
+            elf,
+            dir,
+            classes: Vec::new(),
+            class_ids: HashMap::new(),
+            streams: BTreeMap::new(),
+            error: None,
+        })
+    }
+
+    /// Fill in the packet contexts and write the metadata
+    pub fn close(mut self, rd: &RecorderData) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        for (core_id, stream) in std::mem::take(&mut self.streams).into_iter() {
+            let mut file = stream.file.into_inner().map_err(|e| e.into_error())?;
+            let bits = stream.len * 8;
+            let mut context = Vec::with_capacity(PACKET_CONTEXT_SIZE as usize);
+            context.extend_from_slice(&stream.begin.unwrap_or(0).to_le_bytes());
+            context.extend_from_slice(&stream.end.to_le_bytes());
+            context.extend_from_slice(&bits.to_le_bytes());
+            context.extend_from_slice(&bits.to_le_bytes());
+            context.extend_from_slice(&stream.discarded.to_le_bytes());
+            context.extend_from_slice(&u32::from(core_id).to_le_bytes());
+            file.seek(SeekFrom::Start(PACKET_HEADER_SIZE))?;
+            file.write_all(&context)?;
+        }
+        fs::write(self.dir.join("metadata"), self.metadata(rd))?;
+        Ok(())
+    }
+
+    fn metadata(&self, rd: &RecorderData) -> String {
+        let freq = rd.timestamp_info.timer_frequency;
+        let (freq, clock_description) = if freq.is_unitless() {
+            (1_000_000_000, "Timer ticks, the frequency is unknown")
+        } else {
+            (
+                freq.get_raw(),
+                "Timer ticks since the start of the recording",
+            )
+        };
+
+        let mut m = String::new();
+        let _ = write!(
+            m,
+            r#"/* CTF 1.8 */
+
+typealias integer {{ size = 16; align = 8; signed = false; }} := uint16_t;
+typealias integer {{ size = 32; align = 8; signed = false; }} := uint32_t;
+typealias integer {{ size = 64; align = 8; signed = false; }} := uint64_t;
+typealias integer {{ size = 64; align = 8; signed = true; }} := int64_t;
+typealias floating_point {{ exp_dig = 11; mant_dig = 53; align = 8; }} := double;
+
+trace {{
+    major = 1;
+    minor = 8;
+    byte_order = le;
+    packet.header := struct {{
+        uint32_t magic;
+        uint32_t stream_id;
+    }};
+}};
+
+env {{
+    domain = "freertos";
+    tracer_name = "trace-recorder";
+    kernel_version = "{}";
+    kernel_port = "{}";
+    format_version = {};
+    num_cores = {};
+}};
+
+clock {{
+    name = trace_recorder;
+    description = "{clock_description}";
+    freq = {freq};
+    offset = 0;
+}};
+
+typealias integer {{
+    size = 64; align = 8; signed = false;
+    map = clock.trace_recorder.value;
+}} := uint64_clock_t;
+
+stream {{
+    id = 0;
+    packet.context := struct {{
+        uint64_clock_t timestamp_begin;
+        uint64_clock_t timestamp_end;
+        uint64_t content_size;
+        uint64_t packet_size;
+        uint64_t events_discarded;
+        uint32_t cpu_id;
+    }};
+    event.header := struct {{
+        uint32_t id;
+        uint64_clock_t timestamp;
+    }};
+    event.context := struct {{
+        uint16_t event_count;
+    }};
+}};
+"#,
+            escape(&rd.header.kernel_version.to_string()),
+            escape(&rd.header.kernel_port.to_string()),
+            rd.header.format_version,
+            rd.header.num_cores,
+        );
+
+        for (id, class) in self.classes.iter().enumerate() {
+            let _ = write!(
+                m,
+                "\nevent {{\n    name = \"{}\";\n    id = {id};\n    stream_id = 0;\n    fields := struct {{\n",
+                escape(&class.name)
+            );
+            for (name, ty) in class.fields.iter() {
+                let _ = match ty {
+                    FieldType::U32 => writeln!(m, "        uint32_t {name};"),
+                    FieldType::I64 => writeln!(m, "        int64_t {name};"),
+                    FieldType::U64 => writeln!(m, "        uint64_t {name};"),
+                    FieldType::F64 => writeln!(m, "        double {name};"),
+                    FieldType::Str => writeln!(m, "        string {name};"),
+                    FieldType::StrSeq => writeln!(
+                        m,
+                        "        uint32_t {name}_len;\n        string {name}[{name}_len];"
+                    ),
+                };
+            }
+            m.push_str("    };\n};\n");
+        }
+        m
+    }
+
+    /// ID of the event class for the event type and fields, declared on first use
+    fn class_id(&mut self, event_type: EventType, name: String, fields: &[(String, Value)]) -> u32 {
+        let signature = fields
+            .iter()
+            .map(|(n, v)| format!("{n}:{:?}", v.field_type()))
+            .collect::<Vec<_>>()
+            .join(",");
+        if let Some(id) = self.class_ids.get(&(event_type, signature.clone())) {
+            return *id;
+        }
+
+        // Event class names must be unique
+        let mut unique_name = name.clone();
+        let mut n = 1;
+        while self.classes.iter().any(|c| c.name == unique_name) {
+            n += 1;
+            unique_name = format!("{name}_{n}");
+        }
+        let id = self.classes.len() as u32;
+        self.classes.push(EventClass {
+            name: unique_name,
+            fields: fields
+                .iter()
+                .map(|(n, v)| (n.clone(), v.field_type()))
+                .collect(),
+        });
+        self.class_ids.insert((event_type, signature), id);
+        id
+    }
+
+    fn write_event(
+        &mut self,
+        rd: &RecorderData,
+        record: &EventRecord,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let fields = event_fields(rd, self.elf, &record.event);
+        let name = match &record.event {
+            TraceEvent::Custom(ev) => ev.name.clone(),
+            _ => self
+                .schema
+                .name(record.event_type)
+                .map(|n| n.to_owned())
+                .unwrap_or_else(|| record.event_type.to_string()),
+        };
+        let id = self.class_id(record.event_type, name, &fields);
+
+        let time = record.trace_time.get_raw();
+        let mut buf = Vec::with_capacity(64);
+        buf.extend_from_slice(&id.to_le_bytes());
+        buf.extend_from_slice(&time.to_le_bytes());
+        buf.extend_from_slice(&record.event_count.to_le_bytes());
+        fields.iter().for_each(|(_, v)| v.encode(&mut buf));
+
+        let stream = match self.streams.entry(record.core_id) {
+            std::collections::btree_map::Entry::Occupied(e) => e.into_mut(),
+            std::collections::btree_map::Entry::Vacant(e) => {
+                let path = self.dir.join(format!("stream_{}", record.core_id));
+                let mut file = BufWriter::new(File::create(path)?);
+                file.write_all(&CTF_MAGIC.to_le_bytes())?;
+                file.write_all(&0_u32.to_le_bytes())?;
+                // The packet context is filled in by close()
+                file.write_all(&[0; PACKET_CONTEXT_SIZE as usize])?;
+                e.insert(Stream {
+                    file,
+                    len: PACKET_HEADER_SIZE + PACKET_CONTEXT_SIZE,
+                    begin: None,
+                    end: 0,
+                    discarded: 0,
+                })
+            }
+        };
+        stream.file.write_all(&buf)?;
+        stream.len += buf.len() as u64;
+        stream.begin.get_or_insert(time);
+        stream.end = time;
+        stream.discarded += record.dropped_events.unwrap_or(0);
+        Ok(())
+    }
+}
+
+impl Analyzer for CtfExporter<'_> {
+    fn on_event(&mut self, rd: &RecorderData, record: &EventRecord) {
+        if self.error.is_none() {
+            if let Err(e) = self.write_event(rd, record) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    fn finish(&mut self, _rd: &RecorderData, _summary: &TraceSummary) {
+        for stream in self.streams.values_mut() {
+            if let Err(e) = stream.file.flush() {
+                self.error.get_or_insert(e.into());
+            }
+        }
+    }
+}
+
+/// The fields of the parser's event structure
+fn event_fields(
+    rd: &RecorderData,
+    elf: Option<&FirmwareElf>,
+    event: &TraceEvent,
+) -> Vec<(String, Value)> {
+    let mut f: Vec<(String, Value)> = Vec::new();
+    let mut push = |name: &str, value: Value| f.push((name.to_owned(), value));
+    let object = |handle: ObjectHandle, name: Option<String>| {
+        Value::Str(name.unwrap_or_else(|| object_name(rd, elf, handle)))
+    };
+    // Absent timeouts are -1
+    let ticks = |t: Option<Ticks>| Value::I64(t.map(|t| i64::from(t.get_raw())).unwrap_or(-1));
+
+    let ev = match event {
+        TraceEvent::Parsed(ev) => ev,
+        TraceEvent::ElfUser(ev) => {
+            push("channel", Value::Str(ev.channel.clone()));
+            push("format_string", Value::Str(ev.format_string.clone()));
+            push("formatted_string", Value::Str(ev.formatted_string.clone()));
+            push("args", Value::StrSeq(Vec::new()));
+            return f;
+        }
+        TraceEvent::Custom(ev) => {
+            for (name, value) in ev.fields.iter() {
+                let value = match value {
+                    FieldValue::Unsigned(v) => Value::U64(*v),
+                    FieldValue::Signed(v) => Value::I64(*v),
+                    FieldValue::Float(v) => Value::F64(*v),
+                    FieldValue::String(v) => Value::Str(v.clone()),
+                };
+                push(&identifier(name), value);
+            }
+            return f;
+        }
+    };
+
+    match ev {
+        Event::TraceStart(ev) => {
+            push(
+                "current_task_handle",
+                Value::U32(ev.current_task_handle.into()),
+            );
+            push("current_task", Value::Str(ev.current_task.to_string()));
+        }
+        Event::TsConfig(ev) => {
+            push("frequency", Value::U32(ev.frequency.get_raw()));
+            push("tick_rate_hz", Value::U32(ev.tick_rate_hz));
+            push("hwtc_type", Value::Str(ev.hwtc_type.to_string()));
+            push(
+                "isr_chaining_threshold",
+                Value::U32(ev.isr_chaining_threshold),
+            );
+            push(
+                "htc_period",
+                Value::I64(ev.htc_period.map(i64::from).unwrap_or(-1)),
+            );
+        }
+        Event::ObjectName(ev) => {
+            push("handle", Value::U32(ev.handle.into()));
+            push("name", Value::Str(ev.name.to_string()));
+        }
+        Event::TaskCreate(ev)
+        | Event::TaskReady(ev)
+        | Event::TaskPriority(ev)
+        | Event::TaskPriorityInherit(ev)
+        | Event::TaskPriorityDisinherit(ev)
+        | Event::TaskBegin(ev)
+        | Event::TaskResume(ev)
+        | Event::TaskActivate(ev) => {
+            push("handle", Value::U32(ev.handle.into()));
+            push("name", Value::Str(ev.name.to_string()));
+            push("priority", Value::U32(ev.priority.into()));
+        }
+        Event::IsrDefine(ev) | Event::IsrBegin(ev) | Event::IsrResume(ev) => {
+            push("handle", Value::U32(ev.handle.into()));
+            push("name", Value::Str(ev.name.to_string()));
+            push("priority", Value::U32(ev.priority.into()));
+        }
+        Event::TaskNotify(ev)
+        | Event::TaskNotifyFromIsr(ev)
+        | Event::TaskNotifyWait(ev)
+        | Event::TaskNotifyWaitBlock(ev) => {
+            push("handle", Value::U32(ev.handle.into()));
+            push(
+                "task_name",
+                object(ev.handle, ev.task_name.as_ref().map(|n| n.to_string())),
+            );
+            push("ticks_to_wait", ticks(ev.ticks_to_wait));
+        }
+        Event::MemoryAlloc(ev) | Event::MemoryFree(ev) => {
+            push("address", Value::U32(ev.address));
+            push("size", Value::U32(ev.size));
+            push("heap_current", Value::U32(ev.heap.current));
+            push("heap_high_water_mark", Value::U32(ev.heap.high_water_mark));
+            push("heap_max", Value::U32(ev.heap.max));
+        }
+        Event::QueueCreate(ev) => {
+            push("handle", Value::U32(ev.handle.into()));
+            push(
+                "name",
+                object(ev.handle, ev.name.as_ref().map(|n| n.to_string())),
+            );
+            push("queue_length", Value::U32(ev.queue_length));
+        }
+        Event::QueueSend(ev)
+        | Event::QueueSendBlock(ev)
+        | Event::QueueSendFromIsr(ev)
+        | Event::QueueReceive(ev)
+        | Event::QueueReceiveBlock(ev)
+        | Event::QueueReceiveFromIsr(ev)
+        | Event::QueuePeek(ev)
+        | Event::QueuePeekBlock(ev)
+        | Event::QueueSendFront(ev)
+        | Event::QueueSendFrontBlock(ev)
+        | Event::QueueSendFrontFromIsr(ev) => {
+            push("handle", Value::U32(ev.handle.into()));
+            push(
+                "name",
+                object(ev.handle, ev.name.as_ref().map(|n| n.to_string())),
+            );
+            push("ticks_to_wait", ticks(ev.ticks_to_wait));
+            push("messages_waiting", Value::U32(ev.messages_waiting));
+        }
+        Event::MutexCreate(ev) => {
+            push("handle", Value::U32(ev.handle.into()));
+            push(
+                "name",
+                object(ev.handle, ev.name.as_ref().map(|n| n.to_string())),
+            );
+        }
+        Event::MutexGive(ev)
+        | Event::MutexGiveBlock(ev)
+        | Event::MutexGiveRecursive(ev)
+        | Event::MutexTake(ev)
+        | Event::MutexTakeBlock(ev)
+        | Event::MutexTakeRecursive(ev)
+        | Event::MutexTakeRecursiveBlock(ev) => {
+            push("handle", Value::U32(ev.handle.into()));
+            push(
+                "name",
+                object(ev.handle, ev.name.as_ref().map(|n| n.to_string())),
+            );
+            push("ticks_to_wait", ticks(ev.ticks_to_wait));
+        }
+        Event::SemaphoreBinaryCreate(ev) | Event::SemaphoreCountingCreate(ev) => {
+            push("handle", Value::U32(ev.handle.into()));
+            push(
+                "name",
+                object(ev.handle, ev.name.as_ref().map(|n| n.to_string())),
+            );
+            push("count", Value::I64(ev.count.map(i64::from).unwrap_or(-1)));
+        }
+        Event::SemaphoreGive(ev)
+        | Event::SemaphoreGiveBlock(ev)
+        | Event::SemaphoreGiveFromIsr(ev)
+        | Event::SemaphoreTake(ev)
+        | Event::SemaphoreTakeBlock(ev)
+        | Event::SemaphoreTakeFromIsr(ev)
+        | Event::SemaphorePeek(ev)
+        | Event::SemaphorePeekBlock(ev) => {
+            push("handle", Value::U32(ev.handle.into()));
+            push(
+                "name",
+                object(ev.handle, ev.name.as_ref().map(|n| n.to_string())),
+            );
+            push("ticks_to_wait", ticks(ev.ticks_to_wait));
+            push("count", Value::U32(ev.count));
+        }
+        Event::EventGroupCreate(ev) => {
+            push("handle", Value::U32(ev.handle.into()));
+            push(
+                "name",
+                object(ev.handle, ev.name.as_ref().map(|n| n.to_string())),
+            );
+            push("event_bits", Value::U32(ev.event_bits));
+        }
+        Event::EventGroupSync(ev)
+        | Event::EventGroupWaitBits(ev)
+        | Event::EventGroupClearBits(ev)
+        | Event::EventGroupClearBitsFromIsr(ev)
+        | Event::EventGroupSetBits(ev)
+        | Event::EventGroupSetBitsFromIsr(ev)
+        | Event::EventGroupSyncBlock(ev)
+        | Event::EventGroupWaitBitsBlock(ev) => {
+            push("handle", Value::U32(ev.handle.into()));
+            push(
+                "name",
+                object(ev.handle, ev.name.as_ref().map(|n| n.to_string())),
+            );
+            push("bits", Value::U32(ev.bits));
+        }
+        Event::MessageBufferCreate(ev) => {
+            push("handle", Value::U32(ev.handle.into()));
+            push(
+                "name",
+                object(ev.handle, ev.name.as_ref().map(|n| n.to_string())),
+            );
+            push("buffer_size", Value::U32(ev.buffer_size));
+        }
+        Event::MessageBufferSend(ev)
+        | Event::MessageBufferReceive(ev)
+        | Event::MessageBufferSendFromIsr(ev)
+        | Event::MessageBufferReceiveFromIsr(ev)
+        | Event::MessageBufferReset(ev) => {
+            push("handle", Value::U32(ev.handle.into()));
+            push(
+                "name",
+                object(ev.handle, ev.name.as_ref().map(|n| n.to_string())),
+            );
+            push("bytes_in_buffer", Value::U32(ev.bytes_in_buffer));
+        }
+        Event::MessageBufferSendBlock(ev) | Event::MessageBufferReceiveBlock(ev) => {
+            push("handle", Value::U32(ev.handle.into()));
+            push(
+                "name",
+                object(ev.handle, ev.name.as_ref().map(|n| n.to_string())),
+            );
+        }
+        Event::StateMachineCreate(ev) => {
+            push("handle", Value::U32(ev.handle.into()));
+            push("name", Value::Str(ev.name.to_string()));
+        }
+        Event::StateMachineStateCreate(ev) | Event::StateMachineStateChange(ev) => {
+            push("handle", Value::U32(ev.handle.into()));
+            push("name", Value::Str(ev.name.to_string()));
+            push("state_handle", Value::U32(ev.state_handle.into()));
+            push("state", Value::Str(ev.state.to_string()));
+        }
+        Event::User(ev) => {
+            push("channel", Value::Str(ev.channel.to_string()));
+            push("format_string", Value::Str(ev.format_string.to_string()));
+            push(
+                "formatted_string",
+                Value::Str(ev.formatted_string.to_string()),
+            );
+            push(
+                "args",
+                Value::StrSeq(ev.args.iter().map(|a| a.to_string()).collect()),
+            );
+        }
+        Event::UnusedStack(ev) => {
+            push("handle", Value::U32(ev.handle.into()));
+            push("task", Value::Str(ev.task.to_string()));
+            push("low_mark", Value::U32(ev.low_mark));
+        }
+        Event::Unknown(ev) => {
+            push("code", Value::U32(u16::from(ev.code).into()));
+        }
+    }
+    f
+}
+
+/// A TSDL identifier from a schema field name
+fn identifier(name: &str) -> String {
+    let mut id: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !id.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        id.insert(0, 'f');
+    }
+    id
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
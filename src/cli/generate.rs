@@ -0,0 +1,24 @@
+use clap::Args;
+use std::{fs::File, io::BufWriter, path::PathBuf};
+use trace_recorder_printer::generate::TraceSpec;
+
+/// Write a synthetic recording from a description of the tasks, ISRs, user events,
+/// heap activity and injected faults (TOML or YAML)
+#[derive(Args, Debug, Clone)]
+pub struct GenerateOpts {
+    /// Output streaming data file (psf)
+    #[clap(long, value_name = "out.psf")]
+    pub output: PathBuf,
+
+    /// Description of the recording
+    #[clap(value_parser, value_name = "spec.toml")]
+    pub spec: PathBuf,
+}
+
+pub fn run(opts: &GenerateOpts) -> Result<(), Box<dyn std::error::Error>> {
+    let spec = TraceSpec::load(&opts.spec)?;
+    let mut out = BufWriter::new(File::create(&opts.output)?);
+    let events = spec.generate(&mut out)?;
+    println!("Wrote {events} events to {}", opts.output.display());
+    Ok(())
+}
@@ -0,0 +1,532 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::{fs, path::Path};
+use trace_recorder_parser::streaming::{event::Event, RecorderData};
+use trace_recorder_printer::{
+    analyzer::Analyzer,
+    elf::FirmwareElf,
+    event::TraceEvent,
+    report::{object_name, ContextKind, TraceReport},
+    schema::EventSchema,
+    timeline::Timeline,
+    trace::EventRecord,
+};
+
+const TIMELINE_WIDTH: usize = 1000;
+const LABEL_WIDTH: usize = 180;
+const COLORS: &[&str] = &[
+    "#4e79a7", "#f28e2b", "#e15759", "#76b7b2", "#59a14f", "#edc948", "#b07aa1", "#ff9da7",
+    "#9c755f", "#bab0ac",
+];
+
+/// Collects what the HTML report needs beyond the statistics:
+/// the event listing, and the heap and stack samples over time
+pub struct HtmlCollector<'a> {
+    schema: &'a EventSchema,
+    elf: Option<&'a FirmwareElf>,
+
+    /// Recording time (ticks), core, type name and text of the first `max_events` events
+    events: Vec<(u64, u8, String, String)>,
+    max_events: usize,
+
+    /// Events left out of the listing
+    omitted_events: u64,
+
+    /// Heap usage after each allocation or free
+    heap: Vec<(u64, u32)>,
+
+    /// Stack low marks of each task
+    stacks: BTreeMap<String, Vec<(u64, u32)>>,
+}
+
+impl<'a> HtmlCollector<'a> {
+    pub fn new(schema: &'a EventSchema, elf: Option<&'a FirmwareElf>, max_events: usize) -> Self {
+        Self {
+            schema,
+            elf,
+            events: Vec::new(),
+            max_events,
+            omitted_events: 0,
+            heap: Vec::new(),
+            stacks: BTreeMap::new(),
+        }
+    }
+}
+
+impl Analyzer for HtmlCollector<'_> {
+    fn on_event(&mut self, rd: &RecorderData, record: &EventRecord) {
+        let time = record.trace_time.get_raw();
+        if self.events.len() < self.max_events {
+            let name = self
+                .schema
+                .name(record.event_type)
+                .map(|n| n.to_owned())
+                .unwrap_or_else(|| record.event_type.to_string());
+            self.events
+                .push((time, record.core_id, name, record.event.to_string()));
+        } else {
+            self.omitted_events += 1;
+        }
+
+        match &record.event {
+            TraceEvent::Parsed(Event::MemoryAlloc(ev) | Event::MemoryFree(ev)) => {
+                self.heap.push((time, ev.heap.current));
+            }
+            TraceEvent::Parsed(Event::UnusedStack(ev)) => {
+                self.stacks
+                    .entry(object_name(rd, self.elf, ev.handle))
+                    .or_default()
+                    .push((time, ev.low_mark));
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Write the report as a single HTML file, with inline SVG charts and no external resources
+pub fn write<P: AsRef<Path>>(
+    path: P,
+    title: &str,
+    rd: &RecorderData,
+    elf: Option<&FirmwareElf>,
+    report: &TraceReport,
+    timeline: &Timeline,
+    collector: &HtmlCollector,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let fmt_ticks = |ticks: f64| match report.duration(ticks) {
+        Some(d) => format!("{d:?}"),
+        None => format!("{ticks:.0} ticks"),
+    };
+    let mut h = String::new();
+
+    writeln!(
+        h,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{STYLE}</style>\n</head>\n<body>",
+        esc(title)
+    )?;
+    writeln!(h, "<h1>{}</h1>", esc(title))?;
+
+    // Summary
+    writeln!(h, "<h2>Summary</h2>\n<table>")?;
+    let rows = [
+        (
+            "Kernel",
+            format!("{} {}", rd.header.kernel_port, rd.header.kernel_version),
+        ),
+        ("Cores", report.num_cores.to_string()),
+        (
+            "Timer frequency",
+            rd.timestamp_info.timer_frequency.to_string(),
+        ),
+        ("Total time", fmt_ticks(report.total_time.get_raw() as f64)),
+        ("Total events", report.total_events.to_string()),
+        ("Dropped events", report.dropped_events.to_string()),
+        ("Trace restarts", report.restarts.to_string()),
+    ];
+    for (k, v) in rows.iter() {
+        writeln!(h, "<tr><th>{k}</th><td>{}</td></tr>", esc(v))?;
+    }
+    writeln!(h, "</table>")?;
+
+    // Contexts
+    writeln!(
+        h,
+        "<h2>Contexts</h2>\n<table>\n<tr><th>Symbol</th><th>Type</th><th>Count</th><th>Runtime</th><th>CPU %</th>\
+         <th>Min</th><th>P50</th><th>P90</th><th>P99</th><th>Max</th><th>Stack LM</th></tr>"
+    )?;
+    for (sym, ctx) in report.contexts.iter() {
+        writeln!(
+            h,
+            "<tr><td class=\"l\">{}</td><td class=\"l\">{}</td><td>{}</td><td>{}</td><td>{:.02}</td>\
+             <td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            match ctx.dropped_event_gaps {
+                0 => esc(sym),
+                _ => format!("{} *", esc(sym)),
+            },
+            ctx.kind,
+            ctx.count,
+            fmt_ticks(ctx.total_runtime.get_raw() as f64),
+            ctx.cpu_percent,
+            fmt_ticks(ctx.runtime.min),
+            fmt_ticks(ctx.runtime.p50),
+            fmt_ticks(ctx.runtime.p90),
+            fmt_ticks(ctx.runtime.p99),
+            fmt_ticks(ctx.runtime.max),
+            ctx.stack_low_mark.map(|s| s.to_string()).unwrap_or_default(),
+        )?;
+    }
+    writeln!(h, "</table>")?;
+    if report.contexts.values().any(|c| c.dropped_event_gaps != 0) {
+        writeln!(
+            h,
+            "<p>* Ran while events were dropped, the runtime is unreliable</p>"
+        )?;
+    }
+
+    // Event types
+    writeln!(
+        h,
+        "<h2>Event Types</h2>\n<table>\n<tr><th>Count</th><th>%</th><th>Type</th></tr>"
+    )?;
+    let mut types: Vec<_> = report.event_types.iter().collect();
+    types.sort_by_key(|(_, count)| std::cmp::Reverse(**count));
+    for (name, count) in types {
+        let percentage = 100.0 * (*count as f64 / report.total_events.max(1) as f64);
+        writeln!(
+            h,
+            "<tr><td>{count}</td><td>{percentage:.01}</td><td class=\"l\">{}</td></tr>",
+            esc(name)
+        )?;
+    }
+    writeln!(h, "</table>")?;
+
+    // Objects
+    writeln!(
+        h,
+        "<h2>Objects</h2>\n<table>\n<tr><th>Handle</th><th>Address</th><th>Class</th><th>Symbol</th></tr>"
+    )?;
+    for (handle, entry) in rd.entry_table.entries().iter() {
+        let class = entry.class.map(|c| c.to_string()).unwrap_or_default();
+        let sym = entry
+            .symbol
+            .as_ref()
+            .map(|s| s.to_string())
+            .or_else(|| elf.and_then(|elf| elf.resolve(u32::from(*handle))))
+            .unwrap_or_default();
+        writeln!(
+            h,
+            "<tr><td>{handle}</td><td>0x{handle:08X}</td><td>{}</td><td class=\"l\">{}</td></tr>",
+            esc(&class),
+            esc(&sym)
+        )?;
+    }
+    writeln!(h, "</table>")?;
+
+    // Charts
+    writeln!(h, "<h2>Execution Timeline</h2>")?;
+    h.push_str(&timeline_svg(timeline, &fmt_ticks));
+
+    writeln!(h, "<h2>CPU Usage</h2>")?;
+    h.push_str(&pie_svg(report));
+
+    writeln!(
+        h,
+        "<h2>Execution Time Histograms</h2>\n<div class=\"grid\">"
+    )?;
+    for (idx, row) in timeline.rows.iter().enumerate() {
+        let durations: Vec<f64> = row.intervals.iter().map(|(s, e)| (e - s) as f64).collect();
+        let name = match row.kind {
+            ContextKind::Isr => format!("{} (ISR)", row.name),
+            ContextKind::Task => row.name.clone(),
+        };
+        h.push_str(&histogram_svg(
+            &name,
+            &durations,
+            COLORS[idx % COLORS.len()],
+            &fmt_ticks,
+        ));
+    }
+    writeln!(h, "</div>")?;
+
+    let end = timeline.end.max(1) as f64;
+    if !collector.heap.is_empty() {
+        writeln!(h, "<h2>Heap Usage</h2>")?;
+        let series = vec![("Heap".to_owned(), collector.heap.clone())];
+        h.push_str(&line_svg(&series, end, "bytes", &fmt_ticks));
+    }
+    if !collector.stacks.is_empty() {
+        writeln!(h, "<h2>Stack Low Marks</h2>")?;
+        let series: Vec<_> = collector
+            .stacks
+            .iter()
+            .map(|(task, samples)| (task.clone(), samples.clone()))
+            .collect();
+        h.push_str(&line_svg(&series, end, "words", &fmt_ticks));
+    }
+
+    // Events
+    writeln!(
+        h,
+        "<h2>Events</h2>\n<input id=\"filter\" type=\"search\" placeholder=\"Filter events\" oninput=\"filterEvents()\">\n\
+         <table id=\"events\">\n<tr><th>Time</th><th>Core</th><th class=\"l\">Type</th><th class=\"l\">Event</th></tr>"
+    )?;
+    for (time, core, name, text) in collector.events.iter() {
+        writeln!(
+            h,
+            "<tr><td>{}</td><td>{core}</td><td class=\"l\">{}</td><td class=\"l\">{}</td></tr>",
+            fmt_ticks(*time as f64),
+            esc(name),
+            esc(text)
+        )?;
+    }
+    writeln!(h, "</table>")?;
+    if collector.omitted_events != 0 {
+        writeln!(
+            h,
+            "<p>The first {} events are listed, {} more were left out (see --html-max-events)</p>",
+            collector.events.len(),
+            collector.omitted_events
+        )?;
+    }
+    writeln!(h, "<script>{SCRIPT}</script>\n</body>\n</html>")?;
+
+    fs::write(path, h)?;
+    Ok(())
+}
+
+fn timeline_svg(timeline: &Timeline, fmt_ticks: &dyn Fn(f64) -> String) -> String {
+    const ROW_HEIGHT: usize = 20;
+    let width = LABEL_WIDTH + TIMELINE_WIDTH;
+    let height = ROW_HEIGHT * timeline.rows.len() + 30;
+    let end = timeline.end.max(1) as f64;
+    let mut s = String::new();
+    let _ = writeln!(
+        s,
+        "<svg width=\"{width}\" height=\"{height}\" xmlns=\"http://www.w3.org/2000/svg\">"
+    );
+    for (idx, row) in timeline.rows.iter().enumerate() {
+        let y = idx * ROW_HEIGHT;
+        let color = COLORS[idx % COLORS.len()];
+        let _ = writeln!(
+            s,
+            "<text x=\"0\" y=\"{}\">{}</text>",
+            y + 14,
+            esc(&row.name)
+        );
+        // Rasterize to the pixel columns so large recordings stay small
+        let occupancy = row.occupancy(0.0, end, TIMELINE_WIDTH);
+        let mut col = 0;
+        while col < occupancy.len() {
+            if occupancy[col] <= 0.0 {
+                col += 1;
+                continue;
+            }
+            let start = col;
+            while col < occupancy.len() && occupancy[col] > 0.0 {
+                col += 1;
+            }
+            let _ = writeln!(
+                s,
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{color}\"/>",
+                LABEL_WIDTH + start,
+                y + 3,
+                col - start,
+                ROW_HEIGHT - 6
+            );
+        }
+    }
+    let axis_y = ROW_HEIGHT * timeline.rows.len() + 15;
+    let _ = writeln!(
+        s,
+        "<text x=\"{LABEL_WIDTH}\" y=\"{axis_y}\">0</text>\
+         <text x=\"{width}\" y=\"{axis_y}\" text-anchor=\"end\">{}</text>\n</svg>",
+        esc(&fmt_ticks(end))
+    );
+    s
+}
+
+/// Share of the CPU time of all cores, the per context CPU % is summed across cores
+fn pie_svg(report: &TraceReport) -> String {
+    const R: f64 = 100.0;
+    let num_cores = f64::from(report.num_cores.max(1));
+    let mut slices: Vec<(&str, f64)> = report
+        .contexts
+        .iter()
+        .filter(|(_, ctx)| ctx.cpu_percent > 0.0)
+        .map(|(sym, ctx)| (sym.as_str(), ctx.cpu_percent / num_cores))
+        .collect();
+    slices.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let total: f64 = slices.iter().map(|(_, p)| p).sum();
+    if total < 99.99 {
+        slices.push(("Untracked", 100.0 - total));
+    }
+
+    let height = (2.0 * R + 20.0).max(20.0 * slices.len() as f64 + 20.0);
+    let mut s = String::new();
+    let _ = writeln!(
+        s,
+        "<svg width=\"600\" height=\"{height}\" xmlns=\"http://www.w3.org/2000/svg\">"
+    );
+    let (cx, cy) = (R + 10.0, R + 10.0);
+    let mut angle: f64 = -std::f64::consts::FRAC_PI_2;
+    for (idx, (name, percent)) in slices.iter().enumerate() {
+        let color = COLORS[idx % COLORS.len()];
+        let sweep = percent / 100.0 * std::f64::consts::TAU;
+        if sweep >= std::f64::consts::TAU - 1e-6 {
+            let _ = writeln!(
+                s,
+                "<circle cx=\"{cx}\" cy=\"{cy}\" r=\"{R}\" fill=\"{color}\"/>"
+            );
+        } else {
+            let (x0, y0) = (cx + R * angle.cos(), cy + R * angle.sin());
+            let (x1, y1) = (
+                cx + R * (angle + sweep).cos(),
+                cy + R * (angle + sweep).sin(),
+            );
+            let large = if sweep > std::f64::consts::PI { 1 } else { 0 };
+            let _ = writeln!(
+                s,
+                "<path d=\"M{cx},{cy} L{x0:.2},{y0:.2} A{R},{R} 0 {large} 1 {x1:.2},{y1:.2} Z\" fill=\"{color}\"><title>{}: {percent:.02}%</title></path>",
+                esc(name)
+            );
+        }
+        angle += sweep;
+        let ly = 20.0 + idx as f64 * 20.0;
+        let _ = writeln!(
+            s,
+            "<rect x=\"{}\" y=\"{}\" width=\"12\" height=\"12\" fill=\"{color}\"/><text x=\"{}\" y=\"{ly}\">{} {percent:.02}%</text>",
+            2.0 * R + 40.0,
+            ly - 11.0,
+            2.0 * R + 58.0,
+            esc(name)
+        );
+    }
+    s.push_str("</svg>\n");
+    s
+}
+
+fn histogram_svg(
+    name: &str,
+    samples: &[f64],
+    color: &str,
+    fmt_ticks: &dyn Fn(f64) -> String,
+) -> String {
+    const BINS: usize = 20;
+    const W: f64 = 300.0;
+    const H: f64 = 120.0;
+    let min = samples.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = samples.iter().copied().fold(0.0, f64::max);
+    let mut bins = [0u64; BINS];
+    let span = (max - min).max(1.0);
+    for v in samples.iter() {
+        let b = (((v - min) / span) * BINS as f64) as usize;
+        bins[b.min(BINS - 1)] += 1;
+    }
+    let peak = bins.iter().copied().max().unwrap_or(0).max(1) as f64;
+
+    let mut s = String::new();
+    let _ = writeln!(
+        s,
+        "<svg width=\"{W}\" height=\"{}\" xmlns=\"http://www.w3.org/2000/svg\"><text x=\"0\" y=\"14\">{} ({} instances)</text>",
+        H + 40.0,
+        esc(name),
+        samples.len()
+    );
+    let bar_w = W / BINS as f64;
+    for (idx, count) in bins.iter().enumerate() {
+        let bh = *count as f64 / peak * H;
+        let _ = writeln!(
+            s,
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{bh:.1}\" fill=\"{color}\"><title>{count}</title></rect>",
+            idx as f64 * bar_w,
+            20.0 + H - bh,
+            bar_w - 1.0
+        );
+    }
+    if !samples.is_empty() {
+        let _ = writeln!(
+            s,
+            "<text x=\"0\" y=\"{y}\">{}</text><text x=\"{W}\" y=\"{y}\" text-anchor=\"end\">{}</text>",
+            esc(&fmt_ticks(min)),
+            esc(&fmt_ticks(max)),
+            y = H + 36.0
+        );
+    }
+    s.push_str("</svg>\n");
+    s
+}
+
+/// Step curves over the recording time
+fn line_svg(
+    series: &[(String, Vec<(u64, u32)>)],
+    end: f64,
+    unit: &str,
+    fmt_ticks: &dyn Fn(f64) -> String,
+) -> String {
+    const H: f64 = 200.0;
+    let w = TIMELINE_WIDTH as f64;
+    let peak = series
+        .iter()
+        .flat_map(|(_, samples)| samples.iter().map(|(_, v)| *v))
+        .max()
+        .unwrap_or(0)
+        .max(1) as f64;
+
+    let mut s = String::new();
+    let _ = writeln!(
+        s,
+        "<svg width=\"{}\" height=\"{}\" xmlns=\"http://www.w3.org/2000/svg\">\
+         <line x1=\"{LABEL_WIDTH}\" y1=\"{H}\" x2=\"{}\" y2=\"{H}\" stroke=\"#888\"/>\
+         <text x=\"{}\" y=\"14\" text-anchor=\"end\">{peak} {unit}</text>\
+         <text x=\"{}\" y=\"{H}\" text-anchor=\"end\">0</text>",
+        LABEL_WIDTH as f64 + w,
+        H + 20.0 + 20.0 * series.len() as f64,
+        LABEL_WIDTH as f64 + w,
+        LABEL_WIDTH - 4,
+        LABEL_WIDTH - 4,
+    );
+    for (idx, (name, samples)) in series.iter().enumerate() {
+        let color = COLORS[idx % COLORS.len()];
+        let mut points = String::new();
+        let mut prev_y = None;
+        for (t, v) in samples.iter() {
+            let x = LABEL_WIDTH as f64 + *t as f64 / end * w;
+            let y = H - *v as f64 / peak * (H - 20.0);
+            if let Some(prev_y) = prev_y {
+                let _ = write!(points, "{x:.1},{prev_y:.1} ");
+            }
+            let _ = write!(points, "{x:.1},{y:.1} ");
+            prev_y = Some(y);
+        }
+        // Hold the last value until the end of the recording
+        if let Some(prev_y) = prev_y {
+            let _ = write!(points, "{:.1},{prev_y:.1}", LABEL_WIDTH as f64 + w);
+        }
+        let ly = H + 20.0 + 20.0 * idx as f64;
+        let _ = writeln!(
+            s,
+            "<polyline points=\"{points}\" fill=\"none\" stroke=\"{color}\" stroke-width=\"2\"/>\
+             <rect x=\"{LABEL_WIDTH}\" y=\"{}\" width=\"12\" height=\"12\" fill=\"{color}\"/>\
+             <text x=\"{}\" y=\"{ly}\">{}</text>",
+            ly - 11.0,
+            LABEL_WIDTH + 18,
+            esc(name)
+        );
+    }
+    let _ = writeln!(
+        s,
+        "<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>\n</svg>",
+        LABEL_WIDTH as f64 + w,
+        H + 14.0,
+        esc(&fmt_ticks(end))
+    );
+    s
+}
+
+fn esc(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #ccc; padding: 2px 8px; text-align: right; }
+th { background: #f0f0f0; }
+td.l, th.l { text-align: left; }
+svg { font-size: 12px; margin-bottom: 1em; }
+.grid { display: flex; flex-wrap: wrap; gap: 1em; }
+#filter { margin-bottom: 0.5em; width: 30em; }
+";
+
+const SCRIPT: &str = "
+function filterEvents() {
+  const needle = document.getElementById('filter').value.toLowerCase();
+  const rows = document.getElementById('events').rows;
+  for (let i = 1; i < rows.length; i++) {
+    rows[i].style.display = rows[i].textContent.toLowerCase().includes(needle) ? '' : 'none';
+  }
+}
+";
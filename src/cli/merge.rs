@@ -0,0 +1,552 @@
+use crate::cli::{DecodeOpts, TimePoint};
+use clap::Args;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{sync_channel, Receiver, SyncSender},
+    thread,
+};
+use trace_recorder_parser::{
+    streaming::{
+        event::{EventId, EventType},
+        RecorderData,
+    },
+    time::Frequency,
+    types::{Endianness, KernelPortIdentity, ObjectHandle},
+};
+use trace_recorder_printer::{
+    analyzer::Analyzer,
+    elf::FirmwareElf,
+    event::{event_object_handle, TraceEvent},
+    psf::{EventLayout, RawEvent, SessionHeader, SYSTEM_HEAP_SYMBOL},
+    schema::EventSchema,
+    trace::{EventRecord, TraceReader, TraceSummary},
+};
+
+/// Merge captures into one recording, either as consecutive sessions, e.g. rotated capture
+/// files, or as parallel nodes recorded at the same time, e.g. two boards
+#[derive(Args, Debug, Clone)]
+pub struct MergeOpts {
+    #[clap(flatten)]
+    pub decode: DecodeOpts,
+
+    /// Merge the captures as parallel nodes, each on its own cores, with its object handles
+    /// renumbered and its object names prefixed with the node, e.g. `node1/Control`
+    #[clap(long)]
+    pub parallel: bool,
+
+    /// Start of each capture on the merged timeline, in ticks or with a unit, comma separated
+    /// in the order of the captures, e.g. `0,1500ms`. By default a consecutive capture starts
+    /// where the previous one ends, and parallel captures start together.
+    #[clap(long, value_name = "time,...", value_delimiter = ',')]
+    pub offset: Vec<TimePoint>,
+
+    /// Output streaming data file (psf)
+    #[clap(long, value_name = "out.psf")]
+    pub output: PathBuf,
+
+    /// Paths to streaming data files (psf), in order
+    #[clap(value_parser, required = true, num_args = 2..)]
+    pub paths: Vec<PathBuf>,
+}
+
+/// Handles of the parallel nodes are renumbered from here, in steps of `HANDLE_STEP`
+const FIRST_HANDLE: u32 = 0x1000_0000;
+const HANDLE_STEP: u32 = 0x10;
+
+/// Event count bits left by the core ID on multicore recordings
+const SMP_COUNT_MASK: u16 = 0x0F_FF;
+const MAX_CORES: u32 = 16;
+
+/// First pass over a capture
+struct Survey {
+    custom_printf_event_id: Option<u16>,
+    /// Header of the first session, with the objects of the later sessions and the names
+    /// given by events added
+    header: Option<SessionHeader>,
+    /// Symbols of the first session header, as recorded
+    first_symbols: Vec<(u32, String)>,
+    /// Object handles of the capture
+    handles: BTreeSet<u32>,
+    num_cores: u32,
+    error: Option<Box<dyn std::error::Error>>,
+}
+
+impl Analyzer for Survey {
+    fn on_session_header(&mut self, data: &[u8]) {
+        let session = match SessionHeader::parse(data) {
+            Ok(session) => session,
+            Err(e) => {
+                self.error.get_or_insert(e.into());
+                return;
+            }
+        };
+        let header = self.header.get_or_insert_with(|| {
+            self.first_symbols = session
+                .entries
+                .iter()
+                .map(|e| (e.address, e.symbol()))
+                .filter(|(_, s)| !s.is_empty())
+                .collect();
+            session.clone()
+        });
+        for e in session.entries.into_iter() {
+            if !header.entries.iter().any(|h| h.address == e.address) {
+                header.entries.push(e);
+            }
+        }
+    }
+
+    fn on_header(&mut self, rd: &RecorderData) {
+        self.num_cores = self.num_cores.max(rd.header.num_cores);
+    }
+
+    fn on_event(&mut self, rd: &RecorderData, record: &EventRecord) {
+        if let TraceEvent::Parsed(event) = &record.event {
+            if let Some(handle) = event_object_handle(event).filter(|h| *h != ObjectHandle::NO_TASK)
+            {
+                self.handles.insert(u32::from(handle));
+            }
+        }
+        let raw = match RawEvent::parse(&record.data, rd.header.endianness) {
+            Ok(raw) => raw,
+            Err(e) => {
+                self.error.get_or_insert(e.into());
+                return;
+            }
+        };
+        match EventLayout::of(record.event_type, &raw, self.custom_printf_event_id) {
+            EventLayout::Name { string } => {
+                if let (Some(handle), Some(header)) = (raw.param(0), self.header.as_mut()) {
+                    header.entry(handle).symbol = raw.string_at(string * 4).into_bytes();
+                }
+            }
+            EventLayout::UserEvent { .. } | EventLayout::CustomPrintf { .. } => {
+                self.handles.extend(raw.param(0));
+            }
+            EventLayout::FixedUserEvent => {
+                self.handles.extend(raw.param(0));
+                self.handles.extend(raw.param(1));
+            }
+            EventLayout::MemoryBlock | EventLayout::Other { .. } => (),
+        }
+    }
+
+    fn on_restart(&mut self, rd: &RecorderData) {
+        self.num_cores = self.num_cores.max(rd.header.num_cores);
+    }
+}
+
+/// A capture and where it goes in the merged recording
+struct Input {
+    path: PathBuf,
+    header: SessionHeader,
+    /// Symbols at the start of the capture
+    first_symbols: Vec<(u32, String)>,
+    /// Objects named differently by an earlier capture, renamed when the capture starts
+    renames: Vec<(u32, String)>,
+    endianness: Endianness,
+    frequency: Frequency,
+    kernel_port: KernelPortIdentity,
+    num_cores: u32,
+    total_time: u64,
+    /// Start on the merged timeline, if set by the options
+    offset: Option<u64>,
+    /// Start on the merged timeline
+    start: u64,
+    /// Core of the merged recording of the first core of the capture
+    first_core: u32,
+    /// Renumbered object handles, empty for consecutive captures
+    handles: HashMap<u32, u32>,
+    /// Prefix of the object names, empty for consecutive captures
+    prefix: String,
+}
+
+impl Input {
+    fn survey(
+        opts: &MergeOpts,
+        index: usize,
+        elf: Option<&FirmwareElf>,
+        schema: &EventSchema,
+    ) -> Result<(Self, BTreeSet<u32>), Box<dyn std::error::Error>> {
+        let path = &opts.paths[index];
+        let mut reader = reader(opts, path, elf, schema)?;
+        let mut survey = Survey {
+            custom_printf_event_id: opts.decode.custom_printf_event_id,
+            header: None,
+            first_symbols: Vec::new(),
+            handles: BTreeSet::new(),
+            num_cores: 1,
+            error: None,
+        };
+        let summary = reader.run(&mut [&mut survey])?;
+        if let Some(e) = survey.error.take() {
+            return Err(e);
+        }
+        let rd = reader.recorder_data();
+        let offset = opts.offset.get(index).map(|p| p.ticks(rd)).transpose()?;
+        let input = Input {
+            path: path.clone(),
+            header: survey.header.ok_or("Missing PSF header")?,
+            first_symbols: survey.first_symbols,
+            renames: Vec::new(),
+            endianness: rd.header.endianness,
+            frequency: rd.timestamp_info.timer_frequency,
+            kernel_port: rd.header.kernel_port,
+            num_cores: survey.num_cores,
+            total_time: summary.total_time.get_raw(),
+            offset,
+            start: 0,
+            first_core: 0,
+            handles: HashMap::new(),
+            prefix: String::new(),
+        };
+        Ok((input, survey.handles))
+    }
+
+    /// Name of an object in the merged recording, format strings aren't prefixed
+    fn symbol(&self, symbol: &str) -> String {
+        if self.prefix.is_empty() || symbol.is_empty() || symbol.contains('%') {
+            symbol.to_owned()
+        } else {
+            format!("{}{symbol}", self.prefix)
+        }
+    }
+}
+
+/// Event of a capture, ready to be written to the merged recording
+struct Item {
+    time: u64,
+    core: u8,
+    dropped_events: u64,
+    raw: RawEvent,
+}
+
+/// Second pass over a capture, sends its events to be merged
+struct Feed<'a> {
+    input: &'a Input,
+    custom_printf_event_id: Option<u16>,
+    tx: SyncSender<Item>,
+    error: Option<std::io::Error>,
+}
+
+impl Feed<'_> {
+    fn item(&self, rd: &RecorderData, record: &EventRecord) -> std::io::Result<Item> {
+        let mut raw = RawEvent::parse(&record.data, rd.header.endianness)?;
+        if !self.input.handles.is_empty() {
+            let layout = EventLayout::of(record.event_type, &raw, self.custom_printf_event_id);
+            for word in layout.handle_words(&raw, &rd.entry_table) {
+                if let Some(h) = raw.param(word).and_then(|v| self.input.handles.get(&v)) {
+                    raw.set_param(word, *h);
+                }
+            }
+            if let EventLayout::Name { string } = layout {
+                let symbol = self.input.symbol(&raw.string_at(string * 4));
+                raw.set_string_at(string * 4, &symbol);
+                raw.num_params = raw.body.len() / 4;
+            }
+        }
+        Ok(Item {
+            time: self.input.start + record.trace_time.get_raw(),
+            core: self.input.first_core as u8 + record.core_id,
+            dropped_events: record.dropped_events.unwrap_or(0),
+            raw,
+        })
+    }
+}
+
+impl Analyzer for Feed<'_> {
+    fn on_event(&mut self, rd: &RecorderData, record: &EventRecord) {
+        if self.error.is_some() {
+            return;
+        }
+        match self.item(rd, record) {
+            // The merge stopped early when the receiver is gone
+            Ok(item) => _ = self.tx.send(item),
+            Err(e) => self.error = Some(e),
+        }
+    }
+}
+
+/// Writes the merged events, with continuous event counts
+struct Writer<'a, W: Write> {
+    out: &'a mut W,
+    num_cores: u32,
+    count: u16,
+    last_time: u64,
+    events: u64,
+}
+
+impl<W: Write> Writer<'_, W> {
+    fn write(
+        &mut self,
+        time: u64,
+        core: u8,
+        dropped_events: u64,
+        mut raw: RawEvent,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let gap = time.checked_sub(self.last_time).ok_or_else(|| {
+            format!(
+                "Time went backwards from {} to {time}, the inputs must be in time order",
+                self.last_time
+            )
+        })?;
+        if gap > u64::from(u32::MAX) {
+            return Err(format!(
+                "No events for more than one timer period before {time}, the gap doesn't fit \
+                 in the merged recording"
+            )
+            .into());
+        }
+        self.last_time = time;
+        // Events dropped by the capture stay visible as a gap in the counts, the counter
+        // wraps around like the recorder's own
+        let dropped = u16::try_from(dropped_events).unwrap_or(u16::MAX);
+        self.count = self.count.wrapping_add(1).wrapping_add(dropped);
+        raw.timestamp = time as u32;
+        raw.count = if self.num_cores > 1 {
+            (u16::from(core) << 12) | (self.count & SMP_COUNT_MASK)
+        } else {
+            self.count
+        };
+        raw.write(self.out)?;
+        self.events += 1;
+        Ok(())
+    }
+}
+
+fn reader<'a>(
+    opts: &MergeOpts,
+    path: &Path,
+    elf: Option<&'a FirmwareElf>,
+    schema: &'a EventSchema,
+) -> Result<TraceReader<'a, BufReader<File>>, Box<dyn std::error::Error>> {
+    let f = File::open(path)?;
+    let mut reader = TraceReader::new(BufReader::new(f))?;
+    if let Some(elf) = elf {
+        reader.set_elf(elf);
+    }
+    reader.set_event_schema(schema);
+    reader.set_capture_data(true);
+    if let Some(custom_printf_event_id) = opts.decode.custom_printf_event_id {
+        reader.set_custom_printf_event_id(custom_printf_event_id);
+    }
+    reader.set_resync(opts.decode.resync);
+    Ok(reader)
+}
+
+/// Places the captures on the merged timeline and builds the merged header
+fn plan(
+    opts: &MergeOpts,
+    inputs: &mut [Input],
+    handles: Vec<BTreeSet<u32>>,
+) -> Result<SessionHeader, Box<dyn std::error::Error>> {
+    let mut end = 0;
+    let mut next_core = 0;
+    let mut next_handle = FIRST_HANDLE;
+    for (i, (input, handles)) in inputs.iter_mut().zip(handles).enumerate() {
+        input.start = match (input.offset, opts.parallel) {
+            (Some(offset), _) => offset,
+            (None, true) => 0,
+            (None, false) => end,
+        };
+        end = end.max(input.start + input.total_time);
+        if opts.parallel {
+            input.first_core = next_core;
+            next_core += input.num_cores;
+            input.prefix = format!("node{i}/");
+            let entries = input.header.entries.iter().map(|e| e.address);
+            for h in handles.into_iter().chain(entries) {
+                input.handles.entry(h).or_insert_with(|| {
+                    next_handle += HANDLE_STEP;
+                    next_handle - HANDLE_STEP
+                });
+            }
+        }
+    }
+
+    let mut header = inputs[0].header.clone();
+    header.entries.clear();
+    header.state_count = inputs
+        .iter()
+        .map(|i| i.header.state_count)
+        .max()
+        .unwrap_or(0);
+    let mut heap_kept = false;
+    for input in inputs.iter() {
+        for e in input.header.entries.iter() {
+            let address = input.handles.get(&e.address).copied().unwrap_or(e.address);
+            if header.entries.iter().any(|h| h.address == address) {
+                continue;
+            }
+            let mut e = e.clone();
+            e.address = address;
+            e.states.resize(header.state_count, 0);
+            // One system heap, the memory events of all nodes count towards it
+            let symbol = e.symbol();
+            if symbol == SYSTEM_HEAP_SYMBOL && !heap_kept {
+                heap_kept = true;
+            } else {
+                e.symbol = input.symbol(&symbol).into_bytes();
+            }
+            header.entries.push(e);
+        }
+    }
+    if !opts.parallel {
+        // Handles reused by a later capture for other objects
+        let mut names: HashMap<u32, String> = HashMap::new();
+        for input in inputs.iter_mut() {
+            for (address, symbol) in input.first_symbols.iter() {
+                if names.get(address).is_some_and(|n| n != symbol) {
+                    input.renames.push((*address, symbol.clone()));
+                }
+            }
+            for e in input.header.entries.iter() {
+                names.insert(e.address, e.symbol());
+            }
+        }
+    }
+
+    let longest = header.entries.iter().map(|e| e.symbol.len()).max();
+    header.symbol_size = inputs
+        .iter()
+        .map(|i| i.header.symbol_size)
+        .chain(longest.map(|l| (l + 1).div_ceil(4) * 4))
+        .max()
+        .unwrap_or(0);
+    let num_cores = if opts.parallel {
+        next_core
+    } else {
+        inputs.iter().map(|i| i.num_cores).max().unwrap_or(1)
+    };
+    if num_cores > MAX_CORES {
+        return Err(format!("The captures have {num_cores} cores, at most {MAX_CORES} fit").into());
+    }
+    header.set_num_cores(num_cores);
+    Ok(header)
+}
+
+pub fn run(opts: &MergeOpts) -> Result<(), Box<dyn std::error::Error>> {
+    if opts.offset.len() > opts.paths.len() {
+        return Err("More offsets than captures".into());
+    }
+    let elf = opts.decode.load_elf()?;
+    let schema = opts.decode.load_schema()?;
+
+    let mut inputs = Vec::new();
+    let mut handles = Vec::new();
+    for i in 0..opts.paths.len() {
+        let (input, h) = Input::survey(opts, i, elf.as_ref(), &schema)?;
+        let first = inputs.first().unwrap_or(&input);
+        let mismatch = if input.endianness != first.endianness {
+            Some("byte order")
+        } else if input.frequency != first.frequency {
+            Some("timer frequency")
+        } else if input.kernel_port != first.kernel_port {
+            Some("kernel port")
+        } else {
+            None
+        };
+        if let Some(what) = mismatch {
+            return Err(format!(
+                "{} has a different {what} than {}",
+                input.path.display(),
+                first.path.display()
+            )
+            .into());
+        }
+        inputs.push(input);
+        handles.push(h);
+    }
+    let mut header = plan(opts, &mut inputs, handles)?;
+    let num_cores = inputs
+        .iter()
+        .map(|i| i.first_core + i.num_cores)
+        .max()
+        .unwrap_or(1);
+
+    let mut out = BufWriter::new(File::create(&opts.output)?);
+    let mut events = 0;
+    thread::scope(|s| -> Result<(), Box<dyn std::error::Error>> {
+        let mut feeds = Vec::new();
+        let mut receivers: Vec<Receiver<Item>> = Vec::new();
+        for input in inputs.iter() {
+            let (tx, rx) = sync_channel(4096);
+            receivers.push(rx);
+            let (elf, schema) = (elf.as_ref(), &schema);
+            feeds.push(s.spawn(move || {
+                let mut feed = Feed {
+                    input,
+                    custom_printf_event_id: opts.decode.custom_printf_event_id,
+                    tx,
+                    error: None,
+                };
+                let result = reader(opts, &input.path, elf, schema)
+                    .and_then(|mut r| Ok(r.run(&mut [&mut feed])?))
+                    .map_err(|e| e.to_string());
+                match feed.error {
+                    Some(e) => Err(e.to_string()),
+                    None => result.map(|_: TraceSummary| ()),
+                }
+            }));
+        }
+
+        // Merge by time, the earlier capture first on ties
+        let mut heads: Vec<Option<Item>> = receivers.iter().map(|r| r.recv().ok()).collect();
+        let first_time = heads.iter().flatten().map(|i| i.time).min().unwrap_or(0);
+        header.set_free_running_timer((first_time >> 32) as u32);
+        header.write(&mut out)?;
+        let mut started = vec![false; inputs.len()];
+
+        let mut writer = Writer {
+            out: &mut out,
+            num_cores,
+            count: 0,
+            last_time: first_time,
+            events: 0,
+        };
+        while let Some(next) = heads
+            .iter()
+            .enumerate()
+            .filter_map(|(i, h)| h.as_ref().map(|h| (h.time, i)))
+            .min()
+            .map(|(_, i)| i)
+        {
+            let item = heads[next].take().unwrap();
+            heads[next] = receivers[next].recv().ok();
+            if !started[next] {
+                started[next] = true;
+                for (handle, symbol) in inputs[next].renames.iter() {
+                    let mut raw = RawEvent {
+                        endianness: header.endianness,
+                        id: EventId::from(EventType::ObjectName).0,
+                        num_params: 0,
+                        count: 0,
+                        timestamp: 0,
+                        body: vec![0; 4],
+                    };
+                    raw.set_param(0, *handle);
+                    raw.set_string_at(4, symbol);
+                    raw.num_params = raw.body.len() / 4;
+                    writer.write(item.time, item.core, 0, raw)?;
+                }
+            }
+            writer.write(item.time, item.core, item.dropped_events, item.raw)?;
+        }
+        events = writer.events;
+        drop(receivers);
+        for feed in feeds {
+            feed.join().map_err(|_| "Reading a capture failed")??;
+        }
+        Ok(())
+    })?;
+    out.flush()?;
+    println!(
+        "Wrote {events} events from {} captures to {}",
+        inputs.len(),
+        opts.output.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,251 @@
+use crate::cli::{DecodeOpts, TimeRange};
+use clap::{ArgGroup, Args};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use trace_recorder_parser::{streaming::RecorderData, types::Heap};
+use trace_recorder_printer::{
+    analyzer::Analyzer,
+    psf::SessionHeader,
+    trace::{EventRecord, TraceReader},
+};
+
+/// Write a time range, a range of events, or each session of a capture to a new recording.
+/// The original header, timestamp info and symbol table are kept.
+#[derive(Args, Debug, Clone)]
+#[clap(group(ArgGroup::new("cut").required(true).args(["time", "events", "sessions"])))]
+pub struct SplitOpts {
+    #[clap(flatten)]
+    pub decode: DecodeOpts,
+
+    /// Keep the events in this time range, in ticks or with a unit, e.g. `2s..4s` or `..500ms`
+    #[clap(long, value_name = "start..end")]
+    pub time: Option<TimeRange>,
+
+    /// Keep the events in this range, numbered from 0 in recording order, e.g. `1000..5000`
+    #[clap(long, value_name = "start..end")]
+    pub events: Option<EventRange>,
+
+    /// Write each session, split at the trace restarts, to its own file named
+    /// after the output with the session number, e.g. `out-0.psf`
+    #[clap(long)]
+    pub sessions: bool,
+
+    /// Output streaming data file (psf)
+    #[clap(long, value_name = "out.psf")]
+    pub output: PathBuf,
+
+    /// Path to streaming data file (psf)
+    #[clap(value_parser)]
+    pub path: PathBuf,
+}
+
+/// A `start..end` range of event numbers, either end can be left open
+#[derive(Copy, Clone, Debug)]
+pub struct EventRange {
+    pub start: Option<u64>,
+    pub end: Option<u64>,
+}
+
+impl FromStr for EventRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once("..")
+            .ok_or_else(|| format!("invalid event range '{s}', expected start..end"))?;
+        let number = |n: &str| {
+            let n = n.trim();
+            if n.is_empty() {
+                Ok(None)
+            } else {
+                n.parse()
+                    .map(Some)
+                    .map_err(|_| format!("invalid event number '{n}'"))
+            }
+        };
+        Ok(Self {
+            start: number(start)?,
+            end: number(end)?,
+        })
+    }
+}
+
+/// Writes the selected events as recorded, after the header of their session
+struct Splitter<'a> {
+    opts: &'a SplitOpts,
+
+    /// Time range in ticks since the start of the recording
+    time: Option<(u64, u64)>,
+    /// Number of the next event
+    event_index: u64,
+
+    /// Session number and header as recorded
+    session: usize,
+    header: Vec<u8>,
+    /// Whether the header of the session was written to the current file
+    header_written: bool,
+    /// Heap counters before the current event
+    heap: Heap,
+
+    out: Option<BufWriter<File>>,
+    /// Files written and their number of events
+    files: Vec<(PathBuf, u64)>,
+    /// First error, reported after the reading is done
+    error: Option<Box<dyn std::error::Error>>,
+}
+
+impl Splitter<'_> {
+    fn selected(&self, record: &EventRecord) -> bool {
+        let index = self.event_index;
+        if let Some((start, end)) = self.time {
+            let t = record.trace_time.get_raw();
+            t >= start && t < end
+        } else if let Some(range) = self.opts.events {
+            index >= range.start.unwrap_or(0) && range.end.map(|e| index < e).unwrap_or(true)
+        } else {
+            true
+        }
+    }
+
+    fn write(
+        &mut self,
+        rd: &RecorderData,
+        record: &EventRecord,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.out.is_none() {
+            let path = if self.opts.sessions {
+                session_path(&self.opts.output, self.session)
+            } else {
+                self.opts.output.clone()
+            };
+            self.out = Some(BufWriter::new(File::create(&path)?));
+            self.files.push((path, 0));
+            self.header_written = false;
+        }
+        let out = self.out.as_mut().unwrap();
+        if !self.header_written {
+            // Objects named and memory allocated earlier in the session still apply
+            let mut header = SessionHeader::parse(&self.header)?;
+            header.update_symbols(&rd.entry_table);
+            header.update_heap(&self.heap);
+            header.write(out)?;
+            self.header_written = true;
+        }
+        out.write_all(&record.data)?;
+        if let Some((_, count)) = self.files.last_mut() {
+            *count += 1;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        if let Some(mut out) = self.out.take() {
+            if let Err(e) = out.flush() {
+                self.error.get_or_insert(e.into());
+            }
+        }
+    }
+}
+
+impl Analyzer for Splitter<'_> {
+    fn on_session_header(&mut self, data: &[u8]) {
+        self.header = data.to_vec();
+        self.header_written = false;
+    }
+
+    fn on_header(&mut self, rd: &RecorderData) {
+        self.heap = *rd.system_heap();
+        if let Some(range) = self.opts.time {
+            match range.ticks(rd, u64::MAX) {
+                Ok(ticks) => self.time = Some(ticks),
+                Err(e) => {
+                    self.error.get_or_insert(e.into());
+                }
+            }
+        }
+    }
+
+    fn on_event(&mut self, rd: &RecorderData, record: &EventRecord) {
+        if self.error.is_none() && self.selected(record) {
+            if let Err(e) = self.write(rd, record) {
+                self.error = Some(e);
+            }
+        }
+        self.event_index += 1;
+        self.heap = *rd.system_heap();
+    }
+
+    fn on_restart(&mut self, rd: &RecorderData) {
+        self.heap = *rd.system_heap();
+        if self.opts.sessions {
+            self.close();
+            self.session += 1;
+        }
+    }
+
+    fn finish(
+        &mut self,
+        _rd: &RecorderData,
+        _summary: &trace_recorder_printer::trace::TraceSummary,
+    ) {
+        self.close();
+    }
+}
+
+/// Output path of a session, `out.psf` becomes `out-<session>.psf`
+fn session_path(output: &Path, session: usize) -> PathBuf {
+    let stem = output
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match output.extension() {
+        Some(ext) => format!("{stem}-{session}.{}", ext.to_string_lossy()),
+        None => format!("{stem}-{session}"),
+    };
+    output.with_file_name(name)
+}
+
+pub fn run(opts: &SplitOpts) -> Result<(), Box<dyn std::error::Error>> {
+    let elf = opts.decode.load_elf()?;
+    let schema = opts.decode.load_schema()?;
+
+    let f = File::open(&opts.path)?;
+    let mut reader = TraceReader::new(BufReader::new(f))?;
+    if let Some(elf) = &elf {
+        reader.set_elf(elf);
+    }
+    reader.set_event_schema(&schema);
+    reader.set_capture_data(true);
+    if let Some(custom_printf_event_id) = opts.decode.custom_printf_event_id {
+        reader.set_custom_printf_event_id(custom_printf_event_id);
+    }
+    reader.set_resync(opts.decode.resync);
+
+    let mut splitter = Splitter {
+        opts,
+        time: None,
+        event_index: 0,
+        session: 0,
+        header: Vec::new(),
+        header_written: false,
+        heap: Heap::default(),
+        out: None,
+        files: Vec::new(),
+        error: None,
+    };
+    reader.run(&mut [&mut splitter])?;
+    if let Some(e) = splitter.error.take() {
+        return Err(e);
+    }
+    if splitter.files.is_empty() {
+        return Err("No events in the range".into());
+    }
+    for (path, count) in splitter.files.iter() {
+        println!("Wrote {count} events to {}", path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,365 @@
+use rusqlite::{params, Connection};
+use std::{fs, path::Path};
+use trace_recorder_parser::streaming::{
+    entry_table::EntryTable,
+    event::{Event, EventId},
+    RecorderData,
+};
+use trace_recorder_printer::{
+    analyzer::{Analyzer, ContextInterval},
+    elf::FirmwareElf,
+    event::{event_object_handle, ContextHandle, TraceEvent},
+    report::object_name,
+    schema::EventSchema,
+    time::TrcTimeExt,
+    trace::{EventRecord, TraceSummary},
+};
+
+const SCHEMA: &str = "
+CREATE TABLE sessions (
+    id INTEGER PRIMARY KEY,
+    start_time INTEGER,
+    end_time INTEGER,
+    events INTEGER NOT NULL,
+    dropped_events INTEGER NOT NULL,
+    endianness TEXT NOT NULL,
+    format_version INTEGER NOT NULL,
+    kernel_version TEXT NOT NULL,
+    kernel_port TEXT NOT NULL,
+    num_cores INTEGER NOT NULL,
+    timer_frequency INTEGER
+);
+CREATE TABLE objects (
+    session_id INTEGER NOT NULL REFERENCES sessions(id),
+    handle INTEGER NOT NULL,
+    class TEXT,
+    symbol TEXT,
+    PRIMARY KEY (session_id, handle)
+);
+CREATE TABLE events (
+    id INTEGER PRIMARY KEY,
+    session_id INTEGER NOT NULL REFERENCES sessions(id),
+    core_id INTEGER NOT NULL,
+    event_count INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    time INTEGER NOT NULL,
+    time_ns INTEGER,
+    event_id INTEGER NOT NULL,
+    event_type TEXT NOT NULL,
+    handle INTEGER,
+    object TEXT,
+    dropped_before INTEGER,
+    text TEXT NOT NULL
+);
+CREATE TABLE context_intervals (
+    session_id INTEGER NOT NULL REFERENCES sessions(id),
+    core_id INTEGER NOT NULL,
+    handle INTEGER NOT NULL,
+    context TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    start_time INTEGER NOT NULL,
+    end_time INTEGER NOT NULL,
+    duration INTEGER NOT NULL,
+    start_ns INTEGER,
+    end_ns INTEGER
+);
+CREATE TABLE user_events (
+    event_id INTEGER PRIMARY KEY REFERENCES events(id),
+    channel TEXT NOT NULL,
+    format_string TEXT NOT NULL,
+    message TEXT NOT NULL
+);
+CREATE TABLE stack_samples (
+    event_id INTEGER PRIMARY KEY REFERENCES events(id),
+    handle INTEGER NOT NULL,
+    task TEXT NOT NULL,
+    low_mark INTEGER NOT NULL
+);
+CREATE INDEX events_time ON events(time);
+CREATE INDEX events_type ON events(event_type);
+CREATE INDEX context_intervals_time ON context_intervals(start_time, end_time);
+";
+
+/// Writes the sessions, objects, events, user events and stack samples to a SQLite
+/// database as they are read, the context intervals once the reading is done.
+///
+/// Times are in ticks since the start of the recording, with `_ns` columns when the timer
+/// frequency is known, `timestamp` is the time since the start of the session.
+pub struct SqliteExporter<'a> {
+    conn: Connection,
+    schema: &'a EventSchema,
+    elf: Option<&'a FirmwareElf>,
+
+    session_id: i64,
+    session_start: Option<u64>,
+    session_end: u64,
+    session_events: u64,
+    session_dropped: u64,
+    /// Entry table of the current session, as of its last change
+    entry_table: Option<EntryTable>,
+    /// Start time of each session
+    session_starts: Vec<u64>,
+    /// Final entry table of each ended session, to name the context intervals
+    session_tables: Vec<Option<EntryTable>>,
+    next_event_id: i64,
+
+    /// First error, reported by [`SqliteExporter::close`]
+    error: Option<rusqlite::Error>,
+}
+
+impl<'a> SqliteExporter<'a> {
+    /// Create the database, replacing an existing file
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        schema: &'a EventSchema,
+        elf: Option<&'a FirmwareElf>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        conn.execute_batch("BEGIN")?;
+        Ok(Self {
+            conn,
+            schema,
+            elf,
+            session_id: 0,
+            session_start: None,
+            session_end: 0,
+            session_events: 0,
+            session_dropped: 0,
+            entry_table: None,
+            session_starts: Vec::new(),
+            session_tables: Vec::new(),
+            next_event_id: 1,
+            error: None,
+        })
+    }
+
+    /// Write the context intervals and commit
+    pub fn close(
+        mut self,
+        rd: &RecorderData,
+        intervals: &[ContextInterval],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(e) = self.error.take() {
+            return Err(e.into());
+        }
+        let ns = |ticks: u64| rd.convert_ticks(ticks).map(|ns| ns as i64);
+        {
+            let mut stmt = self.conn.prepare(
+                "INSERT INTO context_intervals VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            )?;
+            for i in intervals.iter() {
+                let (start, end) = (i.start.get_raw(), i.end.get_raw());
+                let session_id = self
+                    .session_starts
+                    .partition_point(|s| *s <= start)
+                    .saturating_sub(1);
+                let kind = match i.context {
+                    ContextHandle::Task(_) => "Task",
+                    ContextHandle::Isr(_) => "ISR",
+                };
+                let handle = i.context.object_handle();
+                stmt.execute(params![
+                    session_id as i64,
+                    i.core_id,
+                    u32::from(handle),
+                    self.session_tables
+                        .get(session_id)
+                        .and_then(|t| t.as_ref())
+                        .and_then(|t| t.symbol(handle))
+                        .map(|s| s.to_string())
+                        .unwrap_or_else(|| object_name(rd, self.elf, handle)),
+                    kind,
+                    start as i64,
+                    end as i64,
+                    (end - start) as i64,
+                    ns(start),
+                    ns(end),
+                ])?;
+            }
+        }
+        self.conn.execute_batch("COMMIT")?;
+        Ok(())
+    }
+
+    fn write_session(&mut self, entry_table: Option<&EntryTable>) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "UPDATE sessions SET start_time = ?2, end_time = ?3, events = ?4, dropped_events = ?5 \
+             WHERE id = ?1",
+            params![
+                self.session_id,
+                self.session_start.map(|t| t as i64),
+                self.session_start.map(|_| self.session_end as i64),
+                self.session_events as i64,
+                self.session_dropped as i64,
+            ],
+        )?;
+
+        let Some(entry_table) = entry_table.or(self.entry_table.as_ref()) else {
+            return Ok(());
+        };
+        let mut stmt = self
+            .conn
+            .prepare_cached("INSERT INTO objects VALUES (?1, ?2, ?3, ?4)")?;
+        for (handle, entry) in entry_table.entries().iter() {
+            let symbol = entry
+                .symbol
+                .as_ref()
+                .map(|s| s.to_string())
+                .or_else(|| self.elf.and_then(|elf| elf.resolve(u32::from(*handle))));
+            stmt.execute(params![
+                self.session_id,
+                u32::from(*handle),
+                entry.class.map(|c| c.to_string()),
+                symbol,
+            ])?;
+        }
+        Ok(())
+    }
+
+    fn write_event(&mut self, rd: &RecorderData, record: &EventRecord) -> rusqlite::Result<()> {
+        let id = self.next_event_id;
+        self.next_event_id += 1;
+        let time = record.trace_time.get_raw();
+        self.session_start.get_or_insert(time);
+        self.session_end = time;
+        self.session_events += 1;
+        self.session_dropped += record.dropped_events.unwrap_or(0);
+
+        // Object names can change while reading, keep the latest version of the table
+        let table_changed = match &self.entry_table {
+            Some(t) => t.entries().len() != rd.entry_table.entries().len(),
+            None => true,
+        };
+        if table_changed || matches!(record.event, TraceEvent::Parsed(Event::ObjectName(_))) {
+            self.entry_table = Some(rd.entry_table.clone());
+        }
+
+        let handle = match &record.event {
+            TraceEvent::Parsed(ev) => event_object_handle(ev),
+            _ => None,
+        };
+        let event_type = self
+            .schema
+            .name(record.event_type)
+            .map(|n| n.to_owned())
+            .unwrap_or_else(|| record.event_type.to_string());
+        self.conn
+            .prepare_cached(
+                "INSERT INTO events VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            )?
+            .execute(params![
+                id,
+                self.session_id,
+                record.core_id,
+                record.event_count,
+                record.timestamp.get_raw() as i64,
+                time as i64,
+                rd.convert_ticks(time).map(|ns| ns as i64),
+                EventId::from(record.event_type).0,
+                event_type,
+                handle.map(u32::from),
+                handle.map(|h| object_name(rd, self.elf, h)),
+                record.dropped_events.map(|d| d as i64),
+                record.event.to_string(),
+            ])?;
+
+        match &record.event {
+            TraceEvent::Parsed(Event::User(ev)) => {
+                self.conn
+                    .prepare_cached("INSERT INTO user_events VALUES (?1, ?2, ?3, ?4)")?
+                    .execute(params![
+                        id,
+                        ev.channel.to_string(),
+                        ev.format_string.to_string(),
+                        ev.formatted_string.to_string(),
+                    ])?;
+            }
+            TraceEvent::ElfUser(ev) => {
+                self.conn
+                    .prepare_cached("INSERT INTO user_events VALUES (?1, ?2, ?3, ?4)")?
+                    .execute(params![
+                        id,
+                        ev.channel,
+                        ev.format_string,
+                        ev.formatted_string
+                    ])?;
+            }
+            TraceEvent::Parsed(Event::UnusedStack(ev)) => {
+                self.conn
+                    .prepare_cached("INSERT INTO stack_samples VALUES (?1, ?2, ?3, ?4)")?
+                    .execute(params![
+                        id,
+                        u32::from(ev.handle),
+                        object_name(rd, self.elf, ev.handle),
+                        ev.low_mark,
+                    ])?;
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// The session row is filled in once the session ends
+    fn start_session(&mut self, rd: &RecorderData) -> rusqlite::Result<()> {
+        self.session_starts.push(self.session_end);
+        let freq = rd.timestamp_info.timer_frequency;
+        self.conn.execute(
+            "INSERT INTO sessions VALUES (?1, NULL, NULL, 0, 0, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                self.session_id,
+                rd.header.endianness.to_string(),
+                rd.header.format_version,
+                rd.header.kernel_version.to_string(),
+                rd.header.kernel_port.to_string(),
+                rd.header.num_cores,
+                (!freq.is_unitless()).then(|| freq.get_raw()),
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn record_error(&mut self, res: rusqlite::Result<()>) {
+        if let Err(e) = res {
+            self.error.get_or_insert(e);
+        }
+    }
+}
+
+impl Analyzer for SqliteExporter<'_> {
+    fn on_header(&mut self, rd: &RecorderData) {
+        let res = self.start_session(rd);
+        self.record_error(res);
+    }
+
+    fn on_event(&mut self, rd: &RecorderData, record: &EventRecord) {
+        if self.error.is_none() {
+            let res = self.write_event(rd, record);
+            self.record_error(res);
+        }
+    }
+
+    fn on_restart(&mut self, rd: &RecorderData) {
+        // The recorder data is already the new session's, only the last copy of the
+        // entry table is left of the old one
+        let res = self.write_session(None);
+        self.record_error(res);
+        self.session_id += 1;
+        self.session_start = None;
+        self.session_events = 0;
+        self.session_dropped = 0;
+        self.session_tables.push(self.entry_table.take());
+        let res = self.start_session(rd);
+        self.record_error(res);
+    }
+
+    fn finish(&mut self, rd: &RecorderData, _summary: &TraceSummary) {
+        let res = self.write_session(Some(&rd.entry_table));
+        self.record_error(res);
+        self.session_tables.push(Some(rd.entry_table.clone()));
+    }
+}
@@ -0,0 +1,451 @@
+use crate::cli::DecodeOpts;
+use clap::Args;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Cell, Paragraph, Row, Table, TableState},
+    DefaultTerminal, Frame,
+};
+use std::{fs::File, io::BufReader, path::PathBuf};
+use trace_recorder_parser::streaming::{event::EventType, RecorderData};
+use trace_recorder_printer::{
+    analyzer::{Analyzer, ContextAnalyzer, EventTypeCounter, StackAnalyzer},
+    report::{ContextKind, TraceReport},
+    schema::EventSchema,
+    timeline::Timeline,
+    trace::{EventRecord, TraceReader},
+};
+
+/// Interactive timeline of the contexts and events
+#[derive(Args, Debug, Clone)]
+pub struct TuiOpts {
+    #[clap(flatten)]
+    pub decode: DecodeOpts,
+
+    /// Path to streaming data file (psf)
+    #[clap(value_parser)]
+    pub path: PathBuf,
+}
+
+const LABEL_WIDTH: usize = 20;
+const KEYS: &str = "←/→ cursor  [/] pan  +/- zoom  0 fit  ↑/↓ event  \
+                    t/T event type  n/p next/prev of type  PgUp/PgDn rows  q quit";
+
+/// An event in the listing, with its recording time
+struct ListedEvent {
+    time: u64,
+    event_type: EventType,
+    text: String,
+}
+
+/// Collects the event listing
+struct EventCollector<'a> {
+    schema: &'a EventSchema,
+    events: Vec<ListedEvent>,
+}
+
+impl Analyzer for EventCollector<'_> {
+    fn on_event(&mut self, rd: &RecorderData, record: &EventRecord) {
+        let name = self
+            .schema
+            .name(record.event_type)
+            .map(|n| n.to_owned())
+            .unwrap_or_else(|| record.event_type.to_string());
+        let core = if rd.header.num_cores > 1 {
+            format!("[core {}] ", record.core_id)
+        } else {
+            String::new()
+        };
+        self.events.push(ListedEvent {
+            time: record.trace_time.get_raw(),
+            event_type: record.event_type,
+            text: format!("{core}{name} : {}", record.event),
+        });
+    }
+}
+
+struct App {
+    timeline: Timeline,
+    events: Vec<ListedEvent>,
+    report: TraceReport,
+
+    /// Event types present, with their display names
+    types: Vec<(EventType, String)>,
+    selected_type: usize,
+
+    /// Visible time range, ticks
+    view_start: f64,
+    view_len: f64,
+    cursor: f64,
+
+    /// Index of the event at or before the cursor
+    selected_event: usize,
+    row_scroll: usize,
+
+    /// Width of the bars in the last drawn frame
+    bar_width: usize,
+    status: String,
+}
+
+pub fn run(opts: &TuiOpts) -> Result<(), Box<dyn std::error::Error>> {
+    let elf = opts.decode.load_elf()?;
+    let schema = opts.decode.load_schema()?;
+
+    let f = File::open(&opts.path)?;
+    let mut reader = TraceReader::new(BufReader::new(f))?;
+    if let Some(elf) = &elf {
+        reader.set_elf(elf);
+    }
+    reader.set_event_schema(&schema);
+    if let Some(custom_printf_event_id) = opts.decode.custom_printf_event_id {
+        reader.set_custom_printf_event_id(custom_printf_event_id);
+    }
+    reader.set_resync(opts.decode.resync);
+
+    let mut collector = EventCollector {
+        schema: &schema,
+        events: Vec::new(),
+    };
+    let mut contexts = ContextAnalyzer::with_intervals();
+    let mut stacks = StackAnalyzer::default();
+    let mut types = EventTypeCounter::default();
+    let summary = reader.run(&mut [&mut collector, &mut contexts, &mut stacks, &mut types])?;
+
+    let rd = reader.recorder_data();
+    let timeline = Timeline::new(rd, elf.as_ref(), &contexts.intervals, summary.total_time);
+    let report = TraceReport::new(
+        rd,
+        &summary,
+        &contexts,
+        &stacks,
+        &types,
+        elf.as_ref(),
+        Some(&schema),
+    );
+    let types = types
+        .counts
+        .keys()
+        .map(|t| {
+            let name = schema
+                .name(*t)
+                .map(|n| n.to_owned())
+                .unwrap_or_else(|| t.to_string());
+            (*t, name)
+        })
+        .collect();
+
+    let mut app = App {
+        view_start: 0.0,
+        view_len: timeline.end.max(1) as f64,
+        timeline,
+        events: collector.events,
+        report,
+        types,
+        selected_type: 0,
+        cursor: 0.0,
+        selected_event: 0,
+        row_scroll: 0,
+        bar_width: 1,
+        status: String::new(),
+    };
+
+    let mut terminal = ratatui::init();
+    let res = app.run(&mut terminal);
+    ratatui::restore();
+    res
+}
+
+impl App {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<(), Box<dyn std::error::Error>> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            self.status.clear();
+            let col = self.view_len / self.bar_width as f64;
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Left => self.move_cursor(self.cursor - col),
+                KeyCode::Right => self.move_cursor(self.cursor + col),
+                KeyCode::Char('[') => self.pan(-self.view_len / 2.0),
+                KeyCode::Char(']') => self.pan(self.view_len / 2.0),
+                KeyCode::Char('+') | KeyCode::Char('=') => self.zoom(0.5),
+                KeyCode::Char('-') => self.zoom(2.0),
+                KeyCode::Char('0') => {
+                    self.view_start = 0.0;
+                    self.view_len = self.timeline.end.max(1) as f64;
+                }
+                KeyCode::Up => self.select_event(self.selected_event.saturating_sub(1)),
+                KeyCode::Down => self.select_event(self.selected_event + 1),
+                KeyCode::Char('t') if !self.types.is_empty() => {
+                    self.selected_type = (self.selected_type + 1) % self.types.len();
+                }
+                KeyCode::Char('T') if !self.types.is_empty() => {
+                    self.selected_type =
+                        (self.selected_type + self.types.len() - 1) % self.types.len();
+                }
+                KeyCode::Char('n') => self.jump(true),
+                KeyCode::Char('p') | KeyCode::Char('N') => self.jump(false),
+                KeyCode::PageUp => self.row_scroll = self.row_scroll.saturating_sub(1),
+                KeyCode::PageDown => {
+                    self.row_scroll = (self.row_scroll + 1).min(self.timeline.rows.len())
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn move_cursor(&mut self, time: f64) {
+        self.cursor = time.clamp(0.0, self.timeline.end as f64);
+        let t = self.cursor as u64;
+        self.selected_event = self
+            .events
+            .partition_point(|ev| ev.time <= t)
+            .saturating_sub(1);
+        self.follow_cursor();
+    }
+
+    fn select_event(&mut self, idx: usize) {
+        if let Some(ev) = self.events.get(idx) {
+            self.selected_event = idx;
+            self.cursor = ev.time as f64;
+            self.follow_cursor();
+        }
+    }
+
+    /// Jump to the next or previous event of the selected type
+    fn jump(&mut self, forward: bool) {
+        let Some((event_type, name)) = self.types.get(self.selected_type) else {
+            return;
+        };
+        let found = if forward {
+            self.events
+                .iter()
+                .enumerate()
+                .skip(self.selected_event + 1)
+                .find(|(_, ev)| ev.event_type == *event_type)
+        } else {
+            self.events
+                .iter()
+                .enumerate()
+                .take(self.selected_event)
+                .rev()
+                .find(|(_, ev)| ev.event_type == *event_type)
+        };
+        match found {
+            Some((idx, _)) => self.select_event(idx),
+            None => self.status = format!("No more {name} events"),
+        }
+    }
+
+    /// Scroll the view so the cursor stays visible
+    fn follow_cursor(&mut self) {
+        if self.cursor < self.view_start || self.cursor >= self.view_start + self.view_len {
+            self.view_start = self.cursor - self.view_len / 2.0;
+            self.clamp_view();
+        }
+    }
+
+    fn pan(&mut self, delta: f64) {
+        self.view_start += delta;
+        self.clamp_view();
+        self.cursor = self
+            .cursor
+            .clamp(self.view_start, self.view_start + self.view_len);
+        self.move_cursor(self.cursor);
+    }
+
+    /// Scale the visible range around the cursor
+    fn zoom(&mut self, factor: f64) {
+        let end = self.timeline.end.max(1) as f64;
+        // Don't zoom in past a tick per column
+        let min_len = (self.bar_width as f64).min(end);
+        let new_len = (self.view_len * factor).clamp(min_len, end);
+        let rel = (self.cursor - self.view_start) / self.view_len;
+        self.view_start = self.cursor - rel * new_len;
+        self.view_len = new_len;
+        self.clamp_view();
+    }
+
+    fn clamp_view(&mut self) {
+        let end = self.timeline.end as f64;
+        self.view_start = self.view_start.min(end - self.view_len).max(0.0);
+    }
+
+    fn fmt_time(&self, ticks: f64) -> String {
+        match self.report.duration(ticks) {
+            Some(d) => format!("{d:?}"),
+            None => format!("{ticks:.0} ticks"),
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(2)]).areas(frame.area());
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(70), Constraint::Percentage(30)])
+                .areas(main);
+        let rows = self.timeline.rows.len() as u16 + 2;
+        let [gantt, events] =
+            Layout::vertical([Constraint::Max(rows.max(3)), Constraint::Min(5)]).areas(left);
+        let [contexts, types] =
+            Layout::vertical([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(right);
+
+        self.draw_gantt(frame, gantt);
+        self.draw_events(frame, events);
+        self.draw_contexts(frame, contexts);
+        self.draw_types(frame, types);
+
+        let type_name = self
+            .types
+            .get(self.selected_type)
+            .map(|(_, n)| n.as_str())
+            .unwrap_or("-");
+        let info = format!(
+            "Cursor {}  View {} .. {}  Jump type {type_name}  {}",
+            self.fmt_time(self.cursor),
+            self.fmt_time(self.view_start),
+            self.fmt_time(self.view_start + self.view_len),
+            self.status,
+        );
+        frame.render_widget(
+            Paragraph::new(vec![Line::from(info), Line::from(KEYS).dark_gray()]),
+            status,
+        );
+    }
+
+    fn draw_gantt(&mut self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered().title(" Timeline ");
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+
+        let width = (inner.width as usize)
+            .saturating_sub(LABEL_WIDTH + 1)
+            .max(1);
+        self.bar_width = width;
+        let view_end = self.view_start + self.view_len;
+        let cursor_col = (((self.cursor - self.view_start) / self.view_len) * width as f64)
+            .floor()
+            .clamp(0.0, (width - 1) as f64) as usize;
+
+        let lines: Vec<Line> = self
+            .timeline
+            .rows
+            .iter()
+            .skip(self.row_scroll)
+            .take(inner.height as usize)
+            .map(|row| {
+                let color = match row.kind {
+                    ContextKind::Isr => Color::Yellow,
+                    ContextKind::Task => Color::Cyan,
+                };
+                let mut label: String = row.name.chars().take(LABEL_WIDTH).collect();
+                label = format!("{label:<LABEL_WIDTH$} ");
+                let mut spans = vec![Span::raw(label)];
+                for (c, occ) in row
+                    .occupancy(self.view_start, view_end, width)
+                    .into_iter()
+                    .enumerate()
+                {
+                    let ch = match occ {
+                        o if o <= 0.0 => "·",
+                        o if o < 0.5 => "▄",
+                        _ => "█",
+                    };
+                    let mut style = if occ > 0.0 {
+                        Style::new().fg(color)
+                    } else {
+                        Style::new().fg(Color::DarkGray)
+                    };
+                    if c == cursor_col {
+                        style = style.bg(Color::White);
+                    }
+                    spans.push(Span::styled(ch, style));
+                }
+                Line::from(spans)
+            })
+            .collect();
+        frame.render_widget(Paragraph::new(lines), inner);
+    }
+
+    fn draw_events(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered().title(format!(" Events ({}) ", self.events.len()));
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+
+        // Keep the selected event in the middle
+        let height = inner.height as usize;
+        let first = self.selected_event.saturating_sub(height / 2);
+        let lines: Vec<Line> = self
+            .events
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(height)
+            .map(|(idx, ev)| {
+                let line = Line::from(format!(
+                    "{:>14}  {}",
+                    self.fmt_time(ev.time as f64),
+                    ev.text
+                ));
+                if idx == self.selected_event {
+                    line.add_modifier(Modifier::REVERSED)
+                } else if self.types.get(self.selected_type).map(|(t, _)| *t) == Some(ev.event_type)
+                {
+                    line.fg(Color::Green)
+                } else {
+                    line
+                }
+            })
+            .collect();
+        frame.render_widget(Paragraph::new(lines), inner);
+    }
+
+    fn draw_contexts(&self, frame: &mut Frame, area: Rect) {
+        let rows = self.report.contexts.iter().map(|(sym, ctx)| {
+            Row::new(vec![
+                Cell::from(sym.clone()),
+                Cell::from(format!("{:.02}", ctx.cpu_percent)),
+                Cell::from(ctx.count.to_string()),
+                Cell::from(self.fmt_time(ctx.runtime.max)),
+            ])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Min(10),
+                Constraint::Length(7),
+                Constraint::Length(7),
+                Constraint::Length(12),
+            ],
+        )
+        .header(Row::new(vec!["Symbol", "CPU %", "Count", "Max"]).bold())
+        .block(Block::bordered().title(" Contexts "));
+        frame.render_widget(table, area);
+    }
+
+    fn draw_types(&self, frame: &mut Frame, area: Rect) {
+        let rows = self.types.iter().map(|(_, name)| {
+            let count = self.report.event_types.get(name).copied().unwrap_or(0);
+            Row::new(vec![
+                Cell::from(count.to_string()),
+                Cell::from(name.clone()),
+            ])
+        });
+        let table = Table::new(rows, [Constraint::Length(8), Constraint::Min(10)])
+            .header(Row::new(vec!["Count", "Type"]).bold())
+            .row_highlight_style(Modifier::REVERSED)
+            .block(Block::bordered().title(format!(
+                " Event types  total {}  dropped {}  restarts {} ",
+                self.report.total_events, self.report.dropped_events, self.report.restarts
+            )));
+        let mut state = TableState::default().with_selected(Some(self.selected_type));
+        frame.render_stateful_widget(table, area, &mut state);
+    }
+}
@@ -0,0 +1,383 @@
+use crate::cli::DecodeOpts;
+use clap::Args;
+use comfy_table::modifiers::UTF8_ROUND_CORNERS;
+use comfy_table::presets::UTF8_FULL;
+use comfy_table::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::{fmt, fs::File, io::BufReader, path::PathBuf, time::Duration};
+use trace_recorder_parser::{
+    streaming::{event::Event, Error, RecorderData},
+    time::Timestamp,
+    types::ObjectHandle,
+};
+use trace_recorder_printer::{
+    analyzer::Analyzer,
+    event::{event_object_handle, ContextHandle, ContextSwitch, CoreId, EventCounter, TraceEvent},
+    report::object_name,
+    time::TrcTimeExt,
+    trace::{EventRecord, TraceReader, TraceSummary},
+};
+
+/// Check a capture for consistency, without printing the events.
+/// Exits with a non-zero status (65) when there are errors.
+#[derive(Args, Debug, Clone)]
+pub struct ValidateOpts {
+    #[clap(flatten)]
+    pub decode: DecodeOpts,
+
+    /// Path to streaming data file (psf)
+    #[clap(value_parser)]
+    pub path: PathBuf,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
+enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        })
+    }
+}
+
+/// When a finding happened
+#[derive(Copy, Clone, Debug)]
+enum FindingTime {
+    /// Time since the start of the recording
+    Trace(Timestamp),
+    /// Raw timestamp of the event, for timestamps the time since the start can't be trusted on
+    Ticks(u32),
+}
+
+struct Finding {
+    severity: Severity,
+    /// File offset of the event, or of the data
+    offset: u64,
+    /// For decoded events
+    time: Option<FindingTime>,
+    check: &'static str,
+    message: String,
+}
+
+/// Runs the checks on the events
+#[derive(Default)]
+struct Validator {
+    findings: Vec<Finding>,
+
+    /// Event count and timestamp of the last event in the session
+    last: Option<(u16, u32)>,
+    /// Handles in the entry table at the start of the session, and the tasks created since
+    created: BTreeSet<ObjectHandle>,
+    /// Handles already reported as missing from the entry table
+    missing: BTreeSet<ObjectHandle>,
+    /// Active ISRs of each core with the offset and time of their beginning, outermost first
+    isrs: BTreeMap<CoreId, Vec<(ObjectHandle, u64, Timestamp)>>,
+}
+
+impl Validator {
+    fn push(
+        &mut self,
+        severity: Severity,
+        offset: u64,
+        time: Option<FindingTime>,
+        check: &'static str,
+        message: String,
+    ) {
+        self.findings.push(Finding {
+            severity,
+            offset,
+            time,
+            check,
+            message,
+        });
+    }
+
+    fn new_session(&mut self, rd: &RecorderData) {
+        self.last = None;
+        self.created = rd.entry_table.entries().keys().copied().collect();
+        self.missing.clear();
+    }
+
+    /// ISRs still active at the end of a session
+    fn unended_isrs(&mut self, rd: &RecorderData) {
+        let isrs: Vec<_> = std::mem::take(&mut self.isrs)
+            .into_values()
+            .flatten()
+            .collect();
+        for (isr, offset, time) in isrs {
+            let name = object_name(rd, None, isr);
+            self.push(
+                Severity::Warning,
+                offset,
+                Some(FindingTime::Trace(time)),
+                "isr-nesting",
+                format!("ISR '{name}' never ended"),
+            );
+        }
+    }
+}
+
+impl Analyzer for Validator {
+    fn on_header(&mut self, rd: &RecorderData) {
+        self.new_session(rd);
+    }
+
+    fn on_event(&mut self, rd: &RecorderData, record: &EventRecord) {
+        let offset = record.offset;
+        let time = Some(FindingTime::Trace(record.trace_time));
+        let count = record.event_count;
+        let timestamp = record.event.timestamp().ticks() as u32;
+
+        if let Some((last_count, last_timestamp)) = self.last {
+            let mask = if rd.header.num_cores > 1 {
+                EventCounter::SMP_MASK
+            } else {
+                u16::MAX
+            };
+            let step = count.wrapping_sub(last_count) & mask;
+            if step == 0 {
+                self.push(
+                    Severity::Error,
+                    offset,
+                    time,
+                    "event-count",
+                    format!("Event count {count} repeated"),
+                );
+            } else if step > mask / 2 {
+                self.push(
+                    Severity::Error,
+                    offset,
+                    time,
+                    "event-count",
+                    format!("Event count went backwards from {last_count} to {count}"),
+                );
+            } else if let Some(dropped) = record.dropped_events {
+                self.push(
+                    Severity::Info,
+                    offset,
+                    time,
+                    "event-count",
+                    format!(
+                        "{dropped} events dropped between event count {last_count} and {count}"
+                    ),
+                );
+            }
+
+            // Smaller than a timer wraparound
+            if timestamp < last_timestamp
+                && u64::from(last_timestamp - timestamp) < rd.timer_wraparound() / 2
+            {
+                self.push(
+                    Severity::Error,
+                    offset,
+                    Some(FindingTime::Ticks(timestamp)),
+                    "timestamp",
+                    format!("Timestamp went backwards from {last_timestamp} to {timestamp}"),
+                );
+            }
+        }
+        self.last = Some((count, timestamp));
+
+        let TraceEvent::Parsed(event) = &record.event else {
+            return;
+        };
+
+        if let Some(handle) = event_object_handle(event) {
+            if !rd.entry_table.entries().contains_key(&handle) && self.missing.insert(handle) {
+                self.push(
+                    Severity::Warning,
+                    offset,
+                    time,
+                    "entry-table",
+                    format!("Object handle 0x{handle:08X} is missing from the entry table"),
+                );
+            }
+        }
+
+        if let Event::TaskCreate(ev) = event {
+            self.created.insert(ev.handle);
+        }
+        let Some(switch) = ContextSwitch::of(&record.event) else {
+            return;
+        };
+        let name = object_name(rd, None, switch.context.object_handle());
+        let isrs = self.isrs.entry(record.core_id).or_default();
+        match switch.context {
+            ContextHandle::Task(h) => {
+                // Back to a task, the ISRs have returned
+                isrs.clear();
+                if self.created.insert(h) {
+                    self.push(
+                        Severity::Warning,
+                        offset,
+                        time,
+                        "task-create",
+                        format!("Task '{name}' began without being created"),
+                    );
+                }
+            }
+            // Back to an interrupted ISR, the nested ones have returned
+            ContextHandle::Isr(h) if switch.isr_resume => {
+                match isrs.iter().position(|(isr, _, _)| *isr == h) {
+                    Some(pos) => isrs.truncate(pos + 1),
+                    None => {
+                        isrs.clear();
+                        isrs.push((h, offset, record.trace_time));
+                        self.push(
+                            Severity::Warning,
+                            offset,
+                            time,
+                            "isr-nesting",
+                            format!("ISR '{name}' resumed without having begun"),
+                        );
+                    }
+                }
+            }
+            ContextHandle::Isr(h) => {
+                if isrs.iter().any(|(isr, _, _)| *isr == h) {
+                    self.push(
+                        Severity::Warning,
+                        offset,
+                        time,
+                        "isr-nesting",
+                        format!("ISR '{name}' began again before it ended"),
+                    );
+                } else {
+                    isrs.push((h, offset, record.trace_time));
+                }
+            }
+        }
+    }
+
+    fn on_error(&mut self, _rd: &RecorderData, offset: u64, error: &Error) {
+        match error {
+            Error::Io(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => self.push(
+                Severity::Error,
+                offset,
+                None,
+                "truncated",
+                "The stream ends in the middle of an event".to_string(),
+            ),
+            Error::ObjectLookup(handle) => self.push(
+                Severity::Error,
+                offset,
+                None,
+                "entry-table",
+                format!(
+                    "Object handle 0x{handle:08X} is missing from the entry table, \
+                     the event can't be decoded"
+                ),
+            ),
+            _ => self.push(Severity::Error, offset, None, "decode", error.to_string()),
+        }
+    }
+
+    fn on_restart(&mut self, rd: &RecorderData) {
+        self.unended_isrs(rd);
+        self.new_session(rd);
+    }
+
+    fn finish(&mut self, rd: &RecorderData, summary: &TraceSummary) {
+        self.unended_isrs(rd);
+        for r in summary.skipped.iter() {
+            self.push(
+                Severity::Error,
+                r.offset,
+                None,
+                "corrupted",
+                format!("Skipped {} bytes of corrupted data", r.len),
+            );
+        }
+    }
+}
+
+pub fn run(opts: &ValidateOpts) -> Result<exitcode::ExitCode, Box<dyn std::error::Error>> {
+    let elf = opts.decode.load_elf()?;
+    let schema = opts.decode.load_schema()?;
+
+    let f = File::open(&opts.path)?;
+    let mut reader = TraceReader::new(BufReader::new(f))?;
+    if let Some(elf) = &elf {
+        reader.set_elf(elf);
+    }
+    reader.set_event_schema(&schema);
+    if let Some(custom_printf_event_id) = opts.decode.custom_printf_event_id {
+        reader.set_custom_printf_event_id(custom_printf_event_id);
+    }
+    reader.set_resync(opts.decode.resync);
+
+    let mut validator = Validator::default();
+    let summary = reader.run(&mut [&mut validator])?;
+    let rd = reader.recorder_data();
+
+    let mut findings = validator.findings;
+    findings.sort_by_key(|f| f.offset);
+    let rows: Vec<Vec<Cell>> = findings
+        .iter()
+        .map(|f| {
+            let severity = match f.severity {
+                Severity::Error => Cell::new(f.severity)
+                    .fg(Color::Red)
+                    .add_attribute(Attribute::Bold),
+                Severity::Warning => Cell::new(f.severity).fg(Color::Yellow),
+                Severity::Info => Cell::new(f.severity),
+            };
+            let time = f
+                .time
+                .map(|t| match t {
+                    FindingTime::Trace(t) => match rd.convert_timestamp(t) {
+                        Some(ns) => format!("{:?}", Duration::from_nanos(ns)),
+                        None => t.ticks().to_string(),
+                    },
+                    FindingTime::Ticks(ticks) => format!("{ticks} ticks"),
+                })
+                .unwrap_or_default();
+            vec![
+                severity,
+                Cell::new(format!("0x{:08X}", f.offset)),
+                Cell::new(time),
+                Cell::new(f.check),
+                Cell::new(&f.message),
+            ]
+        })
+        .collect();
+    let count = |severity| findings.iter().filter(|f| f.severity == severity).count();
+    let errors = count(Severity::Error);
+
+    println!("File: {}", opts.path.display());
+    println!("Events: {}", summary.total_events);
+    if !rows.is_empty() {
+        let mut table = Table::new();
+        table
+            .load_preset(UTF8_FULL)
+            .apply_modifier(UTF8_ROUND_CORNERS)
+            .set_content_arrangement(ContentArrangement::Dynamic)
+            .set_header(vec!["Severity", "Offset", "Time", "Check", "Finding"])
+            .add_rows(rows);
+        for i in [1, 2] {
+            table
+                .column_mut(i)
+                .unwrap()
+                .set_cell_alignment(CellAlignment::Right);
+        }
+        println!("{table}");
+    }
+    println!(
+        "Findings: {errors} errors, {} warnings, {} info",
+        count(Severity::Warning),
+        count(Severity::Info)
+    );
+
+    Ok(if errors == 0 {
+        exitcode::OK
+    } else {
+        exitcode::DATAERR
+    })
+}
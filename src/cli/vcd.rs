@@ -0,0 +1,292 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+use trace_recorder_parser::{
+    streaming::{event::Event, RecorderData},
+    types::Argument,
+};
+use trace_recorder_printer::{
+    analyzer::{Analyzer, TaskState, TaskStateTracker},
+    elf::FirmwareElf,
+    event::{ContextSwitch, CoreContext, CoreId, TraceEvent},
+    report::object_name,
+    time::TrcTimeExt,
+    trace::EventRecord,
+};
+
+/// Records the value changes of the task state, ISR and user event signals, written
+/// as a Value Change Dump once the reading is done since the signals must be declared first.
+///
+/// Task states are 2-bit values: 0 suspended, 1 blocked, 2 ready, 3 running.
+/// ISRs are active from their entry to their return, nested ISRs included.
+/// The numeric arguments of user events on the selected channels are 32-bit integers.
+pub struct VcdExporter<'a> {
+    elf: Option<&'a FirmwareElf>,
+    channels: &'a [String],
+
+    tracker: TaskStateTracker,
+    /// Running task and active ISRs of each core
+    cores: BTreeMap<CoreId, CoreContext>,
+
+    signals: Vec<Signal>,
+    signal_ids: HashMap<(SignalKind, String), usize>,
+    /// Trace time, signal and value of each change
+    changes: Vec<(u64, usize, SignalValue)>,
+    last_time: u64,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+enum SignalKind {
+    Task,
+    Isr,
+    User,
+}
+
+struct Signal {
+    kind: SignalKind,
+    /// `channel/argN` for user event signals
+    name: String,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum SignalValue {
+    Unknown,
+    State(TaskState),
+    Active(bool),
+    Int(u32),
+}
+
+impl<'a> VcdExporter<'a> {
+    pub fn new(elf: Option<&'a FirmwareElf>, channels: &'a [String]) -> Self {
+        Self {
+            elf,
+            channels,
+            tracker: TaskStateTracker::default(),
+            cores: BTreeMap::new(),
+            signals: Vec::new(),
+            signal_ids: HashMap::new(),
+            changes: Vec::new(),
+            last_time: 0,
+        }
+    }
+
+    pub fn write<P: AsRef<Path>>(
+        &self,
+        path: P,
+        rd: &RecorderData,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut out = BufWriter::new(File::create(path)?);
+        let ns = !rd.timestamp_info.timer_frequency.is_unitless();
+
+        writeln!(out, "$version trace-recorder-printer $end")?;
+        writeln!(
+            out,
+            "$comment Task states: 0 suspended, 1 blocked, 2 ready, 3 running $end"
+        )?;
+        if !ns {
+            writeln!(
+                out,
+                "$comment The timer frequency is unknown, times are timer ticks $end"
+            )?;
+        }
+        writeln!(out, "$timescale 1 ns $end")?;
+
+        let ids: Vec<String> = (0..self.signals.len()).map(identifier).collect();
+        for (kind, scope) in [(SignalKind::Task, "tasks"), (SignalKind::Isr, "isrs")] {
+            writeln!(out, "$scope module {scope} $end")?;
+            for (i, s) in self
+                .signals
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| s.kind == kind)
+            {
+                let (var, width) = match kind {
+                    SignalKind::Task => ("reg", 2),
+                    _ => ("wire", 1),
+                };
+                writeln!(
+                    out,
+                    "$var {var} {width} {} {} $end",
+                    ids[i],
+                    reference(&s.name)
+                )?;
+            }
+            writeln!(out, "$upscope $end")?;
+        }
+        let mut user_scopes: BTreeMap<&str, Vec<(usize, &str)>> = BTreeMap::new();
+        for (i, s) in self.signals.iter().enumerate() {
+            if s.kind == SignalKind::User {
+                if let Some((channel, arg)) = s.name.rsplit_once('/') {
+                    user_scopes.entry(channel).or_default().push((i, arg));
+                }
+            }
+        }
+        if !user_scopes.is_empty() {
+            writeln!(out, "$scope module user $end")?;
+            for (channel, args) in user_scopes.iter() {
+                writeln!(out, "$scope module {} $end", reference(channel))?;
+                for (i, arg) in args.iter() {
+                    writeln!(out, "$var integer 32 {} {arg} $end", ids[*i])?;
+                }
+                writeln!(out, "$upscope $end")?;
+            }
+            writeln!(out, "$upscope $end")?;
+        }
+        writeln!(out, "$enddefinitions $end")?;
+
+        writeln!(out, "$dumpvars")?;
+        for (i, s) in self.signals.iter().enumerate() {
+            let initial = match s.kind {
+                SignalKind::Isr => SignalValue::Active(false),
+                _ => SignalValue::Unknown,
+            };
+            write_value(&mut out, s.kind, initial, &ids[i])?;
+        }
+        writeln!(out, "$end")?;
+
+        let mut changes = self.changes.clone();
+        changes.sort_by_key(|(time, _, _)| *time);
+        let mut last_time = None;
+        for (ticks, i, value) in changes.into_iter() {
+            let time = if ns {
+                rd.convert_ticks(ticks).unwrap_or_default()
+            } else {
+                ticks
+            };
+            if last_time != Some(time) {
+                writeln!(out, "#{time}")?;
+                last_time = Some(time);
+            }
+            write_value(&mut out, self.signals[i].kind, value, &ids[i])?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    fn change(&mut self, time: u64, kind: SignalKind, name: String, value: SignalValue) {
+        let next_id = self.signals.len();
+        let id = *self
+            .signal_ids
+            .entry((kind, name.clone()))
+            .or_insert(next_id);
+        if id == next_id {
+            self.signals.push(Signal { kind, name });
+        }
+        self.changes.push((time, id, value));
+    }
+
+    fn update_isrs(&mut self, rd: &RecorderData, time: u64, core_id: CoreId, context: CoreContext) {
+        let isrs = context.isrs.clone();
+        let old = self.cores.insert(core_id, context).unwrap_or_default().isrs;
+        for h in old.iter().filter(|h| !isrs.contains(h)) {
+            let name = object_name(rd, self.elf, *h);
+            self.change(time, SignalKind::Isr, name, SignalValue::Active(false));
+        }
+        for h in isrs.iter().filter(|h| !old.contains(h)) {
+            let name = object_name(rd, self.elf, *h);
+            self.change(time, SignalKind::Isr, name, SignalValue::Active(true));
+        }
+    }
+}
+
+impl Analyzer for VcdExporter<'_> {
+    fn on_event(&mut self, rd: &RecorderData, record: &EventRecord) {
+        let time = record.trace_time.get_raw();
+        let core_id = record.core_id;
+        self.last_time = time;
+
+        for (task, state) in self.tracker.update(record) {
+            let name = object_name(rd, self.elf, task);
+            self.change(time, SignalKind::Task, name, SignalValue::State(state));
+        }
+
+        if let Some(switch) = ContextSwitch::of(&record.event) {
+            let mut context = self.cores.get(&core_id).cloned().unwrap_or_default();
+            context.switch(&switch);
+            self.update_isrs(rd, time, core_id, context);
+            return;
+        }
+
+        if let TraceEvent::Parsed(Event::User(ev)) = &record.event {
+            let channel = ev.channel.to_string();
+            if !self.channels.contains(&channel) {
+                return;
+            }
+            for (n, arg) in ev.args.iter().filter_map(integer).enumerate() {
+                let name = format!("{channel}/arg{n}");
+                self.change(time, SignalKind::User, name, SignalValue::Int(arg));
+            }
+        }
+    }
+
+    fn on_restart(&mut self, rd: &RecorderData) {
+        // The task states are unknown until the next events, the ISRs have returned
+        let time = self.last_time;
+        for (task, _) in self.tracker.states().clone().into_iter() {
+            let name = object_name(rd, self.elf, task);
+            self.change(time, SignalKind::Task, name, SignalValue::Unknown);
+        }
+        self.tracker.reset();
+        let cores: Vec<CoreId> = self.cores.keys().copied().collect();
+        for core_id in cores {
+            self.update_isrs(rd, time, core_id, CoreContext::default());
+        }
+    }
+}
+
+/// Integer arguments, as their 32-bit two's complement
+fn integer(arg: &Argument) -> Option<u32> {
+    Some(match arg {
+        Argument::I8(v) => *v as u32,
+        Argument::U8(v) => u32::from(*v),
+        Argument::I16(v) => *v as u32,
+        Argument::U16(v) => u32::from(*v),
+        Argument::I32(v) => *v as u32,
+        Argument::U32(v) => *v,
+        _ => return None,
+    })
+}
+
+fn write_value(
+    out: &mut impl Write,
+    kind: SignalKind,
+    value: SignalValue,
+    id: &str,
+) -> std::io::Result<()> {
+    match (kind, value) {
+        (SignalKind::Isr, SignalValue::Active(active)) => writeln!(out, "{}{id}", u8::from(active)),
+        (SignalKind::Isr, _) => writeln!(out, "x{id}"),
+        (_, SignalValue::State(state)) => {
+            let bits = match state {
+                TaskState::Suspended => "00",
+                TaskState::Blocked => "01",
+                TaskState::Ready => "10",
+                TaskState::Running => "11",
+            };
+            writeln!(out, "b{bits} {id}")
+        }
+        (_, SignalValue::Int(v)) => writeln!(out, "b{v:b} {id}"),
+        _ => writeln!(out, "bx {id}"),
+    }
+}
+
+/// VCD identifier code of the nth signal, printable ASCII characters
+fn identifier(mut n: usize) -> String {
+    let mut id = String::new();
+    loop {
+        id.push((b'!' + (n % 94) as u8) as char);
+        n /= 94;
+        if n == 0 {
+            return id;
+        }
+        n -= 1;
+    }
+}
+
+/// Signal references can't have whitespace
+fn reference(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<_>>().join("_")
+}
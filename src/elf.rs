@@ -0,0 +1,103 @@
+use object::{Object, ObjectSection, ObjectSymbol, SectionKind, SymbolKind};
+use std::collections::BTreeMap;
+use std::{fs, path::Path};
+
+/// Symbol and read-only data read from the firmware ELF file, used to name
+/// kernel objects and addresses that aren't in the entry table, and to
+/// recover strings the recorder only logged the address of
+#[derive(Clone, Debug, Default)]
+pub struct FirmwareElf {
+    /// Global data symbols, keyed by address
+    symbols: BTreeMap<u64, ElfSymbol>,
+
+    /// Contents of the read-only sections (.rodata and friends), keyed by address
+    rodata: BTreeMap<u64, Vec<u8>>,
+}
+
+#[derive(Clone, Debug)]
+struct ElfSymbol {
+    name: String,
+    size: u64,
+}
+
+impl FirmwareElf {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let data = fs::read(path)?;
+        let file = object::File::parse(data.as_slice())?;
+
+        let mut symbols = BTreeMap::new();
+        for sym in file.symbols() {
+            if !sym.is_global() || !sym.is_definition() {
+                continue;
+            }
+            if sym.kind() != SymbolKind::Data {
+                continue;
+            }
+            let name = match sym.name() {
+                Ok(n) if !n.is_empty() => n,
+                _ => continue,
+            };
+
+            // Prefer the sized symbol when several share an address (aliases)
+            let address = sym.address();
+            let size = sym.size();
+            let replace = symbols
+                .get(&address)
+                .map(|s: &ElfSymbol| s.size == 0 && size != 0)
+                .unwrap_or(true);
+            if replace {
+                symbols.insert(
+                    address,
+                    ElfSymbol {
+                        name: name.to_owned(),
+                        size,
+                    },
+                );
+            }
+        }
+
+        let mut rodata = BTreeMap::new();
+        for section in file.sections() {
+            if !matches!(
+                section.kind(),
+                SectionKind::ReadOnlyData
+                    | SectionKind::ReadOnlyDataWithRel
+                    | SectionKind::ReadOnlyString
+                    | SectionKind::Text
+            ) {
+                continue;
+            }
+            let data = section.data()?;
+            if !data.is_empty() {
+                rodata.insert(section.address(), data.to_vec());
+            }
+        }
+
+        Ok(Self { symbols, rodata })
+    }
+
+    /// Resolve an address to the global symbol containing it.
+    /// Addresses inside a symbol are rendered as `symbol+0xOFFSET`.
+    pub fn resolve(&self, address: u32) -> Option<String> {
+        let address = u64::from(address);
+        let (sym_addr, sym) = self.symbols.range(..=address).next_back()?;
+        let offset = address - sym_addr;
+        if offset == 0 {
+            Some(sym.name.clone())
+        } else if offset < sym.size {
+            Some(format!("{}+0x{offset:X}", sym.name))
+        } else {
+            None
+        }
+    }
+
+    /// Read the NUL terminated string at the given address from the read-only sections
+    pub fn string(&self, address: u32) -> Option<String> {
+        let address = u64::from(address);
+        let (section_addr, data) = self.rodata.range(..=address).next_back()?;
+        let start = usize::try_from(address - section_addr).ok()?;
+        let bytes = data.get(start..)?;
+        let end = bytes.iter().position(|b| *b == 0)?;
+        Some(String::from_utf8_lossy(&bytes[..end]).into_owned())
+    }
+}
@@ -0,0 +1,328 @@
+use crate::schema::CustomEvent;
+use crate::user_event::ElfUserEvent;
+use std::fmt;
+use trace_recorder_parser::{
+    streaming::event::{Event, IsrEvent, TaskEvent},
+    time::Timestamp,
+    types::{ObjectHandle, Priority},
+};
+
+/// An event from the stream, either materialized by the parser or
+/// recovered from the raw event data by this tool
+#[derive(Clone, Debug)]
+pub enum TraceEvent {
+    Parsed(Event),
+    ElfUser(ElfUserEvent),
+    Custom(CustomEvent),
+}
+
+impl TraceEvent {
+    pub fn event_count(&self) -> u16 {
+        match self {
+            TraceEvent::Parsed(ev) => ev.event_count().into(),
+            TraceEvent::ElfUser(ev) => ev.event_count,
+            TraceEvent::Custom(ev) => ev.event_count,
+        }
+    }
+
+    pub fn timestamp(&self) -> Timestamp {
+        match self {
+            TraceEvent::Parsed(ev) => ev.timestamp(),
+            TraceEvent::ElfUser(ev) => ev.timestamp,
+            TraceEvent::Custom(ev) => ev.timestamp,
+        }
+    }
+}
+
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceEvent::Parsed(ev) => ev.fmt(f),
+            TraceEvent::ElfUser(ev) => write!(f, "User({ev})"),
+            TraceEvent::Custom(ev) => write!(f, "Custom({ev})"),
+        }
+    }
+}
+
+/// Core the event was recorded on, always 0 on single-core configurations
+pub type CoreId = u8;
+
+/// Split the event count field into the core ID and the event counter.
+///
+/// On SMP configurations (TRC_CFG_CORE_COUNT > 1) the recorder packs the
+/// core ID into the upper 4 bits, leaving a 12-bit event counter.
+pub fn split_event_count(num_cores: u32, raw: u16) -> (CoreId, u16) {
+    if num_cores > 1 {
+        ((raw >> 12) as CoreId, raw & EventCounter::SMP_MASK)
+    } else {
+        (0, raw)
+    }
+}
+
+/// Event counter that tracks rollovers and discontinuities.
+/// Same as TrackingEventCounter, but also handles the narrower
+/// counter used on SMP configurations.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct EventCounter {
+    mask: u16,
+    count: u16,
+    rollovers: u32,
+}
+
+impl EventCounter {
+    pub const SMP_MASK: u16 = 0x0F_FF;
+
+    pub fn new(num_cores: u32) -> Self {
+        Self {
+            mask: if num_cores > 1 {
+                Self::SMP_MASK
+            } else {
+                u16::MAX
+            },
+            count: 0,
+            rollovers: 0,
+        }
+    }
+
+    /// Sets the initial counter value and reset the rollover tracking.
+    pub fn set_initial_count(&mut self, count: u16) {
+        self.count = count & self.mask;
+        self.rollovers = 0;
+    }
+
+    /// Updates the event count handling rollovers.
+    /// Returns the number of dropped events, if any.
+    pub fn update(&mut self, count: u16) -> Option<u64> {
+        let count = count & self.mask;
+        let prev_count = self.count();
+
+        // Handle rollover
+        if count <= self.count {
+            self.rollovers += 1;
+        }
+        self.count = count;
+
+        let diff = self.count() - prev_count;
+        if diff != 1 {
+            Some(diff - 1)
+        } else {
+            None
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        u64::from(self.rollovers) * (u64::from(self.mask) + 1) + u64::from(self.count)
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum ContextHandle {
+    Task(ObjectHandle),
+    Isr(ObjectHandle),
+}
+
+impl ContextHandle {
+    pub fn object_handle(self) -> ObjectHandle {
+        match self {
+            ContextHandle::Task(h) => h,
+            ContextHandle::Isr(h) => h,
+        }
+    }
+}
+
+impl From<&TaskEvent> for ContextHandle {
+    fn from(event: &TaskEvent) -> Self {
+        ContextHandle::Task(event.handle)
+    }
+}
+
+impl From<&IsrEvent> for ContextHandle {
+    fn from(event: &IsrEvent) -> Self {
+        ContextHandle::Isr(event.handle)
+    }
+}
+
+/// Switch of the running context of a core, from a task or ISR begin, resume or activate event
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct ContextSwitch {
+    pub context: ContextHandle,
+    pub priority: Priority,
+    /// Back to an interrupted ISR, the ISRs nested in it have returned
+    pub isr_resume: bool,
+}
+
+impl ContextSwitch {
+    /// The context switched in by the event, if it's a context switch
+    pub fn of(event: &TraceEvent) -> Option<Self> {
+        let (context, priority, isr_resume) = match event {
+            TraceEvent::Parsed(Event::IsrBegin(ev)) => (ev.into(), ev.priority, false),
+            TraceEvent::Parsed(Event::IsrResume(ev)) => (ev.into(), ev.priority, true),
+            TraceEvent::Parsed(
+                Event::TaskBegin(ev) | Event::TaskResume(ev) | Event::TaskActivate(ev),
+            ) => (ev.into(), ev.priority, false),
+            _ => return None,
+        };
+        Some(Self {
+            context,
+            priority,
+            isr_resume,
+        })
+    }
+}
+
+/// Running task and ISR nesting of a core
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct CoreContext {
+    /// Task running or interrupted, unknown until the first task switch
+    pub task: Option<ObjectHandle>,
+    /// Active ISRs, outermost first
+    pub isrs: Vec<ObjectHandle>,
+}
+
+impl CoreContext {
+    /// The innermost active ISR, or the task
+    pub fn running(&self) -> ContextHandle {
+        match self.isrs.last() {
+            Some(isr) => ContextHandle::Isr(*isr),
+            None => ContextHandle::Task(self.task.unwrap_or(ObjectHandle::NO_TASK)),
+        }
+    }
+
+    /// Apply a context switch: ISRs nest, resuming an ISR ends the ones nested in it,
+    /// and switching to a task ends them all
+    pub fn switch(&mut self, switch: &ContextSwitch) {
+        match switch.context {
+            ContextHandle::Isr(h) if switch.isr_resume => {
+                match self.isrs.iter().position(|isr| *isr == h) {
+                    Some(pos) => self.isrs.truncate(pos + 1),
+                    None => self.isrs = vec![h],
+                }
+            }
+            ContextHandle::Isr(h) => {
+                if self.isrs.last() != Some(&h) {
+                    self.isrs.push(h);
+                }
+            }
+            ContextHandle::Task(h) => {
+                self.isrs.clear();
+                self.task = Some(h);
+            }
+        }
+    }
+}
+
+/// Returns the kernel object handle the event refers to, if any
+pub fn event_object_handle(event: &Event) -> Option<ObjectHandle> {
+    use Event::*;
+    Some(match event {
+        TraceStart(e) => e.current_task_handle,
+        ObjectName(e) => e.handle,
+        TaskPriority(e)
+        | TaskPriorityInherit(e)
+        | TaskPriorityDisinherit(e)
+        | TaskCreate(e)
+        | TaskReady(e)
+        | TaskBegin(e)
+        | TaskResume(e)
+        | TaskActivate(e) => e.handle,
+        IsrDefine(e) | IsrBegin(e) | IsrResume(e) => e.handle,
+        TaskNotify(e) | TaskNotifyFromIsr(e) | TaskNotifyWait(e) | TaskNotifyWaitBlock(e) => {
+            e.handle
+        }
+        QueueCreate(e) => e.handle,
+        QueueSend(e)
+        | QueueSendBlock(e)
+        | QueueSendFromIsr(e)
+        | QueueReceive(e)
+        | QueueReceiveBlock(e)
+        | QueueReceiveFromIsr(e)
+        | QueuePeek(e)
+        | QueuePeekBlock(e)
+        | QueueSendFront(e)
+        | QueueSendFrontBlock(e)
+        | QueueSendFrontFromIsr(e) => e.handle,
+        MutexCreate(e) => e.handle,
+        MutexGive(e)
+        | MutexGiveBlock(e)
+        | MutexGiveRecursive(e)
+        | MutexTake(e)
+        | MutexTakeBlock(e)
+        | MutexTakeRecursive(e)
+        | MutexTakeRecursiveBlock(e) => e.handle,
+        SemaphoreBinaryCreate(e) | SemaphoreCountingCreate(e) => e.handle,
+        SemaphoreGive(e)
+        | SemaphoreGiveBlock(e)
+        | SemaphoreGiveFromIsr(e)
+        | SemaphoreTake(e)
+        | SemaphoreTakeBlock(e)
+        | SemaphoreTakeFromIsr(e)
+        | SemaphorePeek(e)
+        | SemaphorePeekBlock(e) => e.handle,
+        EventGroupCreate(e) => e.handle,
+        EventGroupSync(e)
+        | EventGroupWaitBits(e)
+        | EventGroupClearBits(e)
+        | EventGroupClearBitsFromIsr(e)
+        | EventGroupSetBits(e)
+        | EventGroupSetBitsFromIsr(e)
+        | EventGroupSyncBlock(e)
+        | EventGroupWaitBitsBlock(e) => e.handle,
+        MessageBufferCreate(e) => e.handle,
+        MessageBufferSend(e)
+        | MessageBufferReceive(e)
+        | MessageBufferSendFromIsr(e)
+        | MessageBufferReceiveFromIsr(e)
+        | MessageBufferReset(e) => e.handle,
+        MessageBufferSendBlock(e) | MessageBufferReceiveBlock(e) => e.handle,
+        StateMachineCreate(e) => e.handle,
+        StateMachineStateCreate(e) | StateMachineStateChange(e) => e.handle,
+        UnusedStack(e) => e.handle,
+        TsConfig(_) | MemoryAlloc(_) | MemoryFree(_) | User(_) | Unknown(_) => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_count_core_id() {
+        assert_eq!(split_event_count(1, 0x3ABC), (0, 0x3ABC));
+        assert_eq!(split_event_count(2, 0x1ABC), (1, 0x0ABC));
+        assert_eq!(split_event_count(4, 0x3FFF), (3, 0x0FFF));
+        assert_eq!(split_event_count(2, 0x0001), (0, 0x0001));
+    }
+
+    #[test]
+    fn single_core_counter() {
+        let mut counter = EventCounter::new(1);
+        counter.set_initial_count(0xFFFE);
+        assert_eq!(counter.update(0xFFFF), None);
+        assert_eq!(counter.update(0x0000), None);
+        assert_eq!(counter.count(), 0x1_0000);
+        assert_eq!(counter.update(0x0004), Some(3));
+        assert_eq!(counter.count(), 0x1_0004);
+    }
+
+    #[test]
+    fn smp_counter() {
+        let mut counter = EventCounter::new(2);
+
+        // The core ID bits are ignored
+        counter.set_initial_count(0x1FFE);
+        assert_eq!(counter.count(), 0x0FFE);
+        assert_eq!(counter.update(0x0FFF), None);
+
+        // Rolls over at 12 bits
+        assert_eq!(counter.update(0x1000), None);
+        assert_eq!(counter.count(), 0x1000);
+        assert_eq!(counter.update(0x0003), Some(2));
+        assert_eq!(counter.count(), 0x1003);
+
+        // A repeated count is a full rollover of dropped events
+        assert_eq!(
+            counter.update(0x1003),
+            Some(u64::from(EventCounter::SMP_MASK))
+        );
+    }
+}
@@ -0,0 +1,948 @@
+//! Synthetic recordings, written from a description of the system, for deterministic fixtures.
+//!
+//! ```toml
+//! duration = 100000
+//!
+//! [[task]]
+//! name = "Control"
+//! priority = 3
+//! period = 1000
+//! execution = [100, 300]
+//! alloc = 64
+//!
+//! [[isr]]
+//! name = "Timer0"
+//! priority = 5
+//! period = 250
+//! execution = [5, 10]
+//!
+//! [[object]]
+//! name = "RxQueue"
+//! kind = "queue"
+//!
+//! [[task]]
+//! name = "Comms"
+//! priority = 2
+//! period = 2000
+//! execution = [50, 100]
+//! wait = { object = "RxQueue", ticks = 500, timeout_every = 3 }
+//!
+//! [[user_event]]
+//! task = "Control"
+//! channel = "log"
+//! format = "setpoint %d"
+//! args = [42]
+//!
+//! [[fault]]
+//! kind = "drop"
+//! at = 50000
+//! count = 10
+//! ```
+//!
+//! Times are in timer ticks. The tasks and ISRs are scheduled on a single core by priority,
+//! ISRs preempt the tasks and lower priority ISRs, IDLE runs when nothing else does.
+//! The execution times are drawn from `execution = [min, max]` with a seeded generator,
+//! the same description always gives the same recording.
+
+use crate::psf::{HeaderEntry, SessionHeader};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::{fs, io::Write, path::Path};
+use trace_recorder_parser::{
+    streaming::{
+        event::{EventId, EventType},
+        HeaderInfo,
+    },
+    types::{Endianness, Heap},
+};
+
+/// Description of a recording
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TraceSpec {
+    #[serde(default)]
+    pub endianness: ByteOrder,
+    #[serde(default)]
+    pub kernel_port: KernelPort,
+    /// Timer frequency (Hz)
+    #[serde(default = "default_frequency")]
+    pub frequency: u32,
+    /// OS tick rate (Hz)
+    #[serde(default = "default_tick_rate")]
+    pub tick_rate: u32,
+    /// Timer period of the header, 0 is a free running 32-bit counter
+    #[serde(default = "default_timer_period")]
+    pub timer_period: u32,
+    /// Length of the recording, in ticks
+    pub duration: u64,
+    /// Seed of the execution times
+    #[serde(default)]
+    pub seed: u64,
+    /// Write the user events as custom printf events with this ID
+    #[serde(default)]
+    pub custom_printf_event_id: Option<u16>,
+    /// Size of the system heap, adds the heap to the entry table
+    #[serde(default)]
+    pub heap_size: Option<u32>,
+    #[serde(default, rename = "task")]
+    pub tasks: Vec<TaskSpec>,
+    #[serde(default, rename = "isr")]
+    pub isrs: Vec<IsrSpec>,
+    #[serde(default, rename = "object")]
+    pub objects: Vec<ObjectSpec>,
+    #[serde(default, rename = "user_event")]
+    pub user_events: Vec<UserEventSpec>,
+    #[serde(default, rename = "fault")]
+    pub faults: Vec<FaultSpec>,
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ByteOrder {
+    #[default]
+    Little,
+    Big,
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KernelPort {
+    #[default]
+    FreeRtos,
+    Zephyr,
+    ThreadX,
+}
+
+/// Periodic task
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TaskSpec {
+    pub name: String,
+    pub priority: u32,
+    pub period: u64,
+    /// First release
+    #[serde(default)]
+    pub offset: u64,
+    /// Execution time range of each run
+    pub execution: [u64; 2],
+    /// Bytes allocated from the system heap at the start of each run
+    #[serde(default)]
+    pub alloc: Option<u32>,
+    /// Don't free the allocations at the end of the runs
+    #[serde(default)]
+    pub leak: bool,
+    /// Stack low mark reported at the end of each run
+    #[serde(default)]
+    pub stack_low_mark: Option<u32>,
+    /// Kernel object call the task blocks on at the start of each run
+    #[serde(default)]
+    pub wait: Option<WaitSpec>,
+}
+
+/// Blocking call on a kernel object
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WaitSpec {
+    pub object: String,
+    /// Time blocked before the call returns
+    pub ticks: u64,
+    /// Every this many calls time out and fail, none do when unset
+    #[serde(default)]
+    pub timeout_every: Option<u32>,
+}
+
+/// Kernel object the tasks can block on
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ObjectSpec {
+    pub name: String,
+    pub kind: ObjectKind,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ObjectKind {
+    Queue,
+    Semaphore,
+    Mutex,
+}
+
+impl ObjectKind {
+    /// Create, take, take block and take failed event IDs
+    fn event_ids(self) -> [u16; 4] {
+        match self {
+            ObjectKind::Queue => [0x11, 0x60, 0x66, 0x63],
+            ObjectKind::Semaphore => [0x12, 0x61, 0x67, 0x64],
+            ObjectKind::Mutex => [0x13, 0x62, 0x68, 0x65],
+        }
+    }
+
+    /// Parameters of the take events: the handle, the timeout and, but for mutexes,
+    /// the messages waiting or the semaphore count
+    fn take_params(self, handle: u32, timeout: u32) -> Vec<u32> {
+        match self {
+            ObjectKind::Queue | ObjectKind::Semaphore => vec![handle, timeout, 0],
+            ObjectKind::Mutex => vec![handle, timeout],
+        }
+    }
+}
+
+/// Periodic interrupt
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IsrSpec {
+    pub name: String,
+    pub priority: u32,
+    pub period: u64,
+    /// First arrival
+    #[serde(default)]
+    pub offset: u64,
+    /// Execution time range of each run
+    pub execution: [u64; 2],
+}
+
+/// User event logged at the end of each run of a task
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserEventSpec {
+    pub task: String,
+    pub channel: String,
+    pub format: String,
+    /// 32-bit arguments, up to 6
+    #[serde(default)]
+    pub args: Vec<u32>,
+}
+
+/// Fault injected in the stream at the first event at or after `at`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum FaultSpec {
+    /// Skip event counts, as when the recorder drops events
+    Drop { at: u64, count: u16 },
+    /// Start a new session with a new header
+    Restart { at: u64 },
+    /// Garbage bytes between two events
+    Corrupt { at: u64, bytes: usize },
+    /// End the stream in the middle of an event
+    Truncate { at: u64 },
+    /// Timestamp of one event this many ticks early, as a glitching timer
+    Backwards { at: u64, ticks: u32 },
+}
+
+impl FaultSpec {
+    fn at(&self) -> u64 {
+        match self {
+            FaultSpec::Drop { at, .. }
+            | FaultSpec::Restart { at }
+            | FaultSpec::Corrupt { at, .. }
+            | FaultSpec::Truncate { at }
+            | FaultSpec::Backwards { at, .. } => *at,
+        }
+    }
+}
+
+fn default_frequency() -> u32 {
+    1_000_000
+}
+
+fn default_tick_rate() -> u32 {
+    1000
+}
+
+fn default_timer_period() -> u32 {
+    u32::MAX
+}
+
+const SYMBOL_SIZE: usize = 32;
+const STATE_COUNT: usize = 3;
+const FORMAT_VERSION: u16 = 14;
+/// Address of the first object, the handles are object addresses
+const OBJECT_BASE: u32 = 0x2000_0000;
+/// Address of the first heap allocation
+const HEAP_BASE: u32 = 0x2001_0000;
+/// The user event ID counts the channel and the arguments, IDs from 0x98 are fixed user events
+const MAX_USER_EVENT_ARGS: usize = 6;
+
+const TASK_CREATE: u16 = 0x10;
+const DEFINE_ISR: u16 = 0x07;
+const TASK_READY: u16 = 0x30;
+const ISR_BEGIN: u16 = 0x33;
+const ISR_RESUME: u16 = 0x34;
+const TASK_ACTIVATE: u16 = 0x37;
+const MEMORY_ALLOC: u16 = 0x38;
+const MEMORY_FREE: u16 = 0x39;
+const TASK_DELAY: u16 = 0x7A;
+const USER_EVENT: u16 = 0x90;
+const UNUSED_STACK: u16 = 0xEB;
+
+impl TraceSpec {
+    /// Load a description, YAML when the extension is `.yaml` or `.yml`, TOML otherwise
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)?;
+        let is_yaml = path
+            .extension()
+            .map(|ext| ext == "yaml" || ext == "yml")
+            .unwrap_or(false);
+        let spec: Self = if is_yaml {
+            serde_norway::from_str(&content)?
+        } else {
+            toml::from_str(&content)?
+        };
+        spec.check()?;
+        Ok(spec)
+    }
+
+    fn check(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.frequency == 0 || self.tick_rate == 0 {
+            return Err("The timer frequency and tick rate must be non-zero".into());
+        }
+        if let Some(id) = self.custom_printf_event_id {
+            if !matches!(EventType::from(EventId(id)), EventType::Unknown(_)) || id > 0x0F_FF {
+                return Err(format!(
+                    "Custom printf event ID 0x{id:X} must be an unknown 12-bit event ID"
+                )
+                .into());
+            }
+        }
+        let mut names = BTreeSet::new();
+        let objects = self
+            .tasks
+            .iter()
+            .map(|t| (&t.name, t.period, t.execution))
+            .chain(self.isrs.iter().map(|i| (&i.name, i.period, i.execution)));
+        for (name, period, [min, max]) in objects {
+            if name.is_empty() || name.len() >= SYMBOL_SIZE {
+                return Err(
+                    format!("Name '{name}' must be 1 to {} bytes long", SYMBOL_SIZE - 1).into(),
+                );
+            }
+            if !names.insert(name) || name == "IDLE" {
+                return Err(format!("Duplicate name '{name}'").into());
+            }
+            if period == 0 {
+                return Err(format!("'{name}' has a zero period").into());
+            }
+            if min > max {
+                return Err(format!("'{name}' has an empty execution time range").into());
+            }
+        }
+        for obj in self.objects.iter() {
+            let name = &obj.name;
+            if name.is_empty() || name.len() >= SYMBOL_SIZE {
+                return Err(
+                    format!("Name '{name}' must be 1 to {} bytes long", SYMBOL_SIZE - 1).into(),
+                );
+            }
+            if !names.insert(name) || name == "IDLE" {
+                return Err(format!("Duplicate name '{name}'").into());
+            }
+        }
+        for (task, wait) in self
+            .tasks
+            .iter()
+            .filter_map(|t| t.wait.as_ref().map(|w| (&t.name, w)))
+        {
+            if !self.objects.iter().any(|o| o.name == wait.object) {
+                return Err(format!("'{task}' waits on unknown object '{}'", wait.object).into());
+            }
+            if wait.ticks == 0 || wait.timeout_every == Some(0) {
+                return Err(format!("'{task}' has a zero wait time or timeout interval").into());
+            }
+        }
+        for ev in self.user_events.iter() {
+            if !self.tasks.iter().any(|t| t.name == ev.task) {
+                return Err(format!("User event of unknown task '{}'", ev.task).into());
+            }
+            if ev.channel.is_empty() || ev.channel.len() >= SYMBOL_SIZE {
+                return Err(format!(
+                    "Channel '{}' must be 1 to {} bytes long",
+                    ev.channel,
+                    SYMBOL_SIZE - 1
+                )
+                .into());
+            }
+            if ev.args.len() > MAX_USER_EVENT_ARGS {
+                return Err(format!(
+                    "User event '{}' has more than {MAX_USER_EVENT_ARGS} arguments",
+                    ev.format
+                )
+                .into());
+            }
+            if 1 + ev.args.len() + string_words(&ev.format).len() / 4 > 0xF {
+                return Err(format!("User event '{}' doesn't fit in an event", ev.format).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the recording, returns the number of events
+    pub fn generate<W: Write>(&self, w: &mut W) -> Result<u64, Box<dyn std::error::Error>> {
+        self.check()?;
+        let mut sim = Simulation::new(self, w);
+        sim.run()?;
+        Ok(sim.out.events)
+    }
+
+    fn preamble(&self) -> Vec<u8> {
+        let endianness = self.endianness();
+        let mut b = Vec::with_capacity(crate::psf::PREAMBLE_SIZE);
+        let psf_word = match endianness {
+            Endianness::Little => HeaderInfo::PSF_LITTLE_ENDIAN,
+            Endianness::Big => HeaderInfo::PSF_BIG_ENDIAN,
+        };
+        b.extend_from_slice(&psf_word.to_le_bytes());
+        b.extend_from_slice(&u16_bytes(endianness, FORMAT_VERSION));
+        // TRACE_KERNEL_VERSION, its nibbles encode the byte order
+        let (kernel_version, platform_cfg): (u16, &[u8]) = match self.kernel_port {
+            KernelPort::FreeRtos => (0x1AA1, b"FreeRTOS"),
+            KernelPort::Zephyr => (0x9AA9, b"Zephyr"),
+            KernelPort::ThreadX => (0xEAAE, b"ThreadX"),
+        };
+        b.extend_from_slice(&kernel_version.to_le_bytes());
+        // Options, cores and ISR tail chaining threshold
+        for v in [0, 1, 0] {
+            b.extend_from_slice(&u32_bytes(endianness, v));
+        }
+        // Platform config version 1.0.0 and name
+        b.extend_from_slice(&u16_bytes(endianness, 0));
+        b.extend_from_slice(&[0, 1]);
+        let mut cfg = platform_cfg.to_vec();
+        cfg.resize(8, 0);
+        b.extend_from_slice(&cfg);
+
+        // Free running 32-bit incrementing timer, period, frequency, wraparounds,
+        // OS tick rate, latest timestamp and OS tick count
+        for v in [
+            1,
+            self.timer_period,
+            self.frequency,
+            0,
+            self.tick_rate,
+            0,
+            0,
+        ] {
+            b.extend_from_slice(&u32_bytes(endianness, v));
+        }
+        b
+    }
+
+    fn endianness(&self) -> Endianness {
+        match self.endianness {
+            ByteOrder::Little => Endianness::Little,
+            ByteOrder::Big => Endianness::Big,
+        }
+    }
+}
+
+fn u16_bytes(endianness: Endianness, v: u16) -> [u8; 2] {
+    match endianness {
+        Endianness::Little => v.to_le_bytes(),
+        Endianness::Big => v.to_be_bytes(),
+    }
+}
+
+fn u32_bytes(endianness: Endianness, v: u32) -> [u8; 4] {
+    match endianness {
+        Endianness::Little => v.to_le_bytes(),
+        Endianness::Big => v.to_be_bytes(),
+    }
+}
+
+/// String parameter, NUL terminated and padded to whole words
+fn string_words(s: &str) -> Vec<u8> {
+    let mut b = s.as_bytes().to_vec();
+    b.push(0);
+    b.resize(b.len().div_ceil(4) * 4, 0);
+    b
+}
+
+/// Xorshift generator, good enough for execution times and garbage bytes
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed ^ 0x9E37_79B9_7F4A_7C15)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn range(&mut self, [min, max]: [u64; 2]) -> u64 {
+        min + self.next() % (max - min + 1)
+    }
+}
+
+/// Writes the events, with the faults injected
+struct Output<'a, W> {
+    w: &'a mut W,
+    endianness: Endianness,
+    header: SessionHeader,
+    faults: Vec<FaultSpec>,
+    event_count: u16,
+    events: u64,
+    truncate: bool,
+    /// Ticks the next timestamp is early
+    backwards: u32,
+    done: bool,
+}
+
+impl<W: Write> Output<'_, W> {
+    fn event(
+        &mut self,
+        rng: &mut Rng,
+        heap: &Heap,
+        time: u64,
+        id: u16,
+        num_params: usize,
+        body: &[u8],
+    ) -> std::io::Result<()> {
+        if self.done {
+            return Ok(());
+        }
+        while self.faults.first().map(|f| f.at() <= time).unwrap_or(false) {
+            match self.faults.remove(0) {
+                FaultSpec::Drop { count, .. } => {
+                    self.event_count = self.event_count.wrapping_add(count)
+                }
+                FaultSpec::Restart { .. } => {
+                    self.header.update_heap(heap);
+                    self.header.write(self.w)?;
+                    self.event_count = 0;
+                }
+                FaultSpec::Corrupt { bytes, .. } => {
+                    let garbage: Vec<u8> = (0..bytes).map(|_| rng.next() as u8).collect();
+                    self.w.write_all(&garbage)?;
+                }
+                FaultSpec::Truncate { .. } => self.truncate = true,
+                FaultSpec::Backwards { ticks, .. } => self.backwards = ticks,
+            }
+        }
+
+        self.event_count = self.event_count.wrapping_add(1);
+        let mut b = Vec::with_capacity(8 + body.len());
+        b.extend_from_slice(&u16_bytes(
+            self.endianness,
+            ((num_params as u16) << 12) | id,
+        ));
+        b.extend_from_slice(&u16_bytes(self.endianness, self.event_count));
+        let timestamp = (time as u32).wrapping_sub(std::mem::take(&mut self.backwards));
+        b.extend_from_slice(&u32_bytes(self.endianness, timestamp));
+        b.extend_from_slice(body);
+
+        if self.truncate {
+            b.truncate(b.len() / 2);
+            self.done = true;
+        } else {
+            self.events += 1;
+        }
+        self.w.write_all(&b)
+    }
+}
+
+struct TaskState {
+    handle: u32,
+    next_release: u64,
+    /// Released runs not yet finished
+    pending: u32,
+    /// Execution time left of the current run, zero when it hasn't started
+    remaining: u64,
+    /// Address of the allocation of the current run
+    allocation: Option<u32>,
+    /// End of the wait of the current run, while blocked
+    blocked_until: Option<u64>,
+    /// The current run has blocked, its call returns when it's dispatched again
+    waited: bool,
+    /// Number of waits that returned
+    waits: u32,
+}
+
+struct IsrState {
+    handle: u32,
+    next_arrival: u64,
+    pending: u32,
+    remaining: u64,
+}
+
+/// Fixed priority, preemptive, single core scheduler
+struct Simulation<'a, W> {
+    spec: &'a TraceSpec,
+    out: Output<'a, W>,
+    rng: Rng,
+    heap: Heap,
+    next_address: u32,
+    idle: u32,
+    channels: BTreeMap<&'a str, u32>,
+    objects: BTreeMap<&'a str, (u32, ObjectKind)>,
+    tasks: Vec<TaskState>,
+    isrs: Vec<IsrState>,
+    /// Running task, IDLE when none
+    current: Option<usize>,
+    /// Active ISRs, outermost first
+    isr_stack: Vec<usize>,
+    now: u64,
+}
+
+impl<'a, W: Write> Simulation<'a, W> {
+    fn new(spec: &'a TraceSpec, w: &'a mut W) -> Self {
+        let mut next_handle = OBJECT_BASE;
+        let mut handle = || {
+            next_handle += 0x100;
+            next_handle
+        };
+        let mut entries = Vec::new();
+        let mut entry = |address: u32, priority: u32, symbol: &str| {
+            let mut states = vec![0; STATE_COUNT];
+            states[0] = priority;
+            entries.push(HeaderEntry {
+                address,
+                states,
+                options: 0,
+                symbol: symbol.as_bytes().to_vec(),
+            });
+        };
+
+        let idle = handle();
+        entry(idle, 0, "IDLE");
+        let tasks = spec
+            .tasks
+            .iter()
+            .map(|t| {
+                let h = handle();
+                entry(h, t.priority, &t.name);
+                TaskState {
+                    handle: h,
+                    next_release: t.offset,
+                    pending: 0,
+                    remaining: 0,
+                    allocation: None,
+                    blocked_until: None,
+                    waited: false,
+                    waits: 0,
+                }
+            })
+            .collect();
+        let isrs = spec
+            .isrs
+            .iter()
+            .map(|i| {
+                let h = handle();
+                entry(h, i.priority, &i.name);
+                IsrState {
+                    handle: h,
+                    next_arrival: i.offset,
+                    pending: 0,
+                    remaining: 0,
+                }
+            })
+            .collect();
+        let mut channels = BTreeMap::new();
+        for ev in spec.user_events.iter() {
+            channels.entry(ev.channel.as_str()).or_insert_with(|| {
+                let h = handle();
+                entry(h, 0, &ev.channel);
+                h
+            });
+        }
+        let objects = spec
+            .objects
+            .iter()
+            .map(|o| {
+                let h = handle();
+                entry(h, 0, &o.name);
+                (o.name.as_str(), (h, o.kind))
+            })
+            .collect();
+        let heap = Heap {
+            current: 0,
+            high_water_mark: 0,
+            max: spec.heap_size.unwrap_or(0),
+        };
+        if spec.heap_size.is_some() {
+            let h = handle();
+            entry(h, 0, "System Heap");
+            if let Some(e) = entries.last_mut() {
+                e.states = vec![heap.current, heap.high_water_mark, heap.max];
+            }
+        }
+
+        let header = SessionHeader {
+            endianness: spec.endianness(),
+            preamble: spec.preamble(),
+            symbol_size: SYMBOL_SIZE,
+            state_count: STATE_COUNT,
+            entries,
+        };
+        let mut faults = spec.faults.clone();
+        faults.sort_by_key(|f| f.at());
+
+        Self {
+            spec,
+            out: Output {
+                w,
+                endianness: spec.endianness(),
+                header,
+                faults,
+                event_count: 0,
+                events: 0,
+                truncate: false,
+                backwards: 0,
+                done: false,
+            },
+            rng: Rng::new(spec.seed),
+            heap,
+            next_address: HEAP_BASE,
+            idle,
+            channels,
+            objects,
+            tasks,
+            isrs,
+            current: None,
+            isr_stack: Vec::new(),
+            now: 0,
+        }
+    }
+
+    /// Event with 32-bit parameters and an optional string parameter
+    fn event(&mut self, id: u16, params: &[u32], string: Option<&str>) -> std::io::Result<()> {
+        let endianness = self.out.endianness;
+        let mut body: Vec<u8> = params
+            .iter()
+            .flat_map(|p| u32_bytes(endianness, *p))
+            .collect();
+        body.extend(string.map(string_words).unwrap_or_default());
+        self.out.event(
+            &mut self.rng,
+            &self.heap,
+            self.now,
+            id,
+            body.len() / 4,
+            &body,
+        )
+    }
+
+    /// Custom printf event: channel, argument and format string lengths, arguments and
+    /// format string, the parameter count of the event code is zero
+    fn custom_printf(&mut self, id: u16, ev: &UserEventSpec) -> std::io::Result<()> {
+        let endianness = self.out.endianness;
+        let format = string_words(&ev.format);
+        let mut body = u32_bytes(endianness, self.channels[ev.channel.as_str()]).to_vec();
+        body.extend_from_slice(&u16_bytes(endianness, ev.args.len() as u16));
+        body.extend_from_slice(&u16_bytes(endianness, format.len() as u16));
+        body.extend(ev.args.iter().flat_map(|a| u32_bytes(endianness, *a)));
+        body.extend(format);
+        self.out
+            .event(&mut self.rng, &self.heap, self.now, id, 0, &body)
+    }
+
+    fn run(&mut self) -> std::io::Result<()> {
+        self.out.header.write(self.out.w)?;
+        for i in 0..self.tasks.len() {
+            let (h, p) = (self.tasks[i].handle, self.spec.tasks[i].priority);
+            self.event(TASK_CREATE, &[h, p], None)?;
+        }
+        for i in 0..self.isrs.len() {
+            let (h, isr) = (self.isrs[i].handle, &self.spec.isrs[i]);
+            self.event(DEFINE_ISR, &[h, isr.priority], Some(&isr.name))?;
+        }
+        for obj in self.spec.objects.iter() {
+            let (h, kind) = self.objects[obj.name.as_str()];
+            // Queue length, unused for semaphores and mutexes
+            let arg = u32::from(matches!(kind, ObjectKind::Queue));
+            self.event(kind.event_ids()[0], &[h, arg], None)?;
+        }
+        self.event(TASK_ACTIVATE, &[self.idle, 0], None)?;
+
+        loop {
+            let running_end = if let Some(&i) = self.isr_stack.last() {
+                Some(self.now + self.isrs[i].remaining)
+            } else {
+                self.current.map(|t| self.now + self.tasks[t].remaining)
+            };
+            let next = self
+                .tasks
+                .iter()
+                .map(|t| t.next_release)
+                .chain(self.isrs.iter().map(|i| i.next_arrival))
+                .chain(self.tasks.iter().filter_map(|t| t.blocked_until))
+                .chain(running_end)
+                .min()
+                .unwrap_or(u64::MAX);
+            if next >= self.spec.duration || self.out.done {
+                break;
+            }
+            let elapsed = next - self.now;
+            if let Some(&i) = self.isr_stack.last() {
+                self.isrs[i].remaining -= elapsed;
+            } else if let Some(t) = self.current {
+                self.tasks[t].remaining -= elapsed;
+            }
+            self.now = next;
+            self.step()?;
+        }
+        self.out.w.flush()
+    }
+
+    /// Finish the runs, release the arrivals and dispatch at the current time
+    fn step(&mut self) -> std::io::Result<()> {
+        let mut isr_returned = false;
+        if let Some(&i) = self.isr_stack.last() {
+            if self.isrs[i].remaining == 0 {
+                self.isr_stack.pop();
+                self.isrs[i].pending -= 1;
+                isr_returned = true;
+            }
+        } else if let Some(t) = self.current {
+            if self.tasks[t].remaining == 0 {
+                self.finish_task(t)?;
+            }
+        }
+
+        for i in 0..self.isrs.len() {
+            if self.isrs[i].next_arrival == self.now {
+                self.isrs[i].pending += 1;
+                self.isrs[i].next_arrival += self.spec.isrs[i].period;
+            }
+        }
+        for t in 0..self.tasks.len() {
+            if self.tasks[t].next_release == self.now {
+                if self.tasks[t].pending == 0 {
+                    self.event(TASK_READY, &[self.tasks[t].handle], None)?;
+                }
+                self.tasks[t].pending += 1;
+                self.tasks[t].next_release += self.spec.tasks[t].period;
+            }
+            if self.tasks[t].blocked_until == Some(self.now) {
+                self.tasks[t].blocked_until = None;
+                self.event(TASK_READY, &[self.tasks[t].handle], None)?;
+            }
+        }
+
+        // Highest priority ISR waiting, if it preempts the running one
+        let top_priority = self.isr_stack.last().map(|&i| self.spec.isrs[i].priority);
+        let waiting = (0..self.isrs.len())
+            .filter(|i| self.isrs[*i].pending > 0 && !self.isr_stack.contains(i))
+            .filter(|i| {
+                top_priority
+                    .map(|p| self.spec.isrs[*i].priority > p)
+                    .unwrap_or(true)
+            })
+            .max_by_key(|i| (self.spec.isrs[*i].priority, std::cmp::Reverse(*i)));
+        if let Some(i) = waiting {
+            self.isr_stack.push(i);
+            self.isrs[i].remaining = self.rng.range(self.spec.isrs[i].execution).max(1);
+            return self.event(ISR_BEGIN, &[self.isrs[i].handle], None);
+        }
+        if let Some(&i) = self.isr_stack.last() {
+            if isr_returned {
+                self.event(ISR_RESUME, &[self.isrs[i].handle], None)?;
+            }
+            return Ok(());
+        }
+
+        // Highest priority task ready, the running one keeps the core on a tie.
+        // Dispatch again when the task blocks at the start of its run.
+        let mut switch = isr_returned;
+        loop {
+            let ready = (0..self.tasks.len())
+                .filter(|t| self.tasks[*t].pending > 0 && self.tasks[*t].blocked_until.is_none())
+                .max_by_key(|t| {
+                    (
+                        self.spec.tasks[*t].priority,
+                        Some(*t) == self.current,
+                        std::cmp::Reverse(*t),
+                    )
+                });
+            if ready != self.current || switch {
+                self.current = ready;
+                let (handle, priority) = match ready {
+                    Some(t) => (self.tasks[t].handle, self.spec.tasks[t].priority),
+                    None => (self.idle, 0),
+                };
+                self.event(TASK_ACTIVATE, &[handle, priority], None)?;
+            }
+            switch = false;
+            match ready {
+                Some(t) if self.tasks[t].remaining == 0 && self.start_task(t)? => (),
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Start a run of the task, returns true when it blocks first
+    fn start_task(&mut self, t: usize) -> std::io::Result<bool> {
+        let spec = &self.spec.tasks[t];
+        if let Some(wait) = &spec.wait {
+            let (handle, kind) = self.objects[wait.object.as_str()];
+            let [_, take, block, failed] = kind.event_ids();
+            let timeout = self.os_ticks(wait.ticks) as u32;
+            if !self.tasks[t].waited {
+                self.tasks[t].waited = true;
+                self.tasks[t].blocked_until = Some(self.now + wait.ticks);
+                self.event(block, &kind.take_params(handle, timeout), None)?;
+                return Ok(true);
+            }
+            self.tasks[t].waits += 1;
+            let timed_out = wait
+                .timeout_every
+                .map(|n| self.tasks[t].waits.is_multiple_of(n))
+                .unwrap_or(false);
+            let id = if timed_out { failed } else { take };
+            self.event(id, &kind.take_params(handle, timeout), None)?;
+        }
+        self.tasks[t].remaining = self.rng.range(spec.execution).max(1);
+        if let Some(size) = spec.alloc {
+            let address = self.next_address;
+            self.next_address = self.next_address.wrapping_add(size.div_ceil(8) * 8);
+            self.heap.current = self.heap.current.saturating_add(size);
+            self.heap.high_water_mark = self.heap.high_water_mark.max(self.heap.current);
+            self.tasks[t].allocation = Some(address);
+            self.event(MEMORY_ALLOC, &[address, size], None)?;
+        }
+        Ok(false)
+    }
+
+    fn finish_task(&mut self, t: usize) -> std::io::Result<()> {
+        let all = self.spec;
+        let spec = &all.tasks[t];
+        let handle = self.tasks[t].handle;
+        for ev in all.user_events.iter().filter(|ev| ev.task == spec.name) {
+            if let Some(id) = all.custom_printf_event_id {
+                self.custom_printf(id, ev)?;
+                continue;
+            }
+            let mut params = vec![self.channels[ev.channel.as_str()]];
+            params.extend_from_slice(&ev.args);
+            self.event(
+                USER_EVENT + 1 + ev.args.len() as u16,
+                &params,
+                Some(&ev.format),
+            )?;
+        }
+        if let Some(low_mark) = spec.stack_low_mark {
+            self.event(UNUSED_STACK, &[handle, low_mark], None)?;
+        }
+        if let (Some(size), false) = (spec.alloc, spec.leak) {
+            if let Some(address) = self.tasks[t].allocation.take() {
+                self.heap.current = self.heap.current.saturating_sub(size);
+                self.event(MEMORY_FREE, &[address, size], None)?;
+            }
+        }
+        self.tasks[t].pending -= 1;
+        self.tasks[t].waited = false;
+        if self.tasks[t].pending == 0 {
+            let os_ticks = self.os_ticks(self.tasks[t].next_release - self.now);
+            self.event(TASK_DELAY, &[os_ticks as u32], None)?;
+        }
+        Ok(())
+    }
+
+    /// Timer ticks to OS ticks, rounded up
+    fn os_ticks(&self, ticks: u64) -> u64 {
+        (ticks * u64::from(self.spec.tick_rate)).div_ceil(u64::from(self.spec.frequency))
+    }
+}
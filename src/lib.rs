@@ -0,0 +1,48 @@
+//! Library behind the `trace-recorder-printer` CLI.
+//!
+//! [`TraceReader`](trace::TraceReader) reads a Percepio TraceRecorder streaming
+//! recording (psf), handling trace restarts, dropped events and timestamp
+//! rollovers, and passes every event to a set of [`Analyzer`](analyzer::Analyzer)s.
+//! The statistics printed by the CLI are computed by the built-in analyzers.
+//!
+//! ```no_run
+//! use std::{fs::File, io::BufReader};
+//! use trace_recorder_printer::analyzer::{Analyzer, ContextAnalyzer};
+//! use trace_recorder_printer::trace::{EventRecord, TraceReader};
+//! use trace_recorder_parser::streaming::{event::EventType, RecorderData};
+//!
+//! #[derive(Default)]
+//! struct QueueSendFailures(u64);
+//!
+//! impl Analyzer for QueueSendFailures {
+//!     fn on_event(&mut self, _rd: &RecorderData, record: &EventRecord) {
+//!         if record.event_type == EventType::QueueSendFailed {
+//!             self.0 += 1;
+//!         }
+//!     }
+//! }
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let f = File::open("trace.psf")?;
+//! let mut reader = TraceReader::new(BufReader::new(f))?;
+//! let mut contexts = ContextAnalyzer::default();
+//! let mut failures = QueueSendFailures::default();
+//! let summary = reader.run(&mut [&mut contexts, &mut failures])?;
+//! println!("{} events, {} queue send failures", summary.total_events, failures.0);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod analyzer;
+pub mod check;
+pub mod elf;
+pub mod event;
+pub mod generate;
+pub mod psf;
+pub mod reader;
+pub mod report;
+pub mod schema;
+pub mod time;
+pub mod timeline;
+pub mod trace;
+pub mod user_event;
@@ -1,32 +1,54 @@
-use clap::Parser;
+use crate::cli::{
+    anonymize::AnonymizeOpts, compare::CompareOpts, ctf::CtfExporter, generate::GenerateOpts,
+    html::HtmlCollector, merge::MergeOpts, split::SplitOpts, validate::ValidateOpts,
+    vcd::VcdExporter, DecodeOpts, TimeRange,
+};
+use clap::{Parser, Subcommand};
 use comfy_table::modifiers::UTF8_ROUND_CORNERS;
 use comfy_table::presets::UTF8_FULL;
 use comfy_table::*;
 use itertools::Itertools;
 use statrs::statistics::Statistics;
-use std::collections::{BTreeMap, HashMap, HashSet};
-use std::{fs::File, io::BufReader, path::PathBuf, time::Duration};
+use std::collections::{BTreeMap, HashMap};
+use std::{fmt, fs::File, io::BufReader, path::PathBuf, time::Duration};
 use trace_recorder_parser::{
     streaming::{
-        event::{Event, EventId, IsrEvent, TaskEvent, TrackingEventCounter},
-        Error, RecorderData,
+        event::{Event, EventId},
+        RecorderData,
     },
     time::{StreamingInstant, Timestamp},
-    types::{ObjectHandle, Priority},
+    types::ObjectHandle,
+};
+use trace_recorder_printer::{
+    analyzer::{
+        Analyzer, BlockReason, ContextAnalyzer, ContextStats, DroppedEventAnalyzer,
+        DroppedEventGap, EventTypeCounter, FoldedStackAnalyzer, MigrationStats, StackAnalyzer,
+        StackStats, TaskState, TaskStateAnalyzer, TaskStateStats, UserEventChannelCounter,
+        WaitStats,
+    },
+    check::{CheckResult, Rules},
+    elf::FirmwareElf,
+    event::{event_object_handle, ContextHandle, TraceEvent},
+    report::{object_name, ContextKind, TraceReport},
+    schema::EventSchema,
+    time::{TrcTimeExt, ONE_SECOND},
+    timeline::Timeline,
+    trace::{EventRecord, SkippedRange, TraceReader},
 };
-use tracing::{error, warn};
+
+mod cli;
 
 #[derive(Parser, Debug, Clone)]
 #[clap(version, about = "Print Percepio TraceRecorder streaming data from file", long_about = None)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Opts {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
     /// Don't print events
     #[clap(long)]
     pub no_events: bool,
 
-    /// Custom printf event ID
-    #[clap(long, value_parser=clap_num::maybe_hex::<u16>)]
-    pub custom_printf_event_id: Option<u16>,
-
     /// Only print user event formatted strings
     #[clap(long, conflicts_with = "no_events")]
     pub user_events: bool,
@@ -35,14 +57,98 @@ pub struct Opts {
     #[clap(long)]
     pub raw_timestamps: bool,
 
+    #[clap(flatten)]
+    pub decode: DecodeOpts,
+
+    /// Evaluate the assertions in the rules file over the statistics,
+    /// exits with a non-zero status (65) when any of them fails
+    #[clap(long, value_name = "rules.toml")]
+    pub check: Option<PathBuf>,
+
+    /// Write a self-contained HTML report with the statistics, charts and events
+    #[clap(long, value_name = "report.html")]
+    pub html: Option<PathBuf>,
+
+    /// Maximum number of events listed in the HTML report
+    #[clap(long, default_value_t = 10000, value_name = "count", requires = "html")]
+    pub html_max_events: usize,
+
+    /// Write the sessions, objects, events, context intervals, user events and
+    /// stack samples to a SQLite database
+    #[cfg(feature = "sqlite")]
+    #[clap(long, value_name = "out.db")]
+    pub sqlite: Option<PathBuf>,
+
+    /// Write the decoded events and the context intervals as Parquet files
+    /// (events.parquet, context_intervals.parquet) to this directory
+    #[cfg(feature = "parquet")]
+    #[clap(long, value_name = "dir")]
+    pub parquet: Option<PathBuf>,
+
+    /// Convert the recording to a CTF 1.8 trace (metadata and one stream per core)
+    /// in this directory, for Babeltrace2 and Trace Compass
+    #[clap(long, value_name = "dir")]
+    pub ctf: Option<PathBuf>,
+
+    /// Write a Value Change Dump with the state of each task and the activity of each ISR,
+    /// for GTKWave
+    #[clap(long, value_name = "out.vcd")]
+    pub vcd: Option<PathBuf>,
+
+    /// Add integer signals with the numeric arguments of the user events on this channel
+    /// to the VCD, can be repeated
+    #[clap(long, value_name = "channel", requires = "vcd")]
+    pub vcd_channel: Vec<String>,
+
+    /// Write the CPU time of each core;ISR-nesting;task stack in the folded-stack
+    /// format used by flamegraph tools, in nanoseconds (ticks if the timer frequency is unknown)
+    #[clap(long, value_name = "out.folded")]
+    pub folded: Option<PathBuf>,
+
+    /// User events on this channel start a named interval, appended to the folded stacks
+    #[clap(long, value_name = "channel", requires = "folded")]
+    pub folded_interval_channel: Option<String>,
+
+    /// Print a plain-text timeline of the contexts, this many columns wide
+    #[clap(long, value_name = "width")]
+    pub gantt: Option<usize>,
+
+    /// Time range of the timeline, in ticks or with a unit, e.g. `2ms..5ms` or `..1s`
+    #[clap(long, value_name = "start..end", requires = "gantt")]
+    pub gantt_range: Option<TimeRange>,
+
     /// Path to streaming data file (psf)
-    #[clap(value_parser)]
-    pub path: PathBuf,
+    #[clap(value_parser, required = true)]
+    pub path: Option<PathBuf>,
+}
+
+impl Opts {
+    /// Whether any of the outputs needs the running intervals of the contexts
+    fn needs_context_intervals(&self) -> bool {
+        let intervals = self.gantt.is_some() || self.html.is_some();
+        #[cfg(feature = "sqlite")]
+        let intervals = intervals || self.sqlite.is_some();
+        #[cfg(feature = "parquet")]
+        let intervals = intervals || self.parquet.is_some();
+        intervals
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    Anonymize(AnonymizeOpts),
+    Compare(CompareOpts),
+    Generate(GenerateOpts),
+    Merge(MergeOpts),
+    Split(SplitOpts),
+    #[cfg(feature = "tui")]
+    Tui(cli::tui::TuiOpts),
+    Validate(ValidateOpts),
 }
 
 fn main() {
     match do_main() {
-        Ok(()) => (),
+        Ok(code) => std::process::exit(code),
         Err(e) => {
             eprintln!("{e}");
             let mut cause = e.source();
@@ -55,189 +161,214 @@ fn main() {
     }
 }
 
-fn do_main() -> Result<(), Box<dyn std::error::Error>> {
+fn do_main() -> Result<exitcode::ExitCode, Box<dyn std::error::Error>> {
     let opts = Opts::parse();
 
     reset_signal_pipe_handler()?;
 
     tracing_subscriber::fmt::init();
 
-    let f = File::open(&opts.path)?;
-    let mut r = BufReader::new(f);
-
-    let mut rd = RecorderData::find(&mut r)?;
-
-    if let Some(custom_printf_event_id) = opts.custom_printf_event_id {
-        rd.set_custom_printf_event_id(custom_printf_event_id.into());
-    }
-
-    if !opts.user_events {
-        println!("Protocol: {}", rd.protocol);
-        println!("Header");
-        println!("  - Endianness: {}", rd.header.endianness);
-        println!("  - Format version: {}", rd.header.format_version);
-        println!("  - Kernel version: {}", rd.header.kernel_version);
-        println!("  - Kernel port: {}", rd.header.kernel_port);
-        println!("  - Options: 0x{:X}", rd.header.options);
-        println!("  - IRQ priority order: {}", rd.header.irq_priority_order);
-        println!("  - Cores: {}", rd.header.num_cores);
-        println!(
-            "  - ISR tail chaining threshold: {}",
-            rd.header.isr_tail_chaining_threshold
-        );
-        println!("  - Platform config: {}", rd.header.platform_cfg);
-        println!(
-            "  - Platform config version: {}",
-            rd.header.platform_cfg_version
-        );
-        println!("Timestamp Info");
-        println!("  - Timer type: {}", rd.timestamp_info.timer_type);
-        println!("  - Timer frequency: {}", rd.timestamp_info.timer_frequency);
-        println!("  - Timer period: {}", rd.timestamp_info.timer_period);
-        println!(
-            "  - Timer wraparounds: {}",
-            rd.timestamp_info.timer_wraparounds
-        );
-        println!("  - OS tick rate Hz: {}", rd.timestamp_info.os_tick_rate_hz);
-        println!(
-            "  - Latest timestamp: {}",
-            rd.timestamp_info.latest_timestamp
-        );
-        println!("  - OS tick count: {}", rd.timestamp_info.os_tick_count);
-    }
-
-    let mut observed_type_counters = BTreeMap::new();
-    let mut user_event_channel_counters = BTreeMap::new();
-    let mut total_count = 0_u64;
-    let mut trace_restart_count = 0_u64;
-    let mut event_counter_tracker = TrackingEventCounter::zero();
-    let mut first_event_observed = false;
-    let mut total_dropped_events = 0_u64;
-    let mut time_tracker = StreamingInstant::zero();
-    let mut context_stats: HashMap<ContextHandle, ContextStats> = Default::default();
-    let mut stack_stats: HashMap<ObjectHandle, StackStats> = Default::default();
-    let mut active_context = ContextHandle::Task(ObjectHandle::NO_TASK);
-    let mut session_timestamps = Vec::new();
-
-    loop {
-        let (event_code, event) = match rd.read_event(&mut r) {
-            Ok(Some((ec, ev))) => (ec, ev),
-            Ok(None) => break,
-            Err(e) => match e {
-                Error::TraceRestarted(psf_start_word_endianness) => {
-                    warn!("Detected a restarted trace stream");
-                    trace_restart_count += 1;
-                    first_event_observed = false;
-                    active_context = ContextHandle::Task(ObjectHandle::NO_TASK);
-                    session_timestamps.push(time_tracker.to_timestamp());
-                    rd = RecorderData::read_with_endianness(psf_start_word_endianness, &mut r)?;
-                    if let Some(custom_printf_event_id) = opts.custom_printf_event_id {
-                        rd.set_custom_printf_event_id(custom_printf_event_id.into());
-                    }
-                    continue;
-                }
-                _ => {
-                    error!("{e}");
-                    continue;
-                }
-            },
-        };
-
-        let dropped_events = if !first_event_observed {
-            event_counter_tracker.set_initial_count(event.event_count());
-            time_tracker = StreamingInstant::new(
-                event.timestamp().ticks() as u32,
-                rd.timestamp_info.timer_wraparounds,
-            );
-            first_event_observed = true;
-            None
-        } else {
-            event_counter_tracker.update(event.event_count())
-        };
-
-        let timestamp = time_tracker.elapsed(event.timestamp());
-
-        let timestamp_dur = rd.convert_timestamp(timestamp).map(Duration::from_nanos);
-
-        let event_type = event_code.event_type();
-        if !opts.no_events && !opts.user_events {
-            if opts.raw_timestamps {
-                print!("[{:08}]", timestamp);
-            } else if let Some(dur) = timestamp_dur {
-                print!("[{}.{:03}] ", dur.as_secs(), dur.subsec_millis());
-            }
-            println!("{event_type} : {event} : {}", event.event_count());
+    match &opts.command {
+        Some(Command::Anonymize(anonymize_opts)) => {
+            cli::anonymize::run(anonymize_opts)?;
+            return Ok(exitcode::OK);
         }
-        if opts.user_events {
-            if let Event::User(user_event) = &event {
-                if opts.raw_timestamps {
-                    print!("[{:08}]", timestamp);
-                } else if let Some(dur) = timestamp_dur {
-                    print!("[{}.{:03}] ", dur.as_secs(), dur.subsec_millis());
-                }
-                println!("{user_event}");
-            }
+        Some(Command::Compare(compare_opts)) => {
+            cli::compare::run(compare_opts)?;
+            return Ok(exitcode::OK);
         }
-
-        if let Event::User(user_event) = &event {
-            *user_event_channel_counters
-                .entry(user_event.channel.to_string())
-                .or_insert(0) += 1_u64;
+        Some(Command::Generate(generate_opts)) => {
+            cli::generate::run(generate_opts)?;
+            return Ok(exitcode::OK);
+        }
+        Some(Command::Merge(merge_opts)) => {
+            cli::merge::run(merge_opts)?;
+            return Ok(exitcode::OK);
+        }
+        Some(Command::Split(split_opts)) => {
+            cli::split::run(split_opts)?;
+            return Ok(exitcode::OK);
         }
+        #[cfg(feature = "tui")]
+        Some(Command::Tui(tui_opts)) => {
+            cli::tui::run(tui_opts)?;
+            return Ok(exitcode::OK);
+        }
+        Some(Command::Validate(validate_opts)) => {
+            return cli::validate::run(validate_opts);
+        }
+        None => (),
+    }
 
-        *observed_type_counters.entry(event_type).or_insert(0) += 1_u64;
-        total_count += 1;
+    let elf = opts.decode.load_elf()?;
+    let schema = opts.decode.load_schema()?;
+    let rules = opts
+        .check
+        .as_ref()
+        .map(|path| Rules::load(path, &schema))
+        .transpose()?;
 
-        if let Some(dropped_events) = dropped_events {
-            warn!(
-                event_count = u16::from(event.event_count()),
-                dropped_events, "Dropped events detected"
-            );
-            total_dropped_events += dropped_events;
-        }
+    // Required unless there's a subcommand
+    let path = opts
+        .path
+        .as_ref()
+        .ok_or("Missing streaming data file path")?;
+    let f = File::open(path)?;
+    let mut reader = TraceReader::new(BufReader::new(f))?;
+    if let Some(elf) = &elf {
+        reader.set_elf(elf);
+    }
+    reader.set_event_schema(&schema);
+    if let Some(custom_printf_event_id) = opts.decode.custom_printf_event_id {
+        reader.set_custom_printf_event_id(custom_printf_event_id);
+    }
+    reader.set_resync(opts.decode.resync);
 
-        // Update active context and stats
-        let maybe_contex: Option<(ContextHandle, Priority)> = match &event {
-            Event::IsrBegin(ev) | Event::IsrResume(ev) => Some((ev.into(), ev.priority)),
-            Event::TaskBegin(ev) | Event::TaskResume(ev) | Event::TaskActivate(ev) => {
-                Some((ev.into(), ev.priority))
-            }
-            _ => None,
-        };
-
-        if let Some((contex_switch_handle, prio)) = maybe_contex {
-            if contex_switch_handle != active_context {
-                // Update runtime stats for the previous context being switched out
-                if let Some(prev_ctx_stats) = context_stats.get_mut(&active_context) {
-                    prev_ctx_stats.switch_out(timestamp);
-                }
+    // Check the timeline range before anything gets printed, the open end is bounded later
+    if let Some(range) = opts.gantt_range {
+        range.ticks(reader.recorder_data(), u64::MAX)?;
+    }
 
-                // Same for the new context being switched in
-                let ctx_stats = context_stats
-                    .entry(contex_switch_handle)
-                    .or_insert_with(|| ContextStats::new(timestamp));
-                ctx_stats.switch_in(timestamp, prio);
+    if !opts.user_events {
+        print_header(reader.recorder_data());
+    }
 
-                active_context = contex_switch_handle;
-            }
-        }
+    let mut printer = EventPrinter {
+        opts: &opts,
+        elf: elf.as_ref(),
+        schema: &schema,
+    };
+    let mut type_counter = EventTypeCounter::default();
+    let mut channel_counter = UserEventChannelCounter::default();
+    let mut html_collector = opts
+        .html
+        .as_ref()
+        .map(|_| HtmlCollector::new(&schema, elf.as_ref(), opts.html_max_events));
+    #[cfg(feature = "sqlite")]
+    let mut sqlite_exporter = opts
+        .sqlite
+        .as_ref()
+        .map(|path| cli::sqlite::SqliteExporter::create(path, &schema, elf.as_ref()))
+        .transpose()?;
+    #[cfg(feature = "parquet")]
+    let mut parquet_exporter = opts
+        .parquet
+        .as_ref()
+        .map(|dir| cli::columnar::ParquetExporter::create(dir, &schema, elf.as_ref()))
+        .transpose()?;
+    let mut ctf_exporter = opts
+        .ctf
+        .as_ref()
+        .map(|dir| CtfExporter::create(dir, &schema, elf.as_ref()))
+        .transpose()?;
+    let mut vcd_exporter = opts
+        .vcd
+        .is_some()
+        .then(|| VcdExporter::new(elf.as_ref(), &opts.vcd_channel));
+    let mut folded_analyzer = FoldedStackAnalyzer::new(elf.as_ref());
+    if let Some(channel) = &opts.folded_interval_channel {
+        folded_analyzer.set_interval_channel(channel);
+    }
+    let mut context_analyzer = if opts.needs_context_intervals() {
+        ContextAnalyzer::with_intervals()
+    } else {
+        ContextAnalyzer::default()
+    };
+    let mut stack_analyzer = StackAnalyzer::default();
+    let mut task_state_analyzer = TaskStateAnalyzer::default();
+    let mut dropped_event_analyzer = DroppedEventAnalyzer::default();
+    let mut analyzers: Vec<&mut dyn Analyzer> = vec![
+        &mut printer,
+        &mut type_counter,
+        &mut channel_counter,
+        &mut context_analyzer,
+        &mut stack_analyzer,
+        &mut task_state_analyzer,
+        &mut dropped_event_analyzer,
+    ];
+    if let Some(html_collector) = html_collector.as_mut() {
+        analyzers.push(html_collector);
+    }
+    if opts.folded.is_some() {
+        analyzers.push(&mut folded_analyzer);
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(sqlite_exporter) = sqlite_exporter.as_mut() {
+        analyzers.push(sqlite_exporter);
+    }
+    #[cfg(feature = "parquet")]
+    if let Some(parquet_exporter) = parquet_exporter.as_mut() {
+        analyzers.push(parquet_exporter);
+    }
+    if let Some(ctf_exporter) = ctf_exporter.as_mut() {
+        analyzers.push(ctf_exporter);
+    }
+    if let Some(vcd_exporter) = vcd_exporter.as_mut() {
+        analyzers.push(vcd_exporter);
+    }
+    let summary = reader.run(&mut analyzers)?;
+    drop(analyzers);
 
-        // Update stack stats
-        if let Event::UnusedStack(stack_event) = &event {
-            let stats = stack_stats
-                .entry(stack_event.handle)
-                .or_insert_with(|| StackStats::new(stack_event.low_mark));
-            stats.update(stack_event.low_mark);
-        }
+    let rd = reader.recorder_data();
+    let report = (rules.is_some() || opts.html.is_some()).then(|| {
+        TraceReport::new(
+            rd,
+            &summary,
+            &context_analyzer,
+            &stack_analyzer,
+            &type_counter,
+            elf.as_ref(),
+            Some(&schema),
+        )
+    });
+    #[cfg(feature = "sqlite")]
+    if let Some(sqlite_exporter) = sqlite_exporter {
+        sqlite_exporter.close(rd, &context_analyzer.intervals)?;
+    }
+    #[cfg(feature = "parquet")]
+    if let Some(parquet_exporter) = parquet_exporter {
+        parquet_exporter.close(rd, &context_analyzer.intervals)?;
+    }
+    if let Some(ctf_exporter) = ctf_exporter {
+        ctf_exporter.close(rd)?;
+    }
+    if let Some((vcd_exporter, vcd_path)) = vcd_exporter.zip(opts.vcd.as_ref()) {
+        vcd_exporter.write(vcd_path, rd)?;
+    }
+    if let Some(folded_path) = &opts.folded {
+        write_folded_stacks(folded_path, rd, &folded_analyzer)?;
+    }
+    let check_results = rules
+        .zip(report.as_ref())
+        .map(|(rules, report)| rules.evaluate(report));
+    if let (Some(html_path), Some(html_collector), Some(report)) =
+        (&opts.html, &html_collector, &report)
+    {
+        let timeline = Timeline::new(
+            rd,
+            elf.as_ref(),
+            &context_analyzer.intervals,
+            summary.total_time,
+        );
+        cli::html::write(
+            html_path,
+            &path.display().to_string(),
+            rd,
+            elf.as_ref(),
+            report,
+            &timeline,
+            html_collector,
+        )?;
     }
 
     if opts.user_events {
-        return Ok(());
+        return Ok(check_results
+            .map(print_check_report)
+            .unwrap_or(exitcode::OK));
     }
     println!();
 
-    session_timestamps.push(time_tracker.to_timestamp());
-    let total_time_ticks: Timestamp = session_timestamps.into_iter().sum();
+    let total_time_ticks = summary.total_time;
 
     let rows: Vec<Vec<Cell>> = rd
         .entry_table
@@ -250,9 +381,11 @@ fn do_main() -> Result<(), Box<dyn std::error::Error>> {
                 "".to_owned()
             };
             let entry_sym = if let Some(s) = &entry.symbol {
-                s.as_ref()
+                s.to_string()
             } else {
-                ""
+                elf.as_ref()
+                    .and_then(|elf| elf.resolve(u32::from(*handle)))
+                    .unwrap_or_default()
             };
 
             vec![
@@ -280,7 +413,9 @@ fn do_main() -> Result<(), Box<dyn std::error::Error>> {
     println!("{table}");
     println!();
 
-    let rows: Vec<Vec<Cell>> = observed_type_counters
+    let total_count = type_counter.total;
+    let rows: Vec<Vec<Cell>> = type_counter
+        .counts
         .into_iter()
         .sorted_by_key(|t| t.1)
         .map(|(t, count)| {
@@ -289,7 +424,12 @@ fn do_main() -> Result<(), Box<dyn std::error::Error>> {
                 Cell::new(count),
                 Cell::new(format!("{percentage:.01}")),
                 Cell::new(format!("0x{:03X}", EventId::from(t))),
-                Cell::new(t),
+                Cell::new(
+                    schema
+                        .name(t)
+                        .map(|n| n.to_owned())
+                        .unwrap_or_else(|| t.to_string()),
+                ),
             ]
         })
         .collect();
@@ -311,8 +451,9 @@ fn do_main() -> Result<(), Box<dyn std::error::Error>> {
     println!();
 
     // TODO user event channel counts
-    let total_user_events: u64 = user_event_channel_counters.values().sum();
-    let rows: Vec<Vec<Cell>> = user_event_channel_counters
+    let total_user_events: u64 = channel_counter.counts.values().sum();
+    let rows: Vec<Vec<Cell>> = channel_counter
+        .counts
         .into_iter()
         .sorted_by_key(|t| t.1)
         .map(|(channel, count)| {
@@ -341,9 +482,220 @@ fn do_main() -> Result<(), Box<dyn std::error::Error>> {
     println!("{table}");
     println!();
 
+    let multi_core = rd.header.num_cores > 1;
+    for (core_id, core_context_stats) in context_analyzer.stats.iter() {
+        if multi_core {
+            println!("Core {core_id}");
+        }
+        print_runtime_table(
+            rd,
+            core_context_stats,
+            &stack_analyzer.stats,
+            total_time_ticks,
+        );
+        print_timing_table(rd, core_context_stats);
+    }
+
+    if multi_core {
+        print_migration_table(rd, &context_analyzer.migrations);
+    }
+    if !task_state_analyzer.stats.is_empty() {
+        print_task_state_table(rd, elf.as_ref(), &schema, &task_state_analyzer.stats);
+        print_blocking_tables(rd, elf.as_ref(), &task_state_analyzer.stats);
+    }
+    if let Some(width) = opts.gantt {
+        let timeline = Timeline::new(
+            rd,
+            elf.as_ref(),
+            &context_analyzer.intervals,
+            total_time_ticks,
+        );
+        let (start, end) = opts
+            .gantt_range
+            .unwrap_or(TimeRange {
+                start: None,
+                end: None,
+            })
+            .ticks(rd, timeline.end)?;
+        print_gantt(rd, &timeline, start, end, width);
+    }
+    if !dropped_event_analyzer.gaps.is_empty() {
+        print_dropped_event_table(rd, elf.as_ref(), &dropped_event_analyzer.gaps);
+    }
+    if !summary.skipped.is_empty() {
+        print_skipped_table(&summary.skipped);
+    }
+    println!("Total events: {}", summary.total_events);
+    println!("Dropped events: {}", summary.dropped_events);
+    if opts.decode.resync {
+        println!(
+            "Skipped bytes: {}",
+            summary.skipped.iter().map(|r| r.len).sum::<u64>()
+        );
+    }
+    println!("Trace restarts: {}", summary.restarts);
+    println!("Total time (ticks): {}", total_time_ticks);
+
+    if let Some(total_time_ns) = rd.convert_timestamp(total_time_ticks) {
+        let total_dur = Duration::from_nanos(total_time_ns);
+        println!("Total time (ns): {}", total_time_ns);
+        println!("Total time: {:?}", total_dur);
+    }
+
+    Ok(check_results
+        .map(print_check_report)
+        .unwrap_or(exitcode::OK))
+}
+
+/// Print the pass/fail report, returns the exit code
+fn print_check_report(results: Vec<CheckResult>) -> exitcode::ExitCode {
+    let failed = results.iter().filter(|r| !r.passed).count();
+    let rows: Vec<Vec<Cell>> = results
+        .iter()
+        .map(|r| {
+            let result = if r.passed {
+                Cell::new("PASS").fg(Color::Green)
+            } else {
+                Cell::new("FAIL")
+                    .fg(Color::Red)
+                    .add_attribute(Attribute::Bold)
+            };
+            vec![result, Cell::new(&r.description), Cell::new(&r.actual)]
+        })
+        .collect();
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["Result", "Rule", "Actual"])
+        .add_rows(rows);
+    table
+        .column_mut(2)
+        .unwrap()
+        .set_cell_alignment(CellAlignment::Right);
+    println!();
+    println!("{table}");
+    println!("Checks: {} passed, {failed} failed", results.len() - failed);
+
+    if failed == 0 {
+        exitcode::OK
+    } else {
+        exitcode::DATAERR
+    }
+}
+
+fn print_header(rd: &RecorderData) {
+    println!("Protocol: {}", rd.protocol);
+    println!("Header");
+    println!("  - Endianness: {}", rd.header.endianness);
+    println!("  - Format version: {}", rd.header.format_version);
+    println!("  - Kernel version: {}", rd.header.kernel_version);
+    println!("  - Kernel port: {}", rd.header.kernel_port);
+    println!("  - Options: 0x{:X}", rd.header.options);
+    println!("  - IRQ priority order: {}", rd.header.irq_priority_order);
+    println!("  - Cores: {}", rd.header.num_cores);
+    println!(
+        "  - ISR tail chaining threshold: {}",
+        rd.header.isr_tail_chaining_threshold
+    );
+    println!("  - Platform config: {}", rd.header.platform_cfg);
+    println!(
+        "  - Platform config version: {}",
+        rd.header.platform_cfg_version
+    );
+    println!("Timestamp Info");
+    println!("  - Timer type: {}", rd.timestamp_info.timer_type);
+    println!("  - Timer frequency: {}", rd.timestamp_info.timer_frequency);
+    println!("  - Timer period: {}", rd.timestamp_info.timer_period);
+    println!(
+        "  - Timer wraparounds: {}",
+        rd.timestamp_info.timer_wraparounds
+    );
+    println!("  - OS tick rate Hz: {}", rd.timestamp_info.os_tick_rate_hz);
+    println!(
+        "  - Latest timestamp: {}",
+        rd.timestamp_info.latest_timestamp
+    );
+    println!("  - OS tick count: {}", rd.timestamp_info.os_tick_count);
+}
+
+/// Prints the event listing, or only the user events
+struct EventPrinter<'a> {
+    opts: &'a Opts,
+    elf: Option<&'a FirmwareElf>,
+    schema: &'a EventSchema,
+}
+
+impl Analyzer for EventPrinter<'_> {
+    fn on_event(&mut self, rd: &RecorderData, record: &EventRecord) {
+        let EventRecord {
+            event_type,
+            event,
+            core_id,
+            event_count,
+            timestamp,
+            dropped_events,
+            ..
+        } = record;
+        let timestamp_dur = rd.convert_timestamp(*timestamp).map(Duration::from_nanos);
+
+        if !self.opts.no_events && !self.opts.user_events {
+            if let Some(dropped) = dropped_events {
+                println!("~~~ {dropped} dropped events before event count {event_count} ~~~");
+            }
+            if self.opts.raw_timestamps {
+                print!("[{:08}]", timestamp);
+            } else if let Some(dur) = timestamp_dur {
+                print!("[{}.{:03}] ", dur.as_secs(), dur.subsec_millis());
+            }
+            if rd.header.num_cores > 1 {
+                print!("[core {core_id}] ");
+            }
+            match self.schema.name(*event_type) {
+                Some(name) => print!("{name} : {event} : {event_count}"),
+                None => print!("{event_type} : {event} : {event_count}"),
+            }
+            if let (Some(elf), TraceEvent::Parsed(event)) = (self.elf, &record.event) {
+                let address = match event {
+                    Event::MemoryAlloc(ev) | Event::MemoryFree(ev) => Some(ev.address),
+                    _ => event_object_handle(event)
+                        .filter(|h| rd.entry_table.symbol(*h).is_none())
+                        .map(u32::from),
+                };
+                if let Some(sym) = address.and_then(|a| elf.resolve(a)) {
+                    print!(" : {sym}");
+                }
+            }
+            println!();
+        }
+        if self.opts.user_events {
+            let user_event: Option<&dyn fmt::Display> = match &record.event {
+                TraceEvent::Parsed(Event::User(ev)) => Some(ev),
+                TraceEvent::ElfUser(ev) => Some(ev),
+                _ => None,
+            };
+            if let Some(user_event) = user_event {
+                if self.opts.raw_timestamps {
+                    print!("[{:08}]", timestamp);
+                } else if let Some(dur) = timestamp_dur {
+                    print!("[{}.{:03}] ", dur.as_secs(), dur.subsec_millis());
+                }
+                println!("{user_event}");
+            }
+        }
+    }
+}
+
+fn print_runtime_table(
+    rd: &RecorderData,
+    context_stats: &HashMap<ContextHandle, ContextStats>,
+    stack_stats: &HashMap<ObjectHandle, StackStats>,
+    total_time_ticks: Timestamp,
+) {
     let rows: Vec<Vec<Cell>> = context_stats
         .iter()
-        .sorted_by_key(|t| t.1.total_runtime.get_raw())
+        .sorted_by_key(|t| (t.1.total_runtime.get_raw(), *t.0))
         .map(|(ctx, stats)| {
             let handle = ctx.object_handle();
             let sym = rd
@@ -367,6 +719,12 @@ fn do_main() -> Result<(), Box<dyn std::error::Error>> {
                 .map(|s| format!("{}/{}", s.low_mark_min, s.low_mark_max))
                 .unwrap_or("".to_string());
 
+            // Flag the runtime that overlapped dropped events
+            let sym = match stats.dropped_event_gaps {
+                0 => sym.to_string(),
+                _ => format!("{sym} *"),
+            };
+
             let total_ns = rd.convert_timestamp(stats.total_runtime).unwrap_or(0);
             let total_dur = Duration::from_nanos(total_ns);
             let percentage = 100.0
@@ -411,11 +769,16 @@ fn do_main() -> Result<(), Box<dyn std::error::Error>> {
         .unwrap()
         .set_cell_alignment(CellAlignment::Left);
     println!("{table}");
+    if context_stats.values().any(|s| s.dropped_event_gaps != 0) {
+        println!("* Ran while events were dropped, the runtime is unreliable");
+    }
     println!();
+}
 
+fn print_timing_table(rd: &RecorderData, context_stats: &HashMap<ContextHandle, ContextStats>) {
     let rows: Vec<Vec<Cell>> = context_stats
         .iter()
-        .sorted_by_key(|t| t.1.total_runtime.get_raw())
+        .sorted_by_key(|t| (t.1.total_runtime.get_raw(), *t.0))
         .map(|(ctx, stats)| {
             let handle = ctx.object_handle();
             let sym = rd
@@ -484,150 +847,391 @@ fn do_main() -> Result<(), Box<dyn std::error::Error>> {
         .set_cell_alignment(CellAlignment::Left);
     println!("{table}");
     println!();
-
-    println!("Total events: {total_count}");
-    println!("Dropped events: {total_dropped_events}");
-    println!("Trace restarts: {trace_restart_count}");
-    println!("Total time (ticks): {}", total_time_ticks);
-
-    if let Some(total_time_ns) = rd.convert_timestamp(total_time_ticks) {
-        let total_dur = Duration::from_nanos(total_time_ns);
-        println!("Total time (ns): {}", total_time_ns);
-        println!("Total time: {:?}", total_dur);
-    }
-
-    Ok(())
 }
 
-// ns
-const ONE_SECOND: u64 = 1_000_000_000;
-
-trait TrcTimeExt {
-    // Return nanoseconds
-    fn convert_timestamp(&self, t: Timestamp) -> Option<u64>;
-}
-
-impl TrcTimeExt for RecorderData {
-    fn convert_timestamp(&self, t: Timestamp) -> Option<u64> {
-        if self.timestamp_info.timer_frequency.is_unitless() {
-            None
-        } else {
-            let ticks_ns = u128::from(t.get_raw()) * u128::from(ONE_SECOND);
-            Some((ticks_ns / u128::from(self.timestamp_info.timer_frequency.get_raw())) as u64)
-        }
+fn print_migration_table(
+    rd: &RecorderData,
+    migration_stats: &HashMap<ContextHandle, MigrationStats>,
+) {
+    let rows: Vec<Vec<Cell>> = migration_stats
+        .iter()
+        .sorted_by_key(|t| (t.1.migrations, *t.0))
+        .map(|(ctx, stats)| {
+            let handle = ctx.object_handle();
+            let sym = rd
+                .entry_table
+                .symbol(handle)
+                .map(|s| s.as_ref())
+                .unwrap_or("");
+            let cores: String = stats
+                .cores
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<String>>()
+                .join(",");
+            vec![
+                Cell::new(handle),
+                Cell::new(sym),
+                Cell::new(cores),
+                Cell::new(stats.migrations),
+            ]
+        })
+        .collect();
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["Handle", "Symbol", "Cores", "Migrations"])
+        .add_rows(rows);
+    for c in table.column_iter_mut() {
+        c.set_cell_alignment(CellAlignment::Right);
     }
+    table
+        .column_mut(1)
+        .unwrap()
+        .set_cell_alignment(CellAlignment::Left);
+    println!("{table}");
+    println!();
 }
 
-// Used to prevent panics on broken pipes.
-// See:
-//   https://github.com/rust-lang/rust/issues/46016#issuecomment-605624865
-fn reset_signal_pipe_handler() -> Result<(), Box<dyn std::error::Error>> {
-    #[cfg(target_family = "unix")]
-    {
-        use nix::sys::signal;
-
-        unsafe {
-            signal::signal(signal::Signal::SIGPIPE, signal::SigHandler::SigDfl)?;
-        }
+/// Print the time each task spent in each state, and why it blocked
+fn print_task_state_table(
+    rd: &RecorderData,
+    elf: Option<&FirmwareElf>,
+    schema: &EventSchema,
+    task_stats: &BTreeMap<ObjectHandle, TaskStateStats>,
+) {
+    let states = [
+        TaskState::Running,
+        TaskState::Ready,
+        TaskState::Blocked,
+        TaskState::Suspended,
+    ];
+    let rows: Vec<Vec<Cell>> = task_stats
+        .iter()
+        .sorted_by_key(|t| t.1.time_in(TaskState::Running).get_raw())
+        .map(|(handle, stats)| {
+            let mut row = vec![Cell::new(handle), Cell::new(object_name(rd, elf, *handle))];
+            for state in states.iter() {
+                let time = stats.time_in(*state);
+                row.push(Cell::new(match rd.convert_timestamp(time) {
+                    Some(ns) => format!("{:?}", Duration::from_nanos(ns)),
+                    None => time.ticks().to_string(),
+                }));
+            }
+            row.push(Cell::new(stats.transitions));
+            let blocked_on = stats
+                .most_frequent_block_reason()
+                .map(|(reason, count)| {
+                    let event = schema
+                        .name(reason.event_type)
+                        .map(|n| n.to_owned())
+                        .unwrap_or_else(|| reason.event_type.to_string());
+                    match reason.object {
+                        Some(h) => format!("{event} {} ({count})", object_name(rd, elf, h)),
+                        None => format!("{event} ({count})"),
+                    }
+                })
+                .unwrap_or_default();
+            row.push(Cell::new(blocked_on));
+            row
+        })
+        .collect();
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec![
+            "Handle",
+            "Symbol",
+            "Running",
+            "Ready",
+            "Blocked",
+            "Suspended",
+            "Transitions",
+            "Most Blocked On",
+        ])
+        .add_rows(rows);
+    for c in table.column_iter_mut() {
+        c.set_cell_alignment(CellAlignment::Right);
     }
-
-    Ok(())
+    for i in [1, 7] {
+        table
+            .column_mut(i)
+            .unwrap()
+            .set_cell_alignment(CellAlignment::Left);
+    }
+    println!("{table}");
+    println!();
 }
 
-type DurationTicks = Timestamp;
+fn print_blocking_tables(
+    rd: &RecorderData,
+    elf: Option<&FirmwareElf>,
+    task_stats: &BTreeMap<ObjectHandle, TaskStateStats>,
+) {
+    let fmt_time = |t: Timestamp| match rd.convert_timestamp(t) {
+        Some(ns) => format!("{:?}", Duration::from_nanos(ns)),
+        None => t.ticks().to_string(),
+    };
+    let object = |reason: &BlockReason| {
+        reason
+            .object
+            .map(|h| object_name(rd, elf, h))
+            .unwrap_or_default()
+    };
 
-#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
-struct StackStats {
-    low_mark_min: u32,
-    low_mark_max: u32,
-}
+    let rows: Vec<Vec<Cell>> = task_stats
+        .iter()
+        .flat_map(|(task, stats)| {
+            stats
+                .waits
+                .iter()
+                .map(move |(reason, waits)| (task, stats, reason, waits))
+        })
+        .sorted_by_key(|t| t.3.total.get_raw())
+        .map(|(task, stats, reason, waits)| {
+            let percentage =
+                100.0 * (waits.total.get_raw() as f64 / stats.total_time().get_raw().max(1) as f64);
+            vec![
+                Cell::new(object_name(rd, elf, *task)),
+                Cell::new(object(reason)),
+                Cell::new(reason.kind()),
+                Cell::new(waits.count),
+                Cell::new(fmt_time(waits.total)),
+                Cell::new(fmt_time(waits.max)),
+                Cell::new(waits.timeouts),
+                Cell::new(format!("{percentage:.02}")),
+            ]
+        })
+        .collect();
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec![
+            "Task",
+            "Blocked On",
+            "Kind",
+            "Waits",
+            "Total",
+            "Max",
+            "Timeouts",
+            "% of Task Time",
+        ])
+        .add_rows(rows);
+    for c in table.column_iter_mut() {
+        c.set_cell_alignment(CellAlignment::Right);
+    }
+    for i in [0, 1, 2] {
+        table
+            .column_mut(i)
+            .unwrap()
+            .set_cell_alignment(CellAlignment::Left);
+    }
+    println!("{table}");
+    println!();
 
-impl StackStats {
-    pub fn new(low_mark: u32) -> Self {
-        Self {
-            low_mark_min: low_mark,
-            low_mark_max: low_mark,
+    let mut objects: BTreeMap<(&'static str, Option<ObjectHandle>), (usize, WaitStats)> =
+        BTreeMap::new();
+    for stats in task_stats.values() {
+        for (reason, waits) in stats.waits.iter() {
+            let (tasks, total) = objects.entry((reason.kind(), reason.object)).or_default();
+            *tasks += 1;
+            total.count += waits.count;
+            total.total += waits.total;
+            total.max = total.max.max(waits.max);
+            total.timeouts += waits.timeouts;
         }
     }
-
-    pub fn update(&mut self, low_mark: u32) {
-        self.low_mark_min = self.low_mark_min.min(low_mark);
-        self.low_mark_max = self.low_mark_max.max(low_mark);
+    let rows: Vec<Vec<Cell>> = objects
+        .iter()
+        .sorted_by_key(|t| t.1 .1.total.get_raw())
+        .map(|((kind, object), (tasks, waits))| {
+            vec![
+                Cell::new(object.map(|h| object_name(rd, elf, h)).unwrap_or_default()),
+                Cell::new(kind),
+                Cell::new(tasks),
+                Cell::new(waits.count),
+                Cell::new(fmt_time(waits.total)),
+                Cell::new(fmt_time(waits.max)),
+                Cell::new(waits.timeouts),
+            ]
+        })
+        .collect();
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec![
+            "Object", "Kind", "Tasks", "Waits", "Total", "Max", "Timeouts",
+        ])
+        .add_rows(rows);
+    for c in table.column_iter_mut() {
+        c.set_cell_alignment(CellAlignment::Right);
     }
+    for i in [0, 1] {
+        table
+            .column_mut(i)
+            .unwrap()
+            .set_cell_alignment(CellAlignment::Left);
+    }
+    println!("{table}");
+    println!();
 }
 
-#[derive(Clone, Debug)]
-struct ContextStats {
-    /// Priorities observed
-    priorities: HashSet<Priority>,
-
-    /// When the context was last switched in
-    last_timestamp: Timestamp,
-
-    /// Total time the context has been in the running state
-    total_runtime: DurationTicks,
-
-    /// Duration (in ticks) of each instance the context was in the running state
-    running_instances: Vec<f64>,
-
-    /// Number of times the context was switched in
-    count: u64,
+fn print_dropped_event_table(
+    rd: &RecorderData,
+    elf: Option<&FirmwareElf>,
+    gaps: &[DroppedEventGap],
+) {
+    let rows: Vec<Vec<Cell>> = gaps
+        .iter()
+        .map(|gap| {
+            let time = match rd.convert_timestamp(gap.end) {
+                Some(ns) => format!("{:?}", Duration::from_nanos(ns)),
+                None => gap.end.ticks().to_string(),
+            };
+            let context = gap
+                .context
+                .map(|ctx| object_name(rd, elf, ctx.object_handle()))
+                .unwrap_or_default();
+            vec![
+                Cell::new(time),
+                Cell::new(gap.core_id),
+                Cell::new(gap.count_before),
+                Cell::new(gap.count_after),
+                Cell::new(gap.dropped),
+                Cell::new(context),
+            ]
+        })
+        .collect();
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec![
+            "Time",
+            "Core",
+            "Count Before",
+            "Count After",
+            "Lost",
+            "Active Context",
+        ])
+        .add_rows(rows);
+    for c in table.column_iter_mut() {
+        c.set_cell_alignment(CellAlignment::Right);
+    }
+    table
+        .column_mut(5)
+        .unwrap()
+        .set_cell_alignment(CellAlignment::Left);
+    println!("{table}");
+    println!();
 }
 
-impl ContextStats {
-    fn new(last_timestamp: Timestamp) -> Self {
-        Self {
-            priorities: Default::default(),
-            last_timestamp,
-            total_runtime: DurationTicks::zero(),
-            running_instances: Default::default(),
-            count: 0,
-        }
+fn print_skipped_table(skipped: &[SkippedRange]) {
+    let rows: Vec<Vec<Cell>> = skipped
+        .iter()
+        .map(|r| {
+            vec![
+                Cell::new(format!("0x{:08X}", r.offset)),
+                Cell::new(format!("0x{:08X}", r.offset + r.len)),
+                Cell::new(r.len),
+            ]
+        })
+        .collect();
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["Skipped Offset", "End", "Bytes"])
+        .add_rows(rows);
+    for c in table.column_iter_mut() {
+        c.set_cell_alignment(CellAlignment::Right);
     }
+    println!("{table}");
+    println!();
+}
 
-    /// Called when this context is switched in
-    fn switch_in(&mut self, last_timestamp: Timestamp, prio: Priority) {
-        self.last_timestamp = last_timestamp;
-        self.count += 1;
-        self.priorities.insert(prio);
+/// Write one `stack weight` line per stack
+fn write_folded_stacks(
+    path: &PathBuf,
+    rd: &RecorderData,
+    folded: &FoldedStackAnalyzer,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut out = String::new();
+    for (stack, ticks) in folded.stacks.iter() {
+        let weight = rd.convert_ticks(*ticks).unwrap_or(*ticks);
+        if weight != 0 {
+            out.push_str(&format!("{stack} {weight}\n"));
+        }
     }
+    std::fs::write(path, out)?;
+    Ok(())
+}
 
-    /// Called when this context is switched out
-    fn switch_out(&mut self, timestamp: Timestamp) {
-        if timestamp < self.last_timestamp {
-            warn!("Stats timestamp went backwards");
+/// Print one line per context, `#` where it ran most of the column, `+` where it ran briefly
+fn print_gantt(rd: &RecorderData, timeline: &Timeline, start: u64, end: u64, width: usize) {
+    let width = width.max(1);
+    let freq = rd.timestamp_info.timer_frequency;
+    let fmt_ticks = |ticks: f64| {
+        if freq.is_unitless() {
+            format!("{ticks:.0} ticks")
         } else {
-            let diff = timestamp - self.last_timestamp;
-            self.total_runtime += diff;
-            self.last_timestamp = timestamp;
-            self.running_instances.push(diff.get_raw() as f64);
+            let ns = ticks * ONE_SECOND as f64 / f64::from(freq.get_raw());
+            format!("{:?}", Duration::from_nanos(ns as u64))
         }
+    };
+    println!(
+        "Timeline {} .. {}, {} per column",
+        fmt_ticks(start as f64),
+        fmt_ticks(end as f64),
+        fmt_ticks((end - start) as f64 / width as f64)
+    );
+
+    let labels: Vec<String> = timeline
+        .rows
+        .iter()
+        .map(|row| match row.kind {
+            ContextKind::Isr => format!("{} (ISR)", row.name),
+            ContextKind::Task => row.name.clone(),
+        })
+        .collect();
+    let label_width = labels.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+    for (row, label) in timeline.rows.iter().zip(labels.iter()) {
+        let bar: String = row
+            .occupancy(start as f64, end as f64, width)
+            .into_iter()
+            .map(|occ| match occ {
+                o if o <= 0.0 => ' ',
+                o if o < 0.5 => '+',
+                _ => '#',
+            })
+            .collect();
+        println!("{label:<label_width$} |{bar}|");
     }
+    println!();
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
-enum ContextHandle {
-    Task(ObjectHandle),
-    Isr(ObjectHandle),
-}
+// Used to prevent panics on broken pipes.
+// See:
+//   https://github.com/rust-lang/rust/issues/46016#issuecomment-605624865
+fn reset_signal_pipe_handler() -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(target_family = "unix")]
+    {
+        use nix::sys::signal;
 
-impl ContextHandle {
-    fn object_handle(self) -> ObjectHandle {
-        match self {
-            ContextHandle::Task(h) => h,
-            ContextHandle::Isr(h) => h,
+        unsafe {
+            signal::signal(signal::Signal::SIGPIPE, signal::SigHandler::SigDfl)?;
         }
     }
-}
-
-impl From<&TaskEvent> for ContextHandle {
-    fn from(event: &TaskEvent) -> Self {
-        ContextHandle::Task(event.handle)
-    }
-}
 
-impl From<&IsrEvent> for ContextHandle {
-    fn from(event: &IsrEvent) -> Self {
-        ContextHandle::Isr(event.handle)
-    }
+    Ok(())
 }
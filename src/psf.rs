@@ -0,0 +1,581 @@
+//! Writing PSF recordings
+
+use crate::user_event::{entry_symbol, string_arg_words};
+use std::io::{self, Read, Write};
+use trace_recorder_parser::{
+    streaming::{event::EventType, EntryTable, HeaderInfo},
+    types::{Endianness, Heap, ObjectHandle},
+};
+
+/// Size of the header info and the timestamp info, the part of the header before the entry table
+pub const PREAMBLE_SIZE: usize = 32 + 28;
+
+/// Entry table symbol of the system heap, its states are the heap counters
+pub const SYSTEM_HEAP_SYMBOL: &str = "System Heap";
+
+/// Hardware timer type of a free running, incrementing 32-bit counter
+const FREE_RUNNING_32_INCR: u32 = 1;
+
+/// PSF header of a session: the header info and timestamp info, kept as recorded,
+/// and the entry table
+#[derive(Clone, Debug)]
+pub struct SessionHeader {
+    pub endianness: Endianness,
+    /// Header info and timestamp info, starting with the PSF word
+    pub preamble: Vec<u8>,
+    pub symbol_size: usize,
+    pub state_count: usize,
+    pub entries: Vec<HeaderEntry>,
+}
+
+/// Slot of the entry table
+#[derive(Clone, Debug)]
+pub struct HeaderEntry {
+    pub address: u32,
+    pub states: Vec<u32>,
+    pub options: u32,
+    /// Symbol bytes, NUL padded or truncated to the symbol size when written
+    pub symbol: Vec<u8>,
+}
+
+impl HeaderEntry {
+    /// Symbol up to the NUL padding
+    pub fn symbol(&self) -> String {
+        let end = self
+            .symbol
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(self.symbol.len());
+        String::from_utf8_lossy(&self.symbol[..end]).into_owned()
+    }
+}
+
+impl SessionHeader {
+    /// Parse the header of a session, starting with the PSF word
+    pub fn parse(mut data: &[u8]) -> io::Result<Self> {
+        let mut preamble = vec![0; PREAMBLE_SIZE];
+        data.read_exact(&mut preamble)?;
+        let endianness =
+            match u32::from_le_bytes([preamble[0], preamble[1], preamble[2], preamble[3]]) {
+                HeaderInfo::PSF_LITTLE_ENDIAN => Endianness::Little,
+                HeaderInfo::PSF_BIG_ENDIAN => Endianness::Big,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Missing PSF word",
+                    ))
+                }
+            };
+
+        let read_u32 = |data: &mut &[u8]| -> io::Result<u32> {
+            let mut b = [0; 4];
+            data.read_exact(&mut b)?;
+            Ok(match endianness {
+                Endianness::Little => u32::from_le_bytes(b),
+                Endianness::Big => u32::from_be_bytes(b),
+            })
+        };
+        let num_entries = read_u32(&mut data)?;
+        let symbol_size = read_u32(&mut data)? as usize;
+        let state_count = read_u32(&mut data)? as usize;
+        let mut entries = Vec::new();
+        for _ in 0..num_entries {
+            let address = read_u32(&mut data)?;
+            let states = (0..state_count)
+                .map(|_| read_u32(&mut data))
+                .collect::<io::Result<Vec<u32>>>()?;
+            let options = read_u32(&mut data)?;
+            let mut symbol = vec![0; symbol_size];
+            data.read_exact(&mut symbol)?;
+            entries.push(HeaderEntry {
+                address,
+                states,
+                options,
+                symbol,
+            });
+        }
+
+        Ok(Self {
+            endianness,
+            preamble,
+            symbol_size,
+            state_count,
+            entries,
+        })
+    }
+
+    /// Set the symbols of the entry table, e.g. the names learned from the events of the session
+    /// so far. Objects missing from the table get a slot with zeroed states.
+    pub fn update_symbols(&mut self, table: &EntryTable) {
+        for (handle, entry) in table.entries().iter() {
+            let Some(symbol) = entry.symbol.as_ref() else {
+                continue;
+            };
+            if *handle == ObjectHandle::NO_TASK {
+                continue;
+            }
+            let address = u32::from(*handle);
+            match self.entries.iter_mut().find(|e| e.address == address) {
+                Some(e) if e.symbol() != symbol.as_ref() => e.symbol = symbol.as_bytes().to_vec(),
+                Some(_) => (),
+                None => self.entries.push(HeaderEntry {
+                    address,
+                    states: vec![0; self.state_count],
+                    options: entry.options,
+                    symbol: symbol.as_bytes().to_vec(),
+                }),
+            }
+        }
+    }
+
+    /// Entry of an object, a slot with zeroed states is added if it's missing
+    pub fn entry(&mut self, address: u32) -> &mut HeaderEntry {
+        let i = match self.entries.iter().position(|e| e.address == address) {
+            Some(i) => i,
+            None => {
+                self.entries.push(HeaderEntry {
+                    address,
+                    states: vec![0; self.state_count],
+                    options: 0,
+                    symbol: Vec::new(),
+                });
+                self.entries.len() - 1
+            }
+        };
+        &mut self.entries[i]
+    }
+
+    /// Set the heap counters of the system heap entry
+    pub fn update_heap(&mut self, heap: &Heap) {
+        if let Some(e) = self
+            .entries
+            .iter_mut()
+            .find(|e| e.symbol() == SYSTEM_HEAP_SYMBOL && e.states.len() >= 3)
+        {
+            e.states[0] = heap.current;
+            e.states[1] = heap.high_water_mark;
+            e.states[2] = heap.max;
+        }
+    }
+
+    /// Set the core count of the header info
+    pub fn set_num_cores(&mut self, num_cores: u32) {
+        self.set_preamble_u32(12, num_cores);
+    }
+
+    /// Make the timer a free running 32-bit counter, that wrapped around this many times
+    /// before the first event. The frequency is kept.
+    pub fn set_free_running_timer(&mut self, wraparounds: u32) {
+        let format_version = match self.endianness {
+            Endianness::Little => u16::from_le_bytes([self.preamble[4], self.preamble[5]]),
+            Endianness::Big => u16::from_be_bytes([self.preamble[4], self.preamble[5]]),
+        };
+        // Timer type, then the frequency and the period, in this order up to version 12
+        let period = if format_version <= 12 { 40 } else { 36 };
+        self.set_preamble_u32(32, FREE_RUNNING_32_INCR);
+        self.set_preamble_u32(period, u32::MAX);
+        self.set_preamble_u32(44, wraparounds);
+    }
+
+    fn set_preamble_u32(&mut self, offset: usize, v: u32) {
+        let b = u32_bytes(self.endianness, v);
+        self.preamble[offset..offset + 4].copy_from_slice(&b);
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let u32_bytes = |v: u32| match self.endianness {
+            Endianness::Little => v.to_le_bytes(),
+            Endianness::Big => v.to_be_bytes(),
+        };
+        w.write_all(&self.preamble)?;
+        w.write_all(&u32_bytes(self.entries.len() as u32))?;
+        w.write_all(&u32_bytes(self.symbol_size as u32))?;
+        w.write_all(&u32_bytes(self.state_count as u32))?;
+        for e in self.entries.iter() {
+            w.write_all(&u32_bytes(e.address))?;
+            for s in e.states.iter() {
+                w.write_all(&u32_bytes(*s))?;
+            }
+            w.write_all(&u32_bytes(e.options))?;
+            let mut symbol = e.symbol.clone();
+            symbol.resize(self.symbol_size, 0);
+            w.write_all(&symbol)?;
+        }
+        Ok(())
+    }
+}
+
+/// Event as recorded: the event code, event count and timestamp, followed by the parameters
+#[derive(Clone, Debug)]
+pub struct RawEvent {
+    pub endianness: Endianness,
+    pub id: u16,
+    /// Parameter count of the event code, in words
+    pub num_params: usize,
+    pub count: u16,
+    pub timestamp: u32,
+    /// Parameters and any data following them
+    pub body: Vec<u8>,
+}
+
+impl RawEvent {
+    pub fn parse(data: &[u8], endianness: Endianness) -> io::Result<Self> {
+        if data.len() < 8 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Event shorter than its header",
+            ));
+        }
+        let u16_at = |i: usize| match endianness {
+            Endianness::Little => u16::from_le_bytes([data[i], data[i + 1]]),
+            Endianness::Big => u16::from_be_bytes([data[i], data[i + 1]]),
+        };
+        let code = u16_at(0);
+        let count = u16_at(2);
+        let timestamp = u32_from(endianness, &data[4..8]);
+        Ok(Self {
+            endianness,
+            id: code & 0x0F_FF,
+            num_params: usize::from(code >> 12),
+            count,
+            timestamp,
+            body: data[8..].to_vec(),
+        })
+    }
+
+    /// Parameter word
+    pub fn param(&self, word: usize) -> Option<u32> {
+        self.body
+            .get(word * 4..word * 4 + 4)
+            .map(|b| u32_from(self.endianness, b))
+    }
+
+    pub fn set_param(&mut self, word: usize, value: u32) {
+        if let Some(b) = self.body.get_mut(word * 4..word * 4 + 4) {
+            b.copy_from_slice(&u32_bytes(self.endianness, value));
+        }
+    }
+
+    /// Half-word at a byte offset of the body
+    pub fn u16_at(&self, offset: usize) -> Option<u16> {
+        let b = self.body.get(offset..offset + 2)?;
+        Some(match self.endianness {
+            Endianness::Little => u16::from_le_bytes([b[0], b[1]]),
+            Endianness::Big => u16::from_be_bytes([b[0], b[1]]),
+        })
+    }
+
+    pub fn set_u16_at(&mut self, offset: usize, value: u16) {
+        if let Some(b) = self.body.get_mut(offset..offset + 2) {
+            b.copy_from_slice(&match self.endianness {
+                Endianness::Little => value.to_le_bytes(),
+                Endianness::Big => value.to_be_bytes(),
+            });
+        }
+    }
+
+    /// NUL terminated string starting at a byte offset of the body
+    pub fn string_at(&self, offset: usize) -> String {
+        let s = self.body.get(offset..).unwrap_or_default();
+        let end = s.iter().position(|b| *b == 0).unwrap_or(s.len());
+        String::from_utf8_lossy(&s[..end]).into_owned()
+    }
+
+    /// Replace the body from a byte offset to its end with a string, NUL terminated and
+    /// padded to whole words. Returns the size of the string in bytes, the parameter count
+    /// is left to the caller.
+    pub fn set_string_at(&mut self, offset: usize, s: &str) -> usize {
+        self.body.truncate(offset);
+        let len = (s.len() + 1).div_ceil(4) * 4;
+        self.body.extend_from_slice(s.as_bytes());
+        self.body.resize(offset + len, 0);
+        len
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        if self.num_params > 0x0F {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Event 0x{:X} has {} parameters, at most 15 fit",
+                    self.id, self.num_params
+                ),
+            ));
+        }
+        let code = ((self.num_params as u16) << 12) | self.id;
+        let (code, count) = match self.endianness {
+            Endianness::Little => (code.to_le_bytes(), self.count.to_le_bytes()),
+            Endianness::Big => (code.to_be_bytes(), self.count.to_be_bytes()),
+        };
+        w.write_all(&code)?;
+        w.write_all(&count)?;
+        w.write_all(&u32_bytes(self.endianness, self.timestamp))?;
+        w.write_all(&self.body)
+    }
+}
+
+/// Where an event keeps its strings and object handles
+#[derive(Copy, Clone, Debug)]
+pub enum EventLayout {
+    /// Object handle, then the name from this word
+    Name { string: usize },
+    /// Channel, arguments, then the format string from this word
+    UserEvent { string: usize },
+    /// Channel, format string handle, arguments
+    FixedUserEvent,
+    /// Channel, argument and format string lengths, arguments, format string
+    CustomPrintf { args: usize },
+    /// Heap block address, size
+    MemoryBlock,
+    /// Parameter words, the first `handles` of them are object handles
+    Other { handles: usize },
+}
+
+impl EventLayout {
+    pub fn of(event_type: EventType, raw: &RawEvent, custom_printf_event_id: Option<u16>) -> Self {
+        match event_type {
+            EventType::ObjectName => EventLayout::Name { string: 1 },
+            EventType::DefineIsr => EventLayout::Name { string: 2 },
+            EventType::UserEvent(_) => {
+                // The ID counts the channel and the arguments, fixed user events reuse the upper IDs
+                let arg_count = usize::from(raw.id - 0x90);
+                if raw.id >= 0x98 && arg_count >= raw.num_params {
+                    EventLayout::FixedUserEvent
+                } else {
+                    EventLayout::UserEvent {
+                        string: 1 + arg_count.saturating_sub(1),
+                    }
+                }
+            }
+            EventType::MemoryAlloc | EventType::MemoryFree => EventLayout::MemoryBlock,
+            EventType::Unknown(_) if custom_printf_event_id == Some(raw.id) => {
+                EventLayout::CustomPrintf {
+                    args: raw.u16_at(4).map(usize::from).unwrap_or(0),
+                }
+            }
+            // Timer configuration, tick counts and events the parser doesn't know
+            EventType::Null
+            | EventType::TsConfig
+            | EventType::TaskDelay
+            | EventType::TaskDelayUntil
+            | EventType::Unknown(_) => EventLayout::Other { handles: 0 },
+            // State machine, then state, or the other way around
+            EventType::StateMachineStateCreate | EventType::StateMachineStateChange => {
+                EventLayout::Other { handles: 2 }
+            }
+            // Task and kernel object events start with the object, followed by priorities,
+            // timeouts, counts or bits
+            _ => EventLayout::Other { handles: 1 },
+        }
+    }
+
+    /// Parameter words holding object handles: the objects, the channels and format string
+    /// handles of user events, and the user event arguments printed with `%s`
+    pub fn handle_words(&self, raw: &RawEvent, entry_table: &EntryTable) -> Vec<usize> {
+        let words = raw.body.len() / 4;
+        let handles = match *self {
+            EventLayout::Name { .. } => vec![0],
+            EventLayout::UserEvent { string } => [0]
+                .into_iter()
+                .chain(
+                    string_arg_words(&raw.string_at(string * 4))
+                        .into_iter()
+                        .map(|w| 1 + w)
+                        .filter(|w| *w < string),
+                )
+                .collect(),
+            EventLayout::FixedUserEvent => {
+                let format = raw
+                    .param(1)
+                    .and_then(|h| entry_symbol(entry_table, h))
+                    .map(string_arg_words)
+                    .unwrap_or_default();
+                [0, 1]
+                    .into_iter()
+                    .chain(format.into_iter().map(|w| 2 + w))
+                    .collect()
+            }
+            EventLayout::CustomPrintf { args } => [0]
+                .into_iter()
+                .chain(
+                    string_arg_words(&raw.string_at(8 + 4 * args))
+                        .into_iter()
+                        .map(|w| 2 + w)
+                        .filter(|w| *w < 2 + args),
+                )
+                .collect(),
+            EventLayout::MemoryBlock => Vec::new(),
+            EventLayout::Other { handles } => (0..handles).collect(),
+        };
+        handles.into_iter().filter(|w| *w < words).collect()
+    }
+}
+
+fn u32_from(endianness: Endianness, b: &[u8]) -> u32 {
+    let b = [b[0], b[1], b[2], b[3]];
+    match endianness {
+        Endianness::Little => u32::from_le_bytes(b),
+        Endianness::Big => u32::from_be_bytes(b),
+    }
+}
+
+fn u32_bytes(endianness: Endianness, v: u32) -> [u8; 4] {
+    match endianness {
+        Endianness::Little => v.to_le_bytes(),
+        Endianness::Big => v.to_be_bytes(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::TraceSpec;
+    use trace_recorder_parser::streaming::event::EventId;
+
+    fn event(id: u16, params: &[u32], string: &str) -> RawEvent {
+        let mut raw = RawEvent {
+            endianness: Endianness::Little,
+            id,
+            num_params: 0,
+            count: 1,
+            timestamp: 100,
+            body: params.iter().flat_map(|p| p.to_le_bytes()).collect(),
+        };
+        if !string.is_empty() {
+            raw.set_string_at(raw.body.len(), string);
+        }
+        raw.num_params = raw.body.len() / 4;
+        raw
+    }
+
+    fn handle_words(raw: &RawEvent, custom_printf_event_id: Option<u16>) -> Vec<usize> {
+        let event_type = EventType::from(EventId(raw.id));
+        EventLayout::of(event_type, raw, custom_printf_event_id)
+            .handle_words(raw, &EntryTable::default())
+    }
+
+    #[test]
+    fn raw_event_round_trip() {
+        for endianness in [Endianness::Little, Endianness::Big] {
+            let raw = RawEvent {
+                endianness,
+                id: 0x60,
+                num_params: 3,
+                count: 1,
+                timestamp: 100,
+                body: [0x2000_0100, 5, 1]
+                    .into_iter()
+                    .flat_map(|p| u32_bytes(endianness, p))
+                    .collect(),
+            };
+            let mut data = Vec::new();
+            raw.write(&mut data).unwrap();
+            assert_eq!(data.len(), 8 + 12);
+
+            let mut parsed = RawEvent::parse(&data, endianness).unwrap();
+            assert_eq!((parsed.id, parsed.num_params), (0x60, 3));
+            assert_eq!((parsed.count, parsed.timestamp), (1, 100));
+            assert_eq!(parsed.param(0), Some(0x2000_0100));
+            assert_eq!(parsed.param(3), None);
+            parsed.set_param(1, 7);
+            assert_eq!(parsed.param(1), Some(7));
+        }
+    }
+
+    #[test]
+    fn raw_event_strings_are_padded() {
+        let mut raw = event(0x03, &[0x2000_0100], "Control");
+        assert_eq!(raw.body.len(), 4 + 8);
+        assert_eq!(raw.string_at(4), "Control");
+
+        assert_eq!(raw.set_string_at(4, "Log"), 4);
+        assert_eq!(raw.body.len(), 4 + 4);
+        assert_eq!(raw.string_at(4), "Log");
+
+        raw.num_params = 16;
+        assert!(raw.write(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn session_header_round_trip() {
+        let spec: TraceSpec = toml::from_str(
+            r#"
+            duration = 1000
+            heap_size = 4096
+            [[task]]
+            name = "Control"
+            priority = 3
+            period = 500
+            execution = [10, 20]
+            "#,
+        )
+        .unwrap();
+        let mut psf = Vec::new();
+        spec.generate(&mut psf).unwrap();
+
+        let mut header = SessionHeader::parse(&psf).unwrap();
+        assert_eq!(header.endianness, Endianness::Little);
+        let symbols: Vec<String> = header.entries.iter().map(|e| e.symbol()).collect();
+        assert!(symbols.contains(&"Control".to_owned()));
+        assert!(symbols.contains(&SYSTEM_HEAP_SYMBOL.to_owned()));
+
+        let mut data = Vec::new();
+        header.write(&mut data).unwrap();
+        assert_eq!(data, psf[..data.len()]);
+
+        header.set_num_cores(2);
+        header.entry(0x3000_0000).symbol = b"Added".to_vec();
+        let mut data = Vec::new();
+        header.write(&mut data).unwrap();
+        let parsed = SessionHeader::parse(&data).unwrap();
+        assert_eq!(parsed.preamble[12..16], 2u32.to_le_bytes());
+        assert_eq!(parsed.entries.len(), header.entries.len());
+        assert_eq!(parsed.entries.last().unwrap().symbol(), "Added");
+    }
+
+    #[test]
+    fn kernel_object_handles() {
+        // Handle, priority
+        assert_eq!(handle_words(&event(0x10, &[0x2000_0100, 3], ""), None), [0]);
+        // Handle, ticks to wait, messages waiting
+        assert_eq!(
+            handle_words(&event(0x66, &[0x2000_0100, 10, 0], ""), None),
+            [0]
+        );
+        // Ticks to wait only
+        assert!(handle_words(&event(0x7A, &[0x2000_0100], ""), None).is_empty());
+        // Timer configuration
+        assert!(handle_words(&event(0x02, &[1_000_000, 1000, 1, 0], ""), None).is_empty());
+        // State machine and state
+        assert_eq!(handle_words(&event(0xEE, &[1, 2], ""), None), [0, 1]);
+        assert_eq!(
+            handle_words(&event(0x03, &[0x2000_0100], "Control"), None),
+            [0]
+        );
+        assert!(handle_words(&event(0x38, &[0x2001_0000, 64], ""), None).is_empty());
+        assert!(handle_words(&event(0x1A0, &[1, 2], ""), None).is_empty());
+    }
+
+    #[test]
+    fn user_event_string_arguments() {
+        // Channel, two arguments and the format string
+        let raw = event(0x93, &[0x2000_0100, 42, 0x2000_0200], "value %d of %s");
+        assert_eq!(handle_words(&raw, None), [0, 2]);
+        let raw = event(0x93, &[0x2000_0100, 42, 0x2000_0200], "value %d of %u");
+        assert_eq!(handle_words(&raw, None), [0]);
+
+        // Fixed user event whose format string isn't known
+        let raw = event(0x99, &[0x2000_0100, 0x2000_0300, 0x2000_0200], "");
+        assert_eq!(handle_words(&raw, None), [0, 1]);
+
+        // Channel, 1 argument word and 12 format string bytes
+        let mut raw = event(
+            0x1F0,
+            &[0x2000_0100, 1 | (12 << 16), 0x2000_0200],
+            "name %s",
+        );
+        raw.num_params = 0;
+        assert_eq!(handle_words(&raw, Some(0x1F0)), [0, 2]);
+        assert_eq!(handle_words(&raw, None), Vec::<usize>::new());
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::VecDeque;
+use std::io::{self, Read};
+
+/// Reader that keeps a copy of the bytes read since the last call to `clear`,
+/// so the raw data of an event is available after the parser consumed it
+#[derive(Debug)]
+pub struct RecordingReader<R> {
+    inner: R,
+    recorded: Vec<u8>,
+    /// Bytes put back with `unread`, read before the inner reader
+    pending: VecDeque<u8>,
+    /// Offset of the next byte in the stream
+    position: u64,
+}
+
+impl<R: Read> RecordingReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            recorded: Vec::with_capacity(256),
+            pending: VecDeque::new(),
+            position: 0,
+        }
+    }
+
+    /// Bytes read since the last call to `clear`
+    pub fn recorded(&self) -> &[u8] {
+        &self.recorded
+    }
+
+    pub fn clear(&mut self) {
+        self.recorded.clear();
+    }
+
+    /// Offset of the next byte in the stream
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Put bytes back in front of the stream, they're the next ones read
+    pub fn unread(&mut self, bytes: &[u8]) {
+        for b in bytes.iter().rev() {
+            self.pending.push_front(*b);
+        }
+        self.position -= bytes.len() as u64;
+    }
+}
+
+impl<R: Read> Read for RecordingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = if self.pending.is_empty() {
+            self.inner.read(buf)?
+        } else {
+            let n = buf.len().min(self.pending.len());
+            for (dst, src) in buf.iter_mut().zip(self.pending.drain(..n)) {
+                *dst = src;
+            }
+            n
+        };
+        self.recorded.extend_from_slice(&buf[..n]);
+        self.position += n as u64;
+        Ok(n)
+    }
+}
@@ -0,0 +1,253 @@
+use crate::analyzer::{ContextAnalyzer, EventTypeCounter, StackAnalyzer};
+use crate::elf::FirmwareElf;
+use crate::event::ContextHandle;
+use crate::schema::EventSchema;
+use crate::time::{DurationTicks, ONE_SECOND};
+use crate::trace::{TraceReader, TraceSummary};
+use std::collections::BTreeMap;
+use std::{fmt, fs::File, io::BufReader, path::Path, time::Duration};
+use trace_recorder_parser::{
+    streaming::RecorderData,
+    time::{Frequency, Timestamp},
+    types::ObjectHandle,
+};
+
+/// Statistics of a recording, with contexts and event types keyed by name
+/// so recordings of different firmware builds can be lined up
+#[derive(Clone, Debug)]
+pub struct TraceReport {
+    pub timer_frequency: Frequency,
+    pub num_cores: u32,
+    pub total_events: u64,
+    pub dropped_events: u64,
+    pub restarts: u64,
+    pub total_time: Timestamp,
+
+    /// Contexts by symbol, merged across cores
+    pub contexts: BTreeMap<String, ContextReport>,
+
+    /// Event counts by event type name
+    pub event_types: BTreeMap<String, u64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ContextReport {
+    pub kind: ContextKind,
+
+    /// Number of times the context was switched in
+    pub count: u64,
+
+    pub total_runtime: DurationTicks,
+
+    /// Share of the total time spent in the context
+    pub cpu_percent: f64,
+
+    /// Execution time percentiles (ticks) of the running instances
+    pub runtime: Percentiles,
+
+    /// Lowest stack low mark observed, tasks only
+    pub stack_low_mark: Option<u32>,
+
+    /// Number of running instances that overlapped dropped events, the runtime is
+    /// unreliable when non-zero
+    pub dropped_event_gaps: u64,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum ContextKind {
+    Task,
+    Isr,
+}
+
+impl fmt::Display for ContextKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContextKind::Task => f.write_str("Task"),
+            ContextKind::Isr => f.write_str("ISR"),
+        }
+    }
+}
+
+/// Nearest-rank percentiles, in ticks
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Percentiles {
+    pub min: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Percentiles {
+    pub fn new(samples: &[f64]) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let rank = |p: f64| {
+            let idx = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+            sorted[idx.saturating_sub(1).min(sorted.len() - 1)]
+        };
+        Self {
+            min: sorted[0],
+            p50: rank(50.0),
+            p90: rank(90.0),
+            p99: rank(99.0),
+            max: sorted[sorted.len() - 1],
+        }
+    }
+}
+
+impl TraceReport {
+    /// Read a recording and build its report
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        elf: Option<&FirmwareElf>,
+        schema: Option<&EventSchema>,
+        custom_printf_event_id: Option<u16>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let f = File::open(path)?;
+        let mut reader = TraceReader::new(BufReader::new(f))?;
+        if let Some(elf) = elf {
+            reader.set_elf(elf);
+        }
+        if let Some(schema) = schema {
+            reader.set_event_schema(schema);
+        }
+        if let Some(custom_printf_event_id) = custom_printf_event_id {
+            reader.set_custom_printf_event_id(custom_printf_event_id);
+        }
+
+        let mut contexts = ContextAnalyzer::default();
+        let mut stacks = StackAnalyzer::default();
+        let mut types = EventTypeCounter::default();
+        let summary = reader.run(&mut [&mut contexts, &mut stacks, &mut types])?;
+
+        Ok(Self::new(
+            reader.recorder_data(),
+            &summary,
+            &contexts,
+            &stacks,
+            &types,
+            elf,
+            schema,
+        ))
+    }
+
+    /// Build the report from the built-in analyzers
+    pub fn new(
+        rd: &RecorderData,
+        summary: &TraceSummary,
+        contexts: &ContextAnalyzer,
+        stacks: &StackAnalyzer,
+        types: &EventTypeCounter,
+        elf: Option<&FirmwareElf>,
+        schema: Option<&EventSchema>,
+    ) -> Self {
+        let total_ticks = summary.total_time.get_raw() as f64;
+
+        // Merge the per-core stats by symbol
+        let mut merged: BTreeMap<String, MergedContext> = BTreeMap::new();
+        for core_stats in contexts.stats.values() {
+            for (ctx, stats) in core_stats.iter() {
+                let handle = ctx.object_handle();
+                let kind = match ctx {
+                    ContextHandle::Task(_) => ContextKind::Task,
+                    ContextHandle::Isr(_) => ContextKind::Isr,
+                };
+                let entry = merged
+                    .entry(object_name(rd, elf, handle))
+                    .or_insert_with(|| (kind, 0, DurationTicks::zero(), Vec::new(), handle, 0));
+                entry.1 += stats.count;
+                entry.2 += stats.total_runtime;
+                entry.3.extend_from_slice(&stats.running_instances);
+                entry.5 += stats.dropped_event_gaps;
+            }
+        }
+
+        let contexts = merged
+            .into_iter()
+            .map(
+                |(sym, (kind, count, total_runtime, instances, handle, gaps))| {
+                    let cpu_percent = if total_ticks > 0.0 {
+                        100.0 * (total_runtime.get_raw() as f64 / total_ticks)
+                    } else {
+                        0.0
+                    };
+                    let report = ContextReport {
+                        kind,
+                        count,
+                        total_runtime,
+                        cpu_percent,
+                        runtime: Percentiles::new(&instances),
+                        stack_low_mark: stacks.stats.get(&handle).map(|s| s.low_mark_min),
+                        dropped_event_gaps: gaps,
+                    };
+                    (sym, report)
+                },
+            )
+            .collect();
+
+        let mut event_types = BTreeMap::new();
+        for (t, count) in types.counts.iter() {
+            let name = schema
+                .and_then(|s| s.name(*t))
+                .map(|n| n.to_owned())
+                .unwrap_or_else(|| t.to_string());
+            *event_types.entry(name).or_insert(0) += count;
+        }
+
+        Self {
+            timer_frequency: rd.timestamp_info.timer_frequency,
+            num_cores: rd.header.num_cores,
+            total_events: summary.total_events,
+            dropped_events: summary.dropped_events,
+            restarts: summary.restarts,
+            total_time: summary.total_time,
+            contexts,
+            event_types,
+        }
+    }
+
+    /// Convert ticks to a duration, if the timer frequency is known
+    pub fn duration(&self, ticks: f64) -> Option<Duration> {
+        if self.timer_frequency.is_unitless() {
+            None
+        } else {
+            let ns = ticks * ONE_SECOND as f64 / f64::from(self.timer_frequency.get_raw());
+            Some(Duration::from_nanos(ns as u64))
+        }
+    }
+
+    /// Total recording time, in seconds, if the timer frequency is known
+    pub fn total_seconds(&self) -> Option<f64> {
+        self.duration(self.total_time.get_raw() as f64)
+            .map(|d| d.as_secs_f64())
+    }
+
+    /// Events per second of the given event type, or per tick if the timer frequency is unknown
+    pub fn event_rate(&self, event_type: &str) -> f64 {
+        let count = self.event_types.get(event_type).copied().unwrap_or(0) as f64;
+        let time = self
+            .total_seconds()
+            .unwrap_or(self.total_time.get_raw() as f64);
+        if time > 0.0 {
+            count / time
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Kind, count, total runtime, running instances, handle and dropped event gaps
+type MergedContext = (ContextKind, u64, DurationTicks, Vec<f64>, ObjectHandle, u64);
+
+/// Name of an object: its symbol, the ELF symbol at the handle address, or the address
+pub fn object_name(rd: &RecorderData, elf: Option<&FirmwareElf>, handle: ObjectHandle) -> String {
+    rd.entry_table
+        .symbol(handle)
+        .map(|s| s.to_string())
+        .or_else(|| elf.and_then(|elf| elf.resolve(u32::from(handle))))
+        .unwrap_or_else(|| format!("0x{handle:08X}"))
+}
@@ -0,0 +1,486 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::{fs, path::Path};
+use trace_recorder_parser::{
+    streaming::event::{BaseEvent, EventId, EventType},
+    time::Timestamp,
+    types::Endianness,
+};
+
+/// User-declared layouts for application specific events the parser doesn't know about.
+///
+/// ```toml
+/// [[event]]
+/// id = 0x1A0
+/// name = "MOTOR_STATE"
+/// template = "motor {motor} -> {state} @ {rpm} rpm"
+/// fields = [
+///     { name = "motor", type = "u8" },
+///     { name = "state", type = "u8" },
+///     { name = "rpm", type = "u16" },
+/// ]
+/// ```
+///
+/// Fields are packed in declaration order over the event parameter bytes.
+#[derive(Clone, Debug, Default)]
+pub struct EventSchema {
+    events: BTreeMap<u16, CustomEventDef>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SchemaFile {
+    #[serde(default, rename = "event")]
+    events: Vec<CustomEventDef>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CustomEventDef {
+    /// Event ID (lower 12 bits of the event code)
+    id: u16,
+    name: String,
+    /// Byte order of the fields, defaults to the trace endianness
+    #[serde(default)]
+    endianness: Option<FieldEndianness>,
+    #[serde(default)]
+    fields: Vec<FieldDef>,
+    /// Display template, `{field}` is replaced with the field value
+    #[serde(default)]
+    template: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum FieldEndianness {
+    Little,
+    Big,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FieldDef {
+    name: String,
+    #[serde(rename = "type")]
+    typ: FieldType,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum FieldType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    /// NUL terminated, or the remainder of the event data
+    String,
+}
+
+impl EventSchema {
+    /// Load a schema file, YAML when the extension is `.yaml` or `.yml`, TOML otherwise
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)?;
+        let is_yaml = path
+            .extension()
+            .map(|ext| ext == "yaml" || ext == "yml")
+            .unwrap_or(false);
+        if is_yaml {
+            Self::from_file(serde_norway::from_str(&content)?)
+        } else {
+            Self::parse(&content)
+        }
+    }
+
+    fn parse(content: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::from_file(toml::from_str(content)?)
+    }
+
+    fn from_file(file: SchemaFile) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut events = BTreeMap::new();
+        for def in file.events.into_iter() {
+            if def.id > 0x0F_FF {
+                return Err(format!(
+                    "Custom event '{}' ID 0x{:X} exceeds 12 bits",
+                    def.name, def.id
+                )
+                .into());
+            }
+            if !matches!(EventType::from(EventId(def.id)), EventType::Unknown(_)) {
+                return Err(format!(
+                    "Custom event '{}' ID 0x{:X} is already a known event type ({})",
+                    def.name,
+                    def.id,
+                    EventType::from(EventId(def.id))
+                )
+                .into());
+            }
+            if let Some(prev) = events.insert(def.id, def) {
+                return Err(
+                    format!("Duplicate custom event ID 0x{:X} ({})", prev.id, prev.name).into(),
+                );
+            }
+        }
+
+        Ok(Self { events })
+    }
+
+    /// The declared name of the event type, if it's a custom event
+    pub fn name(&self, event_type: EventType) -> Option<&str> {
+        match event_type {
+            EventType::Unknown(id) => self.events.get(&id.0).map(|def| def.name.as_str()),
+            _ => None,
+        }
+    }
+
+    /// Whether the schema declares a custom event with this name
+    pub fn has_name(&self, name: &str) -> bool {
+        self.events.values().any(|def| def.name == name)
+    }
+
+    /// Decode an unknown event, if the schema declares it
+    pub fn decode(&self, event: &BaseEvent, trace_endianness: Endianness) -> Option<CustomEvent> {
+        let def = self.events.get(&event.code.event_id().0)?;
+        Some(CustomEvent {
+            name: def.name.clone(),
+            event_count: event.event_count.into(),
+            timestamp: event.timestamp,
+            template: def.template.clone(),
+            fields: def.decode_fields(event.parameters(), trace_endianness)?,
+        })
+    }
+}
+
+impl CustomEventDef {
+    fn decode_fields(
+        &self,
+        parameters: &[u32],
+        trace_endianness: Endianness,
+    ) -> Option<Vec<(String, FieldValue)>> {
+        // Parameters are 32-bit words in the trace byte order, get the original bytes back
+        let data: Vec<u8> = parameters
+            .iter()
+            .flat_map(|w| match trace_endianness {
+                Endianness::Little => w.to_le_bytes(),
+                Endianness::Big => w.to_be_bytes(),
+            })
+            .collect();
+
+        let endianness = match self.endianness {
+            Some(FieldEndianness::Little) => Endianness::Little,
+            Some(FieldEndianness::Big) => Endianness::Big,
+            None => trace_endianness,
+        };
+
+        let mut fields = Vec::with_capacity(self.fields.len());
+        let mut data = data.as_slice();
+        for field in self.fields.iter() {
+            let value = field.typ.decode(&mut data, endianness)?;
+            fields.push((field.name.clone(), value));
+        }
+        Some(fields)
+    }
+}
+
+impl FieldType {
+    fn decode(self, data: &mut &[u8], endianness: Endianness) -> Option<FieldValue> {
+        fn take<const N: usize>(data: &mut &[u8], endianness: Endianness) -> Option<[u8; N]> {
+            if data.len() < N {
+                return None;
+            }
+            let (bytes, rest) = data.split_at(N);
+            *data = rest;
+            let mut b: [u8; N] = bytes.try_into().ok()?;
+            if matches!(endianness, Endianness::Little) {
+                b.reverse();
+            }
+            Some(b)
+        }
+
+        Some(match self {
+            FieldType::U8 => FieldValue::Unsigned(take::<1>(data, endianness)?[0].into()),
+            FieldType::I8 => FieldValue::Signed((take::<1>(data, endianness)?[0] as i8).into()),
+            FieldType::U16 => {
+                FieldValue::Unsigned(u16::from_be_bytes(take(data, endianness)?).into())
+            }
+            FieldType::I16 => {
+                FieldValue::Signed(i16::from_be_bytes(take(data, endianness)?).into())
+            }
+            FieldType::U32 => {
+                FieldValue::Unsigned(u32::from_be_bytes(take(data, endianness)?).into())
+            }
+            FieldType::I32 => {
+                FieldValue::Signed(i32::from_be_bytes(take(data, endianness)?).into())
+            }
+            FieldType::F32 => FieldValue::Float(f32::from_be_bytes(take(data, endianness)?).into()),
+            FieldType::String => {
+                let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+                let s = String::from_utf8_lossy(&data[..end]).into_owned();
+                *data = &data[(end + 1).min(data.len())..];
+                FieldValue::String(s)
+            }
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum FieldValue {
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+    String(String),
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldValue::Unsigned(v) => v.fmt(f),
+            FieldValue::Signed(v) => v.fmt(f),
+            FieldValue::Float(v) => v.fmt(f),
+            FieldValue::String(v) => v.fmt(f),
+        }
+    }
+}
+
+/// An unknown event decoded with the schema
+#[derive(Clone, PartialEq, Debug)]
+pub struct CustomEvent {
+    pub name: String,
+    pub event_count: u16,
+    pub timestamp: Timestamp,
+    pub template: Option<String>,
+    pub fields: Vec<(String, FieldValue)>,
+}
+
+impl CustomEvent {
+    /// Render the event with its template, or as a `name=value` list without one
+    pub fn render(&self) -> String {
+        match &self.template {
+            Some(template) => {
+                let mut out = template.clone();
+                for (name, value) in self.fields.iter() {
+                    out = out.replace(&format!("{{{name}}}"), &value.to_string());
+                }
+                out
+            }
+            None => {
+                let mut out = String::new();
+                for (idx, (name, value)) in self.fields.iter().enumerate() {
+                    if idx != 0 {
+                        out.push_str(", ");
+                    }
+                    let _ = write!(out, "{name}={value}");
+                }
+                out
+            }
+        }
+    }
+}
+
+impl fmt::Display for CustomEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}]:'{}'", self.timestamp, self.render())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(content: &str) -> EventSchema {
+        EventSchema::parse(content).unwrap()
+    }
+
+    fn fields(schema: &EventSchema, params: &[u32], endianness: Endianness) -> Vec<FieldValue> {
+        let def = schema.events.values().next().unwrap();
+        def.decode_fields(params, endianness)
+            .unwrap()
+            .into_iter()
+            .map(|(_, v)| v)
+            .collect()
+    }
+
+    fn event(fields: &[(&str, FieldValue)], template: Option<&str>) -> CustomEvent {
+        CustomEvent {
+            name: "MOTOR_STATE".to_owned(),
+            event_count: 1,
+            timestamp: Timestamp::zero(),
+            template: template.map(|t| t.to_owned()),
+            fields: fields
+                .iter()
+                .map(|(n, v)| (n.to_string(), v.clone()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn schema_validation() {
+        let err = |content: &str| EventSchema::parse(content).unwrap_err().to_string();
+        assert!(err("[[event]]\nid = 0x1000\nname = \"A\"").contains("exceeds 12 bits"));
+        assert!(err("[[event]]\nid = 0x90\nname = \"A\"").contains("already a known event type"));
+        assert!(
+            err("[[event]]\nid = 0x1A0\nname = \"A\"\n[[event]]\nid = 0x1A0\nname = \"B\"")
+                .contains("Duplicate custom event ID 0x1A0 (A)")
+        );
+        assert!(err(
+            "[[event]]\nid = 0x1A0\nname = \"A\"\nfields = [{ name = \"x\", type = \"u64\" }]"
+        )
+        .contains("unknown variant"));
+
+        let s = schema("[[event]]\nid = 0x1A0\nname = \"A\"");
+        assert_eq!(s.name(EventType::from(EventId(0x1A0))), Some("A"));
+        assert_eq!(s.name(EventType::from(EventId(0x1A1))), None);
+        assert!(s.has_name("A") && !s.has_name("B"));
+    }
+
+    #[test]
+    fn field_endianness() {
+        let content = r#"
+            [[event]]
+            id = 0x1A0
+            name = "A"
+            fields = [{ name = "a", type = "u16" }, { name = "b", type = "u16" }]
+            "#;
+        let bytes = u32::from_le_bytes([0x12, 0x34, 0x56, 0x78]);
+
+        // Trace byte order
+        let s = schema(content);
+        assert_eq!(
+            fields(&s, &[bytes], Endianness::Little),
+            vec![FieldValue::Unsigned(0x3412), FieldValue::Unsigned(0x7856)]
+        );
+        assert_eq!(
+            fields(&s, &[bytes.swap_bytes()], Endianness::Big),
+            vec![FieldValue::Unsigned(0x1234), FieldValue::Unsigned(0x5678)]
+        );
+
+        // Declared byte order, independent of the trace
+        let s = schema(&format!("{content}endianness = \"big\""));
+        assert_eq!(
+            fields(&s, &[bytes], Endianness::Little),
+            vec![FieldValue::Unsigned(0x1234), FieldValue::Unsigned(0x5678)]
+        );
+    }
+
+    #[test]
+    fn signed_and_float_fields() {
+        let s = schema(
+            r#"
+            [[event]]
+            id = 0x1A0
+            name = "A"
+            fields = [
+                { name = "a", type = "i8" },
+                { name = "b", type = "u8" },
+                { name = "c", type = "i16" },
+                { name = "d", type = "i32" },
+                { name = "e", type = "f32" },
+            ]
+            "#,
+        );
+        let mut data = vec![0xFE, 0xFE];
+        data.extend_from_slice(&(-300i16).to_le_bytes());
+        data.extend_from_slice(&(-70000i32).to_le_bytes());
+        data.extend_from_slice(&1.5f32.to_le_bytes());
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        let params: Vec<u32> = data
+            .chunks(4)
+            .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+        assert_eq!(
+            fields(&s, &params, Endianness::Little),
+            vec![
+                FieldValue::Signed(-2),
+                FieldValue::Unsigned(0xFE),
+                FieldValue::Signed(-300),
+                FieldValue::Signed(-70000),
+                FieldValue::Float(1.5),
+            ]
+        );
+
+        // Not enough parameter bytes for the fields
+        let def = s.events.values().next().unwrap();
+        assert_eq!(def.decode_fields(&params[..2], Endianness::Little), None);
+    }
+
+    #[test]
+    fn string_fields() {
+        let s = schema(
+            r#"
+            [[event]]
+            id = 0x1A0
+            name = "A"
+            fields = [{ name = "a", type = "string" }, { name = "b", type = "u8" }, { name = "c", type = "string" }]
+            "#,
+        );
+        let params = [
+            u32::from_le_bytes(*b"abc\0"),
+            u32::from_le_bytes([7, b'x', b'y', b'z']),
+        ];
+        assert_eq!(
+            fields(&s, &params, Endianness::Little),
+            vec![
+                FieldValue::String("abc".to_owned()),
+                FieldValue::Unsigned(7),
+                FieldValue::String("xyz".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn yaml_schema() {
+        let fixture = |name: &str| {
+            EventSchema::load(
+                Path::new(env!("CARGO_MANIFEST_DIR"))
+                    .join("tests/fixtures")
+                    .join(name),
+            )
+            .unwrap()
+        };
+        let toml = fixture("schema.toml");
+        let yaml = fixture("schema.yaml");
+        assert_eq!(
+            yaml.name(EventType::from(EventId(0x1A0))),
+            Some("MOTOR_STATE")
+        );
+
+        let params = [u32::from_le_bytes([1, 3, 0xE8, 0x03])];
+        let decoded = fields(&yaml, &params, Endianness::Little);
+        assert_eq!(
+            decoded,
+            vec![
+                FieldValue::Unsigned(1),
+                FieldValue::Unsigned(3),
+                FieldValue::Unsigned(1000),
+            ]
+        );
+        assert_eq!(decoded, fields(&toml, &params, Endianness::Little));
+        let def = yaml.events.values().next().unwrap();
+        assert_eq!(
+            def.template.as_deref(),
+            Some("motor {motor} -> {state} @ {rpm} rpm")
+        );
+    }
+
+    #[test]
+    fn template_rendering() {
+        let fields = [
+            ("motor", FieldValue::Unsigned(1)),
+            ("rpm", FieldValue::Signed(-20)),
+            ("label", FieldValue::String("fan".to_owned())),
+        ];
+        assert_eq!(
+            event(
+                &fields,
+                Some("{label} motor {motor} @ {rpm} rpm, {missing}")
+            )
+            .render(),
+            "fan motor 1 @ -20 rpm, {missing}"
+        );
+        assert_eq!(event(&fields, None).render(), "motor=1, rpm=-20, label=fan");
+    }
+}
@@ -0,0 +1,69 @@
+use std::time::Duration;
+use trace_recorder_parser::{streaming::RecorderData, time::Timestamp};
+
+/// A duration, in timer ticks
+pub type DurationTicks = Timestamp;
+
+// ns
+pub const ONE_SECOND: u64 = 1_000_000_000;
+
+pub trait TrcTimeExt {
+    // Return nanoseconds
+    fn convert_timestamp(&self, t: Timestamp) -> Option<u64> {
+        self.convert_ticks(t.get_raw())
+    }
+
+    // Return nanoseconds
+    fn convert_ticks(&self, ticks: u64) -> Option<u64>;
+
+    // Return timer ticks
+    fn convert_duration(&self, d: Duration) -> Option<u64>;
+
+    // Return timer ticks per timer wraparound, a period of 0 is a free running 32-bit counter
+    fn timer_wraparound(&self) -> u64;
+}
+
+impl TrcTimeExt for RecorderData {
+    fn convert_ticks(&self, ticks: u64) -> Option<u64> {
+        if self.timestamp_info.timer_frequency.is_unitless() {
+            None
+        } else {
+            let ticks_ns = u128::from(ticks) * u128::from(ONE_SECOND);
+            Some((ticks_ns / u128::from(self.timestamp_info.timer_frequency.get_raw())) as u64)
+        }
+    }
+
+    fn convert_duration(&self, d: Duration) -> Option<u64> {
+        if self.timestamp_info.timer_frequency.is_unitless() {
+            None
+        } else {
+            let ns_ticks = d.as_nanos() * u128::from(self.timestamp_info.timer_frequency.get_raw());
+            Some((ns_ticks / u128::from(ONE_SECOND)) as u64)
+        }
+    }
+
+    fn timer_wraparound(&self) -> u64 {
+        match self.timestamp_info.timer_period {
+            0 => 1 << 32,
+            period => u64::from(period),
+        }
+    }
+}
+
+/// Parse a duration with a unit, like `2ms`, `500us` or `1.5s`
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .ok_or_else(|| format!("duration '{s}' is missing a unit (ns, us, ms, s)"))?;
+    let (num, unit) = s.split_at(split);
+    let num: f64 = num.parse().map_err(|_| format!("invalid duration '{s}'"))?;
+    let ns_per_unit = match unit.trim() {
+        "ns" => 1.0,
+        "us" | "µs" => 1_000.0,
+        "ms" => 1_000_000.0,
+        "s" => 1_000_000_000.0,
+        u => return Err(format!("unknown duration unit '{u}' in '{s}'")),
+    };
+    Ok(Duration::from_nanos((num * ns_per_unit) as u64))
+}
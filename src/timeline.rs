@@ -0,0 +1,136 @@
+use crate::analyzer::ContextInterval;
+use crate::elf::FirmwareElf;
+use crate::event::ContextHandle;
+use crate::report::{object_name, ContextKind};
+use std::collections::BTreeMap;
+use trace_recorder_parser::{streaming::RecorderData, time::Timestamp};
+
+/// Running instances of each context, one row per context, for drawing timelines
+#[derive(Clone, Debug)]
+pub struct Timeline {
+    /// ISRs first, then tasks, by name
+    pub rows: Vec<TimelineRow>,
+
+    /// End of the recording, ticks since its start
+    pub end: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct TimelineRow {
+    pub name: String,
+    pub kind: ContextKind,
+
+    /// Running instances `[start, end)`, in ticks since the start of the recording, sorted
+    /// and not overlapping
+    pub intervals: Vec<(u64, u64)>,
+}
+
+impl Timeline {
+    /// Build the rows from the intervals recorded by [`ContextAnalyzer::with_intervals`](crate::analyzer::ContextAnalyzer::with_intervals),
+    /// contexts are merged across cores by name
+    pub fn new(
+        rd: &RecorderData,
+        elf: Option<&FirmwareElf>,
+        intervals: &[ContextInterval],
+        end: Timestamp,
+    ) -> Self {
+        let mut rows: BTreeMap<(ContextKind, String), Vec<(u64, u64)>> = BTreeMap::new();
+        for i in intervals.iter() {
+            let kind = match i.context {
+                ContextHandle::Task(_) => ContextKind::Task,
+                ContextHandle::Isr(_) => ContextKind::Isr,
+            };
+            let name = object_name(rd, elf, i.context.object_handle());
+            rows.entry((kind, name))
+                .or_default()
+                .push((i.start.get_raw(), i.end.get_raw()));
+        }
+
+        let mut rows: Vec<TimelineRow> = rows
+            .into_iter()
+            .map(|((kind, name), intervals)| TimelineRow {
+                name,
+                kind,
+                intervals: merge_overlapping(intervals),
+            })
+            .collect();
+        rows.sort_by_key(|r| r.kind != ContextKind::Isr);
+
+        let end = intervals
+            .iter()
+            .map(|i| i.end.get_raw())
+            .max()
+            .unwrap_or(0)
+            .max(end.get_raw());
+
+        Self { rows, end }
+    }
+}
+
+/// Sort the intervals and merge the overlapping ones, contexts of the same name can run
+/// at the same time on different cores
+fn merge_overlapping(mut intervals: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    intervals.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(intervals.len());
+    for (start, end) in intervals.into_iter() {
+        match merged.last_mut() {
+            Some((_, last_end)) if start < *last_end => *last_end = (*last_end).max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+impl TimelineRow {
+    /// Fraction of each of `width` columns spanning `[start, end)` the context was running
+    pub fn occupancy(&self, start: f64, end: f64, width: usize) -> Vec<f64> {
+        let mut cols = vec![0.0; width];
+        if width == 0 || end <= start {
+            return cols;
+        }
+        let col_len = (end - start) / width as f64;
+        let first = self
+            .intervals
+            .partition_point(|(_, i_end)| (*i_end as f64) <= start);
+        for &(i_start, i_end) in self.intervals[first..].iter() {
+            let (i_start, i_end) = (i_start as f64, i_end as f64);
+            if i_start >= end {
+                break;
+            }
+            let (s, e) = (i_start.max(start), i_end.min(end));
+            let first_col = ((s - start) / col_len) as usize;
+            let last_col = (((e - start) / col_len) as usize).min(width - 1);
+            for (c, occ) in cols
+                .iter_mut()
+                .enumerate()
+                .take(last_col + 1)
+                .skip(first_col)
+            {
+                let c_start = start + (end - start) * c as f64 / width as f64;
+                let c_end = start + (end - start) * (c + 1) as f64 / width as f64;
+                *occ += (e.min(c_end) - s.max(c_start)).max(0.0) / col_len;
+            }
+        }
+        cols
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlapping_intervals() {
+        // Two cores running a context of the same name, the second instance inside the first
+        let intervals = merge_overlapping(vec![(50, 60), (0, 100), (100, 120), (110, 130)]);
+        assert_eq!(intervals, vec![(0, 100), (100, 130)]);
+
+        let row = TimelineRow {
+            name: "IDLE".to_owned(),
+            kind: ContextKind::Task,
+            intervals,
+        };
+        assert_eq!(row.occupancy(0.0, 200.0, 4), vec![1.0, 1.0, 0.6, 0.0]);
+        assert_eq!(row.occupancy(55.0, 65.0, 2), vec![1.0, 1.0]);
+    }
+}
@@ -0,0 +1,447 @@
+use crate::analyzer::Analyzer;
+use crate::elf::FirmwareElf;
+use crate::event::{split_event_count, CoreId, EventCounter, TraceEvent};
+use crate::reader::RecordingReader;
+use crate::schema::EventSchema;
+use crate::time::TrcTimeExt;
+use crate::user_event::ElfUserEvent;
+use std::io::{self, Read};
+use trace_recorder_parser::{
+    streaming::{
+        event::{Event, EventId, EventType},
+        Error, HeaderInfo, RecorderData,
+    },
+    time::{StreamingInstant, Timestamp},
+    types::Endianness,
+};
+use tracing::{error, warn};
+
+/// An event along with the state the reader tracks across the stream
+#[derive(Clone, Debug)]
+pub struct EventRecord {
+    pub event_type: EventType,
+    pub event: TraceEvent,
+
+    /// Core the event was recorded on
+    pub core_id: CoreId,
+
+    /// Event counter, without the core ID bits on SMP configurations
+    pub event_count: u16,
+
+    /// Time since the start of the session, with timer wraparounds accounted for
+    pub timestamp: Timestamp,
+
+    /// Time since the start of the recording, earlier sessions included
+    pub trace_time: Timestamp,
+
+    /// Number of events lost between the previous event and this one
+    pub dropped_events: Option<u64>,
+
+    /// File offset of the event
+    pub offset: u64,
+
+    /// Bytes of the event as recorded, empty unless enabled with [`TraceReader::set_capture_data`]
+    pub data: Vec<u8>,
+}
+
+/// Totals over the whole stream, including all restarted sessions
+#[derive(Clone, Debug)]
+pub struct TraceSummary {
+    pub total_events: u64,
+    pub dropped_events: u64,
+    pub restarts: u64,
+
+    /// Sum of the session durations
+    pub total_time: Timestamp,
+
+    /// Corrupted data skipped to resynchronize on the next plausible event
+    pub skipped: Vec<SkippedRange>,
+}
+
+/// Bytes of the recording skipped while resynchronizing
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct SkippedRange {
+    /// File offset of the first byte
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// Largest event counter step of a plausible event, as a fraction of the counter range
+const RESYNC_MAX_COUNT_STEP_DIV: u16 = 2;
+
+/// Largest time between the last event and a plausible one, in seconds
+const RESYNC_MAX_TIME_STEP_SECS: u64 = 60;
+
+/// Bytes needed to check a candidate event: its header, up to 15 parameters and the next header
+const RESYNC_LOOKAHEAD: usize = 8 + 15 * 4 + 8;
+
+/// Reads events from a streaming (psf) recording, handling trace restarts,
+/// dropped-event tracking and timestamp rollovers, and feeds them to a set of analyzers
+pub struct TraceReader<'a, R> {
+    r: RecordingReader<R>,
+    rd: RecorderData,
+    elf: Option<&'a FirmwareElf>,
+    schema: Option<&'a EventSchema>,
+    custom_printf_event_id: Option<EventId>,
+    resync: bool,
+    capture_data: bool,
+    /// Bytes of the PSF header of the current session as recorded
+    header: Vec<u8>,
+}
+
+impl<'a, R: Read> TraceReader<'a, R> {
+    /// Search for the PSF header and read the recorder data
+    pub fn new(r: R) -> Result<Self, Error> {
+        let mut r = RecordingReader::new(r);
+        let rd = RecorderData::find(&mut r)?;
+        // Data before the PSF word is skipped
+        let header = r
+            .recorded()
+            .windows(4)
+            .position(is_trace_start)
+            .map(|pos| r.recorded()[pos..].to_vec())
+            .unwrap_or_default();
+        Ok(Self {
+            r,
+            rd,
+            elf: None,
+            schema: None,
+            custom_printf_event_id: None,
+            resync: false,
+            capture_data: false,
+            header,
+        })
+    }
+
+    /// Recorder data of the current session, the entry table gets updated as events are read
+    pub fn recorder_data(&self) -> &RecorderData {
+        &self.rd
+    }
+
+    /// Firmware ELF used to recover fixed user events whose format string isn't in the entry table
+    pub fn set_elf(&mut self, elf: &'a FirmwareElf) {
+        self.elf = Some(elf);
+    }
+
+    /// Schema used to decode application specific events
+    pub fn set_event_schema(&mut self, schema: &'a EventSchema) {
+        self.schema = Some(schema);
+    }
+
+    /// Custom printf event ID, applied to every session
+    pub fn set_custom_printf_event_id(&mut self, custom_printf_event_id: u16) {
+        let id = EventId::from(custom_printf_event_id);
+        self.custom_printf_event_id = Some(id);
+        self.rd.set_custom_printf_event_id(id);
+    }
+
+    /// Instead of logging the parser errors and carrying on, skip the corrupted data up to
+    /// the next plausible event: a known event code, an event count following the last one
+    /// and a timestamp shortly after it. Events that parse but aren't plausible are skipped too.
+    pub fn set_resync(&mut self, resync: bool) {
+        self.resync = resync;
+    }
+
+    /// Keep a copy of the bytes of every event in [`EventRecord::data`], for the
+    /// subcommands rewriting the recording
+    pub fn set_capture_data(&mut self, capture_data: bool) {
+        self.capture_data = capture_data;
+    }
+
+    /// Read the remainder of the stream, passing everything to the analyzers
+    pub fn run(&mut self, analyzers: &mut [&mut dyn Analyzer]) -> Result<TraceSummary, Error> {
+        let mut summary = TraceSummary {
+            total_events: 0,
+            dropped_events: 0,
+            restarts: 0,
+            total_time: Timestamp::zero(),
+            skipped: Vec::new(),
+        };
+        let mut event_counter_tracker = EventCounter::new(self.rd.header.num_cores);
+        let mut first_event_observed = false;
+        let mut time_tracker = StreamingInstant::zero();
+        let mut session_timestamps = Vec::new();
+        let mut session_offset = Timestamp::zero();
+        // Event count and timestamp of the last event, to resynchronize on
+        let mut last_header: Option<(u16, u32)> = None;
+
+        for a in analyzers.iter_mut() {
+            a.on_session_header(&self.header);
+            a.on_header(&self.rd);
+        }
+
+        loop {
+            self.r.clear();
+            let offset = self.r.position();
+            let (event_type, event) = match self.rd.read_event(&mut self.r) {
+                Ok(Some((ec, Event::Unknown(ev)))) => {
+                    match self
+                        .schema
+                        .and_then(|s| s.decode(&ev, self.rd.header.endianness))
+                    {
+                        Some(custom) => (ec.event_type(), TraceEvent::Custom(custom)),
+                        None => (ec.event_type(), TraceEvent::Parsed(Event::Unknown(ev))),
+                    }
+                }
+                Ok(Some((ec, ev))) => (ec.event_type(), TraceEvent::Parsed(ev)),
+                Ok(None) => {
+                    if !self.r.recorded().is_empty() {
+                        // The stream ends in the middle of an event header
+                        let e = Error::Io(io::ErrorKind::UnexpectedEof.into());
+                        error!("{e}");
+                        for a in analyzers.iter_mut() {
+                            a.on_error(&self.rd, offset, &e);
+                        }
+                        if self.resync {
+                            self.skip_corrupted(offset, last_header, &mut summary)?;
+                        }
+                    }
+                    break;
+                }
+                Err(e) => match e {
+                    Error::TraceRestarted(psf_start_word_endianness) => {
+                        warn!("Detected a restarted trace stream");
+                        summary.restarts += 1;
+                        first_event_observed = false;
+                        last_header = None;
+                        session_timestamps.push(time_tracker.to_timestamp());
+                        session_offset += time_tracker.to_timestamp();
+                        self.rd = RecorderData::read_with_endianness(
+                            psf_start_word_endianness,
+                            &mut self.r,
+                        )?;
+                        event_counter_tracker = EventCounter::new(self.rd.header.num_cores);
+                        if let Some(custom_printf_event_id) = self.custom_printf_event_id {
+                            self.rd.set_custom_printf_event_id(custom_printf_event_id);
+                        }
+                        self.header = self.r.recorded().to_vec();
+                        for a in analyzers.iter_mut() {
+                            a.on_session_header(&self.header);
+                            a.on_restart(&self.rd);
+                        }
+                        continue;
+                    }
+                    Error::FixedUserEventFmtStringLookup(_) if self.elf.is_some() => {
+                        match self.elf.and_then(|elf| {
+                            ElfUserEvent::decode(
+                                self.r.recorded(),
+                                self.rd.header.endianness,
+                                &self.rd.entry_table,
+                                elf,
+                            )
+                        }) {
+                            Some(ev) => (ev.event_type, TraceEvent::ElfUser(ev)),
+                            None => {
+                                error!("{e}");
+                                for a in analyzers.iter_mut() {
+                                    a.on_error(&self.rd, offset, &e);
+                                }
+                                continue;
+                            }
+                        }
+                    }
+                    _ => {
+                        error!("{e}");
+                        for a in analyzers.iter_mut() {
+                            a.on_error(&self.rd, offset, &e);
+                        }
+                        if self.resync {
+                            self.skip_corrupted(offset, last_header, &mut summary)?;
+                        }
+                        continue;
+                    }
+                },
+            };
+
+            let (core_id, event_count) =
+                split_event_count(self.rd.header.num_cores, event.event_count());
+            let raw_timestamp = event.timestamp().ticks() as u32;
+            if self.resync && !self.plausible_event(event_count, raw_timestamp, last_header) {
+                self.skip_corrupted(offset, last_header, &mut summary)?;
+                continue;
+            }
+            last_header = Some((event_count, raw_timestamp));
+
+            let dropped_events = if !first_event_observed {
+                event_counter_tracker.set_initial_count(event_count);
+                time_tracker = StreamingInstant::new(
+                    event.timestamp().ticks() as u32,
+                    self.rd.timestamp_info.timer_wraparounds,
+                );
+                first_event_observed = true;
+                None
+            } else {
+                event_counter_tracker.update(event_count)
+            };
+
+            let timestamp = time_tracker.elapsed(event.timestamp());
+
+            summary.total_events += 1;
+            if let Some(dropped_events) = dropped_events {
+                warn!(event_count, dropped_events, "Dropped events detected");
+                summary.dropped_events += dropped_events;
+            }
+
+            let record = EventRecord {
+                event_type,
+                event,
+                core_id,
+                event_count,
+                timestamp,
+                trace_time: session_offset + timestamp,
+                dropped_events,
+                offset,
+                data: if self.capture_data {
+                    self.r.recorded().to_vec()
+                } else {
+                    Vec::new()
+                },
+            };
+            for a in analyzers.iter_mut() {
+                a.on_event(&self.rd, &record);
+            }
+        }
+
+        session_timestamps.push(time_tracker.to_timestamp());
+        summary.total_time = session_timestamps.into_iter().sum();
+
+        for a in analyzers.iter_mut() {
+            a.finish(&self.rd, &summary);
+        }
+
+        Ok(summary)
+    }
+
+    /// Skip the data of a bad event starting at `offset` up to the next plausible event
+    fn skip_corrupted(
+        &mut self,
+        offset: u64,
+        last_header: Option<(u16, u32)>,
+        summary: &mut TraceSummary,
+    ) -> Result<(), Error> {
+        // Bytes already read, past the start of the bad event
+        let mut buf = self.r.recorded().get(1..).unwrap_or_default().to_vec();
+        let mut pos = 0;
+        let mut end = offset + 1;
+        let mut eof = false;
+        loop {
+            // Enough for a candidate event with all its parameters and the next header
+            while !eof && buf.len() - pos < RESYNC_LOOKAHEAD {
+                let mut chunk = [0; 256];
+                let n = self.r.read(&mut chunk)?;
+                eof = n == 0;
+                buf.extend_from_slice(&chunk[..n]);
+            }
+            let window = &buf[pos..];
+            if window.len() < 8 {
+                // Nothing plausible until the end of the stream
+                end += window.len() as u64;
+                break;
+            }
+            // A single plausible header is often a coincidence, the next one must be too
+            let plausible = is_trace_start(window)
+                || match self.plausible_header(window, last_header) {
+                    Some((header, len)) => match window.get(len..) {
+                        Some(next) if next.len() >= 8 => {
+                            is_trace_start(next)
+                                || self.plausible_header(next, Some(header)).is_some()
+                        }
+                        _ => true,
+                    },
+                    None => false,
+                };
+            if plausible {
+                self.r.unread(window);
+                break;
+            }
+            pos += 1;
+            end += 1;
+            if pos >= 4096 {
+                buf.drain(..pos);
+                pos = 0;
+            }
+        }
+
+        let len = end - offset;
+        warn!(offset, len, "Skipped corrupted data");
+        match summary.skipped.last_mut() {
+            Some(prev) if prev.offset + prev.len == offset => prev.len += len,
+            _ => summary.skipped.push(SkippedRange { offset, len }),
+        }
+        Ok(())
+    }
+
+    /// Event count, timestamp and size of the event starting with these bytes,
+    /// if its header is plausible
+    fn plausible_header(
+        &self,
+        bytes: &[u8],
+        last_header: Option<(u16, u32)>,
+    ) -> Option<((u16, u32), usize)> {
+        let (code, count, timestamp) = match self.rd.header.endianness {
+            Endianness::Little => (
+                u16::from_le_bytes([bytes[0], bytes[1]]),
+                u16::from_le_bytes([bytes[2], bytes[3]]),
+                u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            ),
+            Endianness::Big => (
+                u16::from_be_bytes([bytes[0], bytes[1]]),
+                u16::from_be_bytes([bytes[2], bytes[3]]),
+                u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            ),
+        };
+        let id = EventId(code & 0x0F_FF);
+        let known = match EventType::from(id) {
+            EventType::Null => false,
+            EventType::Unknown(_) => {
+                self.custom_printf_event_id == Some(id)
+                    || self
+                        .schema
+                        .and_then(|s| s.name(EventType::from(id)))
+                        .is_some()
+            }
+            _ => true,
+        };
+        let (_, count) = split_event_count(self.rd.header.num_cores, count);
+        let len = 8 + 4 * usize::from(code >> 12);
+        (known && self.plausible_event(count, timestamp, last_header))
+            .then_some(((count, timestamp), len))
+    }
+
+    /// Whether the event count and timestamp follow the last event's
+    fn plausible_event(&self, count: u16, timestamp: u32, last_header: Option<(u16, u32)>) -> bool {
+        let Some((last_count, last_timestamp)) = last_header else {
+            return true;
+        };
+        let mask = if self.rd.header.num_cores > 1 {
+            EventCounter::SMP_MASK
+        } else {
+            u16::MAX
+        };
+        let count_step = count.wrapping_sub(last_count) & mask;
+        if count_step == 0 || count_step > mask / RESYNC_MAX_COUNT_STEP_DIV {
+            return false;
+        }
+
+        let time_step = if timestamp >= last_timestamp {
+            u64::from(timestamp - last_timestamp)
+        } else {
+            // Timer wraparound
+            let wraparound = self.rd.timer_wraparound();
+            wraparound.saturating_sub(u64::from(last_timestamp)) + u64::from(timestamp)
+        };
+        let freq = self.rd.timestamp_info.timer_frequency;
+        let max_time_step = if freq.is_unitless() {
+            u64::from(u32::MAX / 2)
+        } else {
+            u64::from(freq.get_raw()) * RESYNC_MAX_TIME_STEP_SECS
+        };
+        time_step <= max_time_step
+    }
+}
+
+/// Whether the bytes start with the PSF start word of a restarted trace
+fn is_trace_start(bytes: &[u8]) -> bool {
+    let word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    word == HeaderInfo::PSF_LITTLE_ENDIAN || word == HeaderInfo::PSF_BIG_ENDIAN
+}
@@ -0,0 +1,489 @@
+use crate::elf::FirmwareElf;
+use std::fmt;
+use trace_recorder_parser::{
+    streaming::{
+        event::{EventId, EventType},
+        EntryTable,
+    },
+    time::{Ticks, Timestamp},
+    types::{Endianness, UserEventChannel},
+};
+
+/// Event ID of the first fixed user event (PSF_EVENT_USER_EVENT_FIXED)
+const FIXED_USER_EVENT_ID: u16 = 0x98;
+
+/// A fixed user event whose format string isn't in the entry table, because
+/// the recorder only logged its address.
+/// It gets decoded from the raw event data, with the format string read from the firmware ELF.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct ElfUserEvent {
+    pub event_type: EventType,
+    pub event_count: u16,
+    pub timestamp: Timestamp,
+    pub channel: String,
+    pub format_string: String,
+    pub formatted_string: String,
+}
+
+impl ElfUserEvent {
+    /// Decode the raw event data (header and parameters) of a fixed user event
+    pub fn decode(
+        raw: &[u8],
+        endianness: Endianness,
+        entry_table: &EntryTable,
+        elf: &FirmwareElf,
+    ) -> Option<Self> {
+        let mut r = WordReader::new(raw, endianness);
+        let event_code = r.u16()?;
+        let event_count = r.u16()?;
+        let timestamp = Timestamp::from(Ticks::new(r.u32()?));
+
+        let event_id = EventId(event_code & 0x0F_FF);
+        let event_type = EventType::from(event_id);
+        if !matches!(event_type, EventType::UserEvent(_)) || event_id.0 < FIXED_USER_EVENT_ID {
+            return None;
+        }
+        let arg_count = usize::from(event_id.0 - FIXED_USER_EVENT_ID);
+
+        // <channel-handle> <format-string-address> [args]
+        let channel_handle = r.u32()?;
+        let format_string_address = r.u32()?;
+        let args = r.take(arg_count * 4)?;
+
+        let channel = entry_symbol(entry_table, channel_handle)
+            .map(|s| s.to_owned())
+            .unwrap_or_else(|| UserEventChannel::DEFAULT.to_owned());
+        let format_string = elf.string(format_string_address)?;
+        let formatted_string = format_string_args(&format_string, args, endianness, |addr| {
+            entry_symbol(entry_table, addr)
+                .map(|s| s.to_owned())
+                .or_else(|| elf.string(addr))
+        });
+
+        Some(Self {
+            event_type,
+            event_count,
+            timestamp,
+            channel,
+            format_string,
+            formatted_string,
+        })
+    }
+}
+
+impl fmt::Display for ElfUserEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{}]:[{}]='{}'",
+            self.timestamp, self.channel, self.formatted_string
+        )
+    }
+}
+
+/// Look up a symbol by its raw handle
+pub(crate) fn entry_symbol(entry_table: &EntryTable, handle: u32) -> Option<&str> {
+    entry_table
+        .entries()
+        .iter()
+        .find(|(h, _)| u32::from(**h) == handle)
+        .and_then(|(_, e)| e.symbol.as_deref())
+}
+
+/// Render a printf-style format string with the streaming protocol argument data,
+/// where every argument occupies at least one 32-bit word, and 64-bit arguments (`%lld`, `%lf`)
+/// two. String arguments are addresses, resolved with `string`.
+pub fn format_string_args<F>(
+    format_string: &str,
+    args: &[u8],
+    endianness: Endianness,
+    string: F,
+) -> String
+where
+    F: Fn(u32) -> Option<String>,
+{
+    let mut r = WordReader::new(args, endianness);
+    let mut out = String::new();
+    let mut chars = format_string.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match Spec::parse(&mut chars) {
+            Ok(spec) => match spec.render(&mut r, &string) {
+                Some(s) => out.push_str(&s),
+                None => out.push_str(&spec.text),
+            },
+            Err(text) => {
+                out.push_str(&text);
+                break;
+            }
+        }
+    }
+    out
+}
+
+/// Argument words of a format string holding `%s` arguments, string addresses or handles,
+/// counted the way [`format_string_args`] reads them
+pub fn string_arg_words(format_string: &str) -> Vec<usize> {
+    let mut words = Vec::new();
+    let mut word = 0;
+    let mut chars = format_string.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            continue;
+        }
+        let Ok(spec) = Spec::parse(&mut chars) else {
+            break;
+        };
+        if spec.conversion == 's' {
+            words.push(word);
+        }
+        word += spec.words();
+    }
+    words
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+enum Length {
+    #[default]
+    Int,
+    /// `h`
+    Short,
+    /// `hh`, or `b` in TraceRecorder format strings
+    Octet,
+    /// `l`, a double with `%lf`
+    Long,
+    /// `ll`
+    LongLong,
+}
+
+/// Conversion specification, `%[flags][width][.precision][length]conversion`
+#[derive(Clone, Debug, Default)]
+struct Spec {
+    /// As written in the format string
+    text: String,
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alt: bool,
+    width: usize,
+    precision: Option<usize>,
+    length: Length,
+    conversion: char,
+}
+
+impl Spec {
+    /// Parse the specification following a `%`, the text read so far when it's incomplete
+    fn parse(chars: &mut std::str::Chars) -> Result<Self, String> {
+        let mut spec = Spec {
+            text: String::from("%"),
+            ..Default::default()
+        };
+        // Flags, width, precision, length
+        let mut state = 0;
+        for c in chars.by_ref() {
+            spec.text.push(c);
+            match c {
+                '-' | '0' | '+' | ' ' | '#' if state == 0 => match c {
+                    '-' => spec.left = true,
+                    '0' => spec.zero = true,
+                    '+' => spec.plus = true,
+                    ' ' => spec.space = true,
+                    _ => spec.alt = true,
+                },
+                '0'..='9' if state <= 1 => {
+                    spec.width = spec.width * 10 + c.to_digit(10).unwrap_or(0) as usize;
+                    state = 1;
+                }
+                '.' if state <= 1 => {
+                    spec.precision = Some(0);
+                    state = 2;
+                }
+                '0'..='9' if state == 2 => {
+                    spec.precision = spec
+                        .precision
+                        .map(|p| p * 10 + c.to_digit(10).unwrap_or(0) as usize);
+                }
+                'h' | 'b' | 'l' | 'z' | 't' | 'j' => {
+                    spec.length = match (c, spec.length) {
+                        ('h', Length::Short) | ('b', _) => Length::Octet,
+                        ('h', _) => Length::Short,
+                        ('l', Length::Long) => Length::LongLong,
+                        ('l', _) => Length::Long,
+                        _ => spec.length,
+                    };
+                    state = 3;
+                }
+                _ => {
+                    spec.conversion = c;
+                    return Ok(spec);
+                }
+            }
+        }
+        Err(spec.text)
+    }
+
+    /// Argument words read by the conversion
+    fn words(&self) -> usize {
+        match self.conversion {
+            'd' | 'i' | 'u' | 'o' | 'x' | 'X' if self.length == Length::LongLong => 2,
+            'f' | 'F' if matches!(self.length, Length::Long | Length::LongLong) => 2,
+            'd' | 'i' | 'u' | 'o' | 'x' | 'X' | 'p' | 'c' | 's' | 'f' | 'F' => 1,
+            _ => 0,
+        }
+    }
+
+    /// Render the conversion with the next arguments, `None` when they're missing
+    fn render<F>(&self, r: &mut WordReader, string: &F) -> Option<String>
+    where
+        F: Fn(u32) -> Option<String>,
+    {
+        let int = |r: &mut WordReader| match self.length {
+            Length::LongLong => r.u64(),
+            _ => r.u32().map(u64::from),
+        };
+        Some(match self.conversion {
+            '%' => "%".to_owned(),
+            'd' | 'i' => {
+                let v = int(r)?;
+                let v = match self.length {
+                    Length::Short => i64::from(v as i16),
+                    Length::Octet => i64::from(v as i8),
+                    Length::LongLong => v as i64,
+                    _ => i64::from(v as i32),
+                };
+                let sign = if v < 0 {
+                    "-"
+                } else if self.plus {
+                    "+"
+                } else if self.space {
+                    " "
+                } else {
+                    ""
+                };
+                self.pad_number(sign, self.digits(v.unsigned_abs().to_string()))
+            }
+            'u' | 'o' | 'x' | 'X' => {
+                let v = int(r)?;
+                let v = match self.length {
+                    Length::Short => u64::from(v as u16),
+                    Length::Octet => u64::from(v as u8),
+                    _ => v,
+                };
+                let (digits, prefix) = match self.conversion {
+                    'o' => (format!("{v:o}"), "0"),
+                    'x' => (format!("{v:x}"), "0x"),
+                    'X' => (format!("{v:X}"), "0X"),
+                    _ => (v.to_string(), ""),
+                };
+                let digits = self.digits(digits);
+                let prefix = if self.alt && v != 0 && !digits.starts_with('0') {
+                    prefix
+                } else {
+                    ""
+                };
+                self.pad_number(prefix, digits)
+            }
+            'p' => self.pad(format!("{:X}", r.u32()?)),
+            'c' => self.pad(char::from_u32(r.u32()?)?.to_string()),
+            's' => {
+                let addr = r.u32()?;
+                let s = string(addr).unwrap_or_else(|| format!("<0x{addr:08X}>"));
+                let s = match self.precision {
+                    Some(p) => s.chars().take(p).collect(),
+                    None => s,
+                };
+                self.pad(s)
+            }
+            'f' | 'F' => {
+                let v = match self.length {
+                    Length::Long | Length::LongLong => f64::from_bits(r.u64()?),
+                    _ => f64::from(f32::from_bits(r.u32()?)),
+                };
+                let precision = self.precision.unwrap_or(6);
+                let body = format!("{:.*}", precision, v.abs());
+                let sign = if v.is_sign_negative() {
+                    "-"
+                } else if self.plus {
+                    "+"
+                } else {
+                    ""
+                };
+                self.pad_number(sign, body)
+            }
+            _ => return None,
+        })
+    }
+
+    /// Integer digits, zero extended to the precision
+    fn digits(&self, digits: String) -> String {
+        match self.precision {
+            Some(0) if digits == "0" => String::new(),
+            Some(p) if digits.len() < p => format!("{}{digits}", "0".repeat(p - digits.len())),
+            _ => digits,
+        }
+    }
+
+    /// Pad a number to the width, zeros go between the sign or prefix and the digits
+    fn pad_number(&self, sign: &str, digits: String) -> String {
+        let zero = self.zero && !self.left && (self.precision.is_none() || self.is_float());
+        let len = sign.len() + digits.chars().count();
+        if zero && len < self.width {
+            format!("{sign}{}{digits}", "0".repeat(self.width - len))
+        } else {
+            self.pad(format!("{sign}{digits}"))
+        }
+    }
+
+    fn pad(&self, s: String) -> String {
+        let len = s.chars().count();
+        if len >= self.width {
+            s
+        } else if self.left {
+            format!("{s}{}", " ".repeat(self.width - len))
+        } else {
+            format!("{}{s}", " ".repeat(self.width - len))
+        }
+    }
+
+    fn is_float(&self) -> bool {
+        matches!(self.conversion, 'f' | 'F')
+    }
+}
+
+/// Endian-aware reader over raw event data
+struct WordReader<'a> {
+    data: &'a [u8],
+    endianness: Endianness,
+}
+
+impl<'a> WordReader<'a> {
+    fn new(data: &'a [u8], endianness: Endianness) -> Self {
+        Self { data, endianness }
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() < len {
+            return None;
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(bytes)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let b: [u8; 2] = self.take(2)?.try_into().ok()?;
+        Some(match self.endianness {
+            Endianness::Little => u16::from_le_bytes(b),
+            Endianness::Big => u16::from_be_bytes(b),
+        })
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let b: [u8; 4] = self.take(4)?.try_into().ok()?;
+        Some(match self.endianness {
+            Endianness::Little => u32::from_le_bytes(b),
+            Endianness::Big => u32::from_be_bytes(b),
+        })
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let b: [u8; 8] = self.take(8)?.try_into().ok()?;
+        Some(match self.endianness {
+            Endianness::Little => u64::from_le_bytes(b),
+            Endianness::Big => u64::from_be_bytes(b),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(args: &[u32], endianness: Endianness) -> Vec<u8> {
+        args.iter()
+            .flat_map(|a| match endianness {
+                Endianness::Little => a.to_le_bytes(),
+                Endianness::Big => a.to_be_bytes(),
+            })
+            .collect()
+    }
+
+    fn render(format_string: &str, args: &[u32]) -> String {
+        let args = words(args, Endianness::Little);
+        format_string_args(format_string, &args, Endianness::Little, |addr| {
+            (addr == 0x0800_0000).then(|| "motor".to_owned())
+        })
+    }
+
+    #[test]
+    fn width_padding_and_precision() {
+        assert_eq!(render("%08X", &[0xBEEF]), "0000BEEF");
+        assert_eq!(render("%#010x", &[0xBEEF]), "0x0000beef");
+        assert_eq!(render("[%5d|%-5d]", &[42, 42]), "[   42|42   ]");
+        assert_eq!(render("%05d", &[-42i32 as u32]), "-0042");
+        assert_eq!(render("%+d % d", &[7, 7]), "+7  7");
+        assert_eq!(render("%.4u", &[42]), "0042");
+        assert_eq!(render("%8.3d", &[42]), "     042");
+        assert_eq!(render("%.2f", &[1.5f32.to_bits()]), "1.50");
+        assert_eq!(render("%07.2f", &[(-1.5f32).to_bits()]), "-001.50");
+        assert_eq!(render("%f", &[0.25f32.to_bits()]), "0.250000");
+        assert_eq!(
+            render("%-7s|%.3s", &[0x0800_0000, 0x0800_0000]),
+            "motor  |mot"
+        );
+        assert_eq!(render("%c%c", &['o' as u32, 'k' as u32]), "ok");
+    }
+
+    #[test]
+    fn argument_sizes() {
+        assert_eq!(render("%hd %hu", &[0xFFFF, 0x1_0001]), "-1 1");
+        assert_eq!(render("%hhd %bu", &[0xFF, 0x1FF]), "-1 255");
+        assert_eq!(render("%ld %lu", &[-1i32 as u32, 5]), "-1 5");
+        assert_eq!(render("%s", &[0x2000_0000]), "<0x20000000>");
+    }
+
+    #[test]
+    fn long_long_reads_two_words() {
+        let v: u64 = 0x1_0000_0002;
+        for endianness in [Endianness::Little, Endianness::Big] {
+            let mut args = match endianness {
+                Endianness::Little => v.to_le_bytes(),
+                Endianness::Big => v.to_be_bytes(),
+            }
+            .to_vec();
+            args.extend(words(&[7], endianness));
+            let s = format_string_args("%llu %d", &args, endianness, |_| None);
+            assert_eq!(s, "4294967298 7");
+        }
+        let args = (-3i64).to_le_bytes();
+        assert_eq!(
+            format_string_args("%lld", &args, Endianness::Little, |_| None),
+            "-3"
+        );
+        let args = 2.5f64.to_le_bytes();
+        assert_eq!(
+            format_string_args("%.1lf", &args, Endianness::Little, |_| None),
+            "2.5"
+        );
+    }
+
+    #[test]
+    fn missing_and_unknown_conversions() {
+        assert_eq!(render("100%%", &[]), "100%");
+        assert_eq!(render("%d and %08X", &[1]), "1 and %08X");
+        assert_eq!(render("%q %d", &[3]), "%q 3");
+        assert_eq!(render("tail %-0", &[]), "tail %-0");
+    }
+
+    #[test]
+    fn string_argument_words() {
+        assert_eq!(string_arg_words("%s"), [0]);
+        assert_eq!(string_arg_words("%d %u"), Vec::<usize>::new());
+        assert_eq!(string_arg_words("%lld %s %f %lf %s %% %s"), [2, 6, 7]);
+        assert_eq!(string_arg_words("%08X %.3s"), [1]);
+    }
+}
@@ -0,0 +1,530 @@
+//! Focused tests of the options and subcommands, on the recordings in `tests/fixtures`.
+
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+use trace_recorder_parser::{streaming::RecorderData, types::Endianness};
+use trace_recorder_printer::{
+    analyzer::{Analyzer, TaskStateAnalyzer},
+    psf::RawEvent,
+    trace::{EventRecord, TraceReader, TraceSummary},
+};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// Empty directory for the outputs of a test
+fn out_dir(test: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(test);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn run<I, S>(args: I) -> Output
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+{
+    Command::new(env!("CARGO_BIN_EXE_trace-recorder-printer"))
+        .args(args)
+        .env("RUST_LOG", "off")
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+/// Event counts, timestamps and types of a recording, with its summary
+#[derive(Default)]
+struct Events(Vec<(u8, u16, u64, String)>);
+
+impl Analyzer for Events {
+    fn on_event(&mut self, _rd: &RecorderData, record: &EventRecord) {
+        self.0.push((
+            record.core_id,
+            record.event_count,
+            record.trace_time.get_raw(),
+            record.event_type.to_string(),
+        ));
+    }
+}
+
+fn read(path: &Path) -> (RecorderData, TraceSummary, Events) {
+    let mut reader = TraceReader::new(BufReader::new(File::open(path).unwrap())).unwrap();
+    let mut events = Events::default();
+    let summary = reader.run(&mut [&mut events]).unwrap();
+    (reader.recorder_data().clone(), summary, events)
+}
+
+#[test]
+fn elf_resolves_heap_addresses() {
+    let output = run([
+        "--elf".as_ref(),
+        fixture("firmware.elf").as_os_str(),
+        fixture("freertos_le.psf").as_os_str(),
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let out = stdout(&output);
+    assert!(
+        out.contains("MemoryAlloc([40]:0x20010000:64) : 10 : ucHeap\n"),
+        "{out}"
+    );
+    assert!(
+        out.contains("MemoryAlloc([208]:0x20010040:32) : 17 : ucHeap+0x40\n"),
+        "{out}"
+    );
+    // Named objects aren't resolved
+    assert!(!out.contains("TaskCreate([0]:'Control':3) : 1 :"), "{out}");
+}
+
+#[test]
+fn event_schema_decodes_application_events() {
+    let dir = out_dir("event_schema");
+    // The fixture, followed by an application specific event
+    let (_, _, events) = read(&fixture("freertos_le.psf"));
+    let (_, count, time, _) = events.0.last().cloned().unwrap();
+    let mut psf = fs::read(fixture("freertos_le.psf")).unwrap();
+    RawEvent {
+        endianness: Endianness::Little,
+        id: 0x1A0,
+        num_params: 1,
+        count: count + 1,
+        timestamp: time as u32 + 10,
+        body: vec![1, 3, 0xE8, 0x03],
+    }
+    .write(&mut psf)
+    .unwrap();
+    let path = dir.join("app.psf");
+    fs::write(&path, psf).unwrap();
+    // The same schema in both formats
+    for schema in ["schema.toml", "schema.yaml"] {
+        let output = run([
+            "--event-schema".as_ref(),
+            fixture(schema).as_os_str(),
+            path.as_os_str(),
+        ]);
+        assert!(output.status.success(), "{output:?}");
+        let out = stdout(&output);
+        assert!(
+            out.contains("MOTOR_STATE : Custom([5518]:'motor 1 -> 3 @ 1000 rpm')"),
+            "{out}"
+        );
+    }
+}
+
+#[test]
+fn check_exit_status() {
+    let dir = out_dir("check");
+    let rules = dir.join("rules.toml");
+    fs::write(
+        &rules,
+        r#"
+[[rule]]
+description = "IDLE has headroom"
+context = "IDLE"
+metric = "cpu_percent"
+gt = 20
+
+[[rule]]
+metric = "dropped_events"
+eq = 0
+"#,
+    )
+    .unwrap();
+    let args = |psf: &str| {
+        run([
+            "--no-events".as_ref(),
+            "--check".as_ref(),
+            rules.as_os_str(),
+            fixture(psf).as_os_str(),
+        ])
+    };
+
+    let output = args("freertos_le.psf");
+    assert!(output.status.success(), "{output:?}");
+    assert!(stdout(&output).contains("Checks: 2 passed, 0 failed"));
+
+    let output = args("dropped.psf");
+    assert_eq!(output.status.code(), Some(65), "{output:?}");
+    let out = stdout(&output);
+    assert!(out.contains("FAIL   ┆ dropped events == 0"), "{out}");
+    assert!(out.contains("Checks: 1 passed, 1 failed"), "{out}");
+}
+
+#[test]
+fn gantt_range_checked_before_output() {
+    let output = run([
+        "--gantt".as_ref(),
+        "40".as_ref(),
+        "--gantt-range".as_ref(),
+        "5..5".as_ref(),
+        fixture("freertos_le.psf").as_os_str(),
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    assert!(stdout(&output).is_empty(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("The time range is empty"));
+}
+
+#[test]
+fn validate_accepts_a_clean_capture() {
+    let output = run(["validate".as_ref(), fixture("freertos_le.psf").as_os_str()]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(stdout(&output).contains("Findings: 0 errors, 0 warnings, 0 info"));
+}
+
+#[test]
+fn validate_reports_timestamp_going_backwards() {
+    let output = run(["validate".as_ref(), fixture("timer_glitch.psf").as_os_str()]);
+    let out = stdout(&output);
+    assert_eq!(output.status.code(), Some(65), "{out}");
+    assert!(
+        out.contains("Timestamp went backwards from 1418 to 1300"),
+        "{out}"
+    );
+    // The raw ticks of the event, not the wrapped time since the start
+    assert!(out.contains("┆ 1300 ticks ┆ timestamp ┆"), "{out}");
+    assert!(out.contains("Findings: 1 errors"), "{out}");
+}
+
+#[test]
+fn blocking_waits_and_timeouts() {
+    let mut reader =
+        TraceReader::new(BufReader::new(File::open(fixture("blocking.psf")).unwrap())).unwrap();
+    let mut tasks = TaskStateAnalyzer::default();
+    reader.run(&mut [&mut tasks]).unwrap();
+    let rd = reader.recorder_data();
+
+    // Waits and timeouts on each object, every third queue receive and every second
+    // semaphore take fail after their wait
+    let mut waits: Vec<(String, String, u64, u64)> = tasks
+        .stats
+        .iter()
+        .flat_map(|(task, stats)| {
+            stats.waits.iter().filter_map(move |(reason, w)| {
+                let object = reason.object?;
+                Some((
+                    rd.entry_table.symbol(*task)?.to_string(),
+                    rd.entry_table.symbol(object)?.to_string(),
+                    w.count,
+                    w.timeouts,
+                ))
+            })
+        })
+        .collect();
+    waits.sort();
+    let expected = [
+        ("Comms", "RxQueue", 10, 3),
+        ("Logger", "BusLock", 4, 0),
+        ("Writer", "TxDone", 8, 4),
+    ];
+    assert_eq!(
+        waits,
+        expected.map(|(t, o, c, f)| (t.to_owned(), o.to_owned(), c, f))
+    );
+}
+
+#[test]
+fn split_sessions() {
+    let dir = out_dir("split_sessions");
+    let output = run([
+        "split".as_ref(),
+        "--sessions".as_ref(),
+        "--output".as_ref(),
+        dir.join("session.psf").as_os_str(),
+        fixture("restart.psf").as_os_str(),
+    ]);
+    assert!(output.status.success(), "{output:?}");
+
+    let (_, summary, events) = read(&fixture("restart.psf"));
+    let (_, first, first_events) = read(&dir.join("session-0.psf"));
+    let (_, second, _) = read(&dir.join("session-1.psf"));
+    assert_eq!(summary.restarts, 1);
+    assert_eq!((first.restarts, second.restarts), (0, 0));
+    assert_eq!(
+        first.total_events + second.total_events,
+        summary.total_events
+    );
+    assert_eq!(
+        first_events.0[..],
+        events.0[..first_events.0.len()],
+        "the first session is copied as recorded"
+    );
+}
+
+#[test]
+fn split_time_range() {
+    let dir = out_dir("split_time_range");
+    let path = dir.join("part.psf");
+    let output = run([
+        "split".as_ref(),
+        "--time".as_ref(),
+        "1000..3000".as_ref(),
+        "--output".as_ref(),
+        path.as_os_str(),
+        fixture("freertos_le.psf").as_os_str(),
+    ]);
+    assert!(output.status.success(), "{output:?}");
+
+    let (_, _, events) = read(&path);
+    assert!(!events.0.is_empty());
+    assert!(events.0.iter().all(|e| (1000..3000).contains(&e.2)));
+    let output = run(["validate".as_ref(), path.as_os_str()]);
+    assert!(output.status.success(), "{}", stdout(&output));
+}
+
+#[test]
+fn anonymize_keeps_timing() {
+    let dir = out_dir("anonymize");
+    let path = dir.join("shared.psf");
+    let mapping = dir.join("mapping.toml");
+    let output = run([
+        "anonymize".as_ref(),
+        "--addresses".as_ref(),
+        "--mapping".as_ref(),
+        mapping.as_os_str(),
+        "--output".as_ref(),
+        path.as_os_str(),
+        fixture("freertos_le.psf").as_os_str(),
+    ]);
+    assert!(output.status.success(), "{output:?}");
+
+    let (_, summary, events) = read(&fixture("freertos_le.psf"));
+    let (_, anon_summary, anon_events) = read(&path);
+    assert_eq!(anon_summary.total_events, summary.total_events);
+    assert_eq!(anon_events.0, events.0);
+
+    let mapping = fs::read_to_string(&mapping).unwrap();
+    assert!(mapping.contains("Control = \"Task1\""), "{mapping}");
+    assert!(
+        mapping.contains("\"setpoint %d\" = \"Format1 %d\""),
+        "{mapping}"
+    );
+    let out = stdout(&run(["--user-events".as_ref(), path.as_os_str()]));
+    assert!(out.contains("[Channel1]='Format1 42'"), "{out}");
+    assert!(
+        !out.contains("Control") && !out.contains("setpoint"),
+        "{out}"
+    );
+}
+
+#[test]
+fn anonymize_undecodable_events() {
+    let dir = out_dir("anonymize_corrupted");
+    let path = dir.join("shared.psf");
+    let mapping = dir.join("mapping.toml");
+    let corrupted = fixture("corrupted.psf");
+    let args = |resync: bool| {
+        let mut args = vec![
+            "anonymize".as_ref(),
+            "--mapping".as_ref(),
+            mapping.as_os_str(),
+        ];
+        if resync {
+            args.push("--resync".as_ref());
+        }
+        args.extend(["--output".as_ref(), path.as_os_str(), corrupted.as_os_str()]);
+        run(args)
+    };
+
+    // No output rather than a silently shorter recording
+    let output = args(false);
+    assert!(!output.status.success(), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Can't anonymize the event at offset 460"),
+        "{output:?}"
+    );
+    assert!(!path.exists());
+
+    let output = args(true);
+    assert!(output.status.success(), "{output:?}");
+    let out = stdout(&output);
+    assert!(out.contains("Wrote 30 events"), "{out}");
+    assert!(
+        out.contains("Skipped 19 bytes of corrupted data, not written"),
+        "{out}"
+    );
+}
+
+#[test]
+fn merge_consecutive() {
+    let dir = out_dir("merge_consecutive");
+    let path = dir.join("merged.psf");
+    let output = run([
+        "merge".as_ref(),
+        "--output".as_ref(),
+        path.as_os_str(),
+        fixture("freertos_le.psf").as_os_str(),
+        fixture("dropped.psf").as_os_str(),
+    ]);
+    assert!(output.status.success(), "{output:?}");
+
+    let (_, first, _) = read(&fixture("freertos_le.psf"));
+    let (_, second, _) = read(&fixture("dropped.psf"));
+    let (_, merged, events) = read(&path);
+    // One session, the second capture continues where the first one ends
+    assert_eq!(merged.restarts, 0);
+    assert!(merged.total_events >= first.total_events + second.total_events);
+    assert_eq!(merged.dropped_events, second.dropped_events);
+    assert!(events.0.windows(2).all(|w| w[0].2 <= w[1].2));
+}
+
+#[test]
+fn merge_parallel_is_smp() {
+    let dir = out_dir("merge_parallel");
+    let path = dir.join("boards.psf");
+    let output = run([
+        "merge".as_ref(),
+        "--parallel".as_ref(),
+        "--offset".as_ref(),
+        "0,100us".as_ref(),
+        "--output".as_ref(),
+        path.as_os_str(),
+        fixture("freertos_le.psf").as_os_str(),
+        fixture("restart.psf").as_os_str(),
+    ]);
+    assert!(output.status.success(), "{output:?}");
+
+    // The core ID is split from the event counts, which keep counting over both cores
+    let (rd, summary, events) = read(&path);
+    assert_eq!(rd.header.num_cores, 2);
+    assert_eq!(summary.dropped_events, 0);
+    assert!(events.0.iter().any(|e| e.0 == 0) && events.0.iter().any(|e| e.0 == 1));
+    assert!(events.0.windows(2).all(|w| w[1].1 == w[0].1 + 1));
+
+    let out = stdout(&run([path.as_os_str()]));
+    assert!(out.contains("[core 0] TASK_CREATE"), "{out}");
+    assert!(
+        out.contains("[core 1] TASK_CREATE : TaskCreate([100]:'node1/Worker':2)"),
+        "{out}"
+    );
+}
+
+#[test]
+fn exports() {
+    let dir = out_dir("exports");
+    let output = run([
+        "--no-events".as_ref(),
+        "--vcd".as_ref(),
+        dir.join("out.vcd").as_os_str(),
+        "--folded".as_ref(),
+        dir.join("out.folded").as_os_str(),
+        "--ctf".as_ref(),
+        dir.join("ctf").as_os_str(),
+        "--html".as_ref(),
+        dir.join("report.html").as_os_str(),
+        fixture("freertos_le.psf").as_os_str(),
+    ]);
+    assert!(output.status.success(), "{output:?}");
+
+    let vcd = fs::read_to_string(dir.join("out.vcd")).unwrap();
+    assert!(vcd.contains("$var reg 2 ! Control $end"), "{vcd}");
+    assert!(vcd.contains("$var wire 1 $ Uart $end"), "{vcd}");
+
+    let folded = fs::read_to_string(dir.join("out.folded")).unwrap();
+    assert!(folded.starts_with("core 0;Control "), "{folded}");
+    assert!(folded.contains("core 0;Uart;Timer0;IDLE "), "{folded}");
+
+    let metadata = fs::read_to_string(dir.join("ctf/metadata")).unwrap();
+    assert!(metadata.starts_with("/* CTF 1.8 */"));
+    assert!(dir.join("ctf/stream_0").is_file());
+
+    let html = fs::read_to_string(dir.join("report.html")).unwrap();
+    assert!(html.contains("<svg"));
+    assert!(html.contains("Control"));
+}
+
+#[test]
+fn html_report_of_smp_capture() {
+    let dir = out_dir("html");
+    let path = dir.join("boards.psf");
+    let output = run([
+        "merge".as_ref(),
+        "--parallel".as_ref(),
+        "--output".as_ref(),
+        path.as_os_str(),
+        fixture("freertos_le.psf").as_os_str(),
+        fixture("freertos_le.psf").as_os_str(),
+    ]);
+    assert!(output.status.success(), "{output:?}");
+
+    let report = dir.join("report.html");
+    let output = run([
+        "--no-events".as_ref(),
+        "--html".as_ref(),
+        report.as_os_str(),
+        "--html-max-events".as_ref(),
+        "5".as_ref(),
+        path.as_os_str(),
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let html = fs::read_to_string(report).unwrap();
+
+    // The pie slices are shares of the CPU time of both cores
+    let pie = html
+        .split("<h2>CPU Usage</h2>")
+        .nth(1)
+        .and_then(|s| s.split("</svg>").next())
+        .unwrap();
+    let total: f64 = pie
+        .split("%</text>")
+        .filter_map(|s| s.rsplit(' ').next())
+        .filter_map(|p| p.parse::<f64>().ok())
+        .sum();
+    assert!((total - 100.0).abs() < 0.1, "{pie}");
+
+    let events = html.split("<table id=\"events\">").nth(1).unwrap();
+    assert_eq!(events.matches("<tr><td>").count(), 5);
+    assert!(html.contains("The first 5 events are listed"), "{html}");
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite_export() {
+    let dir = out_dir("sqlite");
+    let db = dir.join("out.db");
+    let output = run([
+        "--no-events".as_ref(),
+        "--sqlite".as_ref(),
+        db.as_os_str(),
+        fixture("restart.psf").as_os_str(),
+    ]);
+    assert!(output.status.success(), "{output:?}");
+
+    let (_, summary, _) = read(&fixture("restart.psf"));
+    let conn = rusqlite::Connection::open(&db).unwrap();
+    let count = |sql: &str| -> i64 { conn.query_row(sql, [], |r| r.get(0)).unwrap() };
+    assert_eq!(
+        count("SELECT COUNT(*) FROM events") as u64,
+        summary.total_events
+    );
+    assert_eq!(count("SELECT COUNT(*) FROM sessions"), 2);
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn parquet_export() {
+    let dir = out_dir("parquet");
+    let output = run([
+        "--no-events".as_ref(),
+        "--parquet".as_ref(),
+        dir.as_os_str(),
+        fixture("freertos_le.psf").as_os_str(),
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    for name in ["events.parquet", "context_intervals.parquet"] {
+        let data = fs::read(dir.join(name)).unwrap();
+        assert!(
+            data.starts_with(b"PAR1") && data.ends_with(b"PAR1"),
+            "{name}"
+        );
+    }
+}
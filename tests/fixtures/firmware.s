@@ -0,0 +1,13 @@
+/* Symbols of the generated recordings, for the --elf tests. firmware.elf is built with:
+ *   as firmware.s -o firmware.o
+ *   ld -nostdlib -static -z max-page-size=16 -z noseparate-code \
+ *      -Tbss=0x20010000 -e 0 firmware.o -o firmware.elf
+ */
+
+/* System heap of the generator, the allocations start at 0x20010000 */
+    .bss
+    .globl ucHeap
+    .type ucHeap, @object
+    .size ucHeap, 65536
+ucHeap:
+    .zero 65536
@@ -0,0 +1,263 @@
+//! Golden output of the printer on the recordings in `tests/fixtures`.
+//!
+//! The recordings are generated from the descriptions next to them. After an intended change
+//! of the output, or of a description, rewrite the recordings and the snapshots in
+//! `tests/snapshots` with `UPDATE_SNAPSHOTS=1 cargo test --test golden` and review the diff.
+
+use serde_json::{json, Value};
+use std::{
+    env,
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+    process::Command,
+};
+use trace_recorder_parser::streaming::RecorderData;
+use trace_recorder_printer::{
+    analyzer::{Analyzer, ContextAnalyzer, EventTypeCounter, StackAnalyzer},
+    generate::TraceSpec,
+    report::TraceReport,
+    trace::{EventRecord, TraceReader},
+};
+
+struct Fixture {
+    name: &'static str,
+    custom_printf_event_id: Option<u16>,
+    resync: bool,
+}
+
+impl Fixture {
+    const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            custom_printf_event_id: None,
+            resync: false,
+        }
+    }
+
+    fn psf(&self) -> PathBuf {
+        fixtures_dir().join(format!("{}.psf", self.name))
+    }
+
+    fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(id) = self.custom_printf_event_id {
+            args.push("--custom-printf-event-id".to_owned());
+            args.push(format!("0x{id:X}"));
+        }
+        if self.resync {
+            args.push("--resync".to_owned());
+        }
+        args.push(self.psf().display().to_string());
+        args
+    }
+}
+
+fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+fn snapshots_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots")
+}
+
+fn update() -> bool {
+    env::var_os("UPDATE_SNAPSHOTS").is_some()
+}
+
+/// Compare with the golden file, or rewrite it when updating.
+/// Returns a description of the first difference.
+fn check_golden(path: &Path, actual: &[u8]) -> Option<String> {
+    if update() {
+        fs::write(path, actual).unwrap();
+        return None;
+    }
+    let expected = match fs::read(path) {
+        Ok(expected) => expected,
+        Err(e) => return Some(format!("{}: {e}", path.display())),
+    };
+    if expected == actual {
+        return None;
+    }
+    let expected = String::from_utf8_lossy(&expected);
+    let actual = String::from_utf8_lossy(actual);
+    let line = expected
+        .lines()
+        .zip(actual.lines())
+        .position(|(e, a)| e != a)
+        .unwrap_or_else(|| expected.lines().count().min(actual.lines().count()));
+    Some(format!(
+        "{}:{}\n  expected: {}\n    actual: {}",
+        path.display(),
+        line + 1,
+        expected.lines().nth(line).unwrap_or("<end>"),
+        actual.lines().nth(line).unwrap_or("<end>"),
+    ))
+}
+
+/// Compare the recording, the printer output and the decoded events with the golden files
+fn golden(f: Fixture) {
+    // The recording first, the outputs are read from it
+    let spec = TraceSpec::load(fixtures_dir().join(format!("{}.toml", f.name))).unwrap();
+    let mut psf = Vec::new();
+    spec.generate(&mut psf).unwrap();
+    let mut mismatches = Vec::new();
+    mismatches.extend(check_golden(&f.psf(), &psf));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_trace-recorder-printer"))
+        .args(f.args())
+        .env("RUST_LOG", "off")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}: {output:?}", f.name);
+    mismatches.extend(check_golden(
+        &snapshots_dir().join(format!("{}.txt", f.name)),
+        &output.stdout,
+    ));
+
+    let mut json = serde_json::to_string_pretty(&decode(&f)).unwrap();
+    json.push('\n');
+    mismatches.extend(check_golden(
+        &snapshots_dir().join(format!("{}.json", f.name)),
+        json.as_bytes(),
+    ));
+    assert!(
+        mismatches.is_empty(),
+        "{} golden files differ, rerun with UPDATE_SNAPSHOTS=1 if the change is intended\n{}",
+        mismatches.len(),
+        mismatches.join("\n")
+    );
+}
+
+#[test]
+fn freertos_le() {
+    golden(Fixture::new("freertos_le"));
+}
+
+#[test]
+fn freertos_be() {
+    golden(Fixture::new("freertos_be"));
+}
+
+#[test]
+fn zephyr() {
+    golden(Fixture::new("zephyr"));
+}
+
+#[test]
+fn threadx() {
+    golden(Fixture::new("threadx"));
+}
+
+#[test]
+fn restart() {
+    golden(Fixture::new("restart"));
+}
+
+#[test]
+fn dropped() {
+    golden(Fixture::new("dropped"));
+}
+
+#[test]
+fn custom_printf() {
+    golden(Fixture {
+        custom_printf_event_id: Some(0x1F0),
+        ..Fixture::new("custom_printf")
+    });
+}
+
+#[test]
+fn corrupted() {
+    golden(Fixture {
+        resync: true,
+        ..Fixture::new("corrupted")
+    });
+}
+
+#[test]
+fn timer_glitch() {
+    golden(Fixture::new("timer_glitch"));
+}
+
+#[test]
+fn blocking() {
+    golden(Fixture::new("blocking"));
+}
+
+/// Collects the events as JSON
+#[derive(Default)]
+struct Events(Vec<Value>);
+
+impl Analyzer for Events {
+    fn on_event(&mut self, _rd: &RecorderData, record: &EventRecord) {
+        self.0.push(json!({
+            "offset": record.offset,
+            "core": record.core_id,
+            "count": record.event_count,
+            "time": record.trace_time.get_raw(),
+            "dropped": record.dropped_events,
+            "type": record.event_type.to_string(),
+            "event": record.event.to_string(),
+        }));
+    }
+}
+
+/// Header, summary, events and statistics of a fixture, through the library
+fn decode(f: &Fixture) -> Value {
+    let mut reader = TraceReader::new(BufReader::new(File::open(f.psf()).unwrap())).unwrap();
+    if let Some(id) = f.custom_printf_event_id {
+        reader.set_custom_printf_event_id(id);
+    }
+    reader.set_resync(f.resync);
+
+    let mut events = Events::default();
+    let mut contexts = ContextAnalyzer::default();
+    let mut stacks = StackAnalyzer::default();
+    let mut types = EventTypeCounter::default();
+    let summary = reader
+        .run(&mut [&mut events, &mut contexts, &mut stacks, &mut types])
+        .unwrap();
+    let rd = reader.recorder_data();
+    let report = TraceReport::new(rd, &summary, &contexts, &stacks, &types, None, None);
+
+    let contexts: serde_json::Map<String, Value> = report
+        .contexts
+        .iter()
+        .map(|(name, ctx)| {
+            let value = json!({
+                "kind": ctx.kind.to_string(),
+                "count": ctx.count,
+                "total_runtime": ctx.total_runtime.get_raw(),
+                "cpu_percent": format!("{:.2}", ctx.cpu_percent),
+                "runtime": [ctx.runtime.min, ctx.runtime.p50, ctx.runtime.p90, ctx.runtime.max],
+                "stack_low_mark": ctx.stack_low_mark,
+                "dropped_event_gaps": ctx.dropped_event_gaps,
+            });
+            (name.clone(), value)
+        })
+        .collect();
+
+    json!({
+        "header": {
+            "endianness": format!("{:?}", rd.header.endianness),
+            "format_version": rd.header.format_version,
+            "kernel_port": rd.header.kernel_port.to_string(),
+            "timer_frequency": rd.timestamp_info.timer_frequency.get_raw(),
+        },
+        "summary": {
+            "total_events": summary.total_events,
+            "dropped_events": summary.dropped_events,
+            "restarts": summary.restarts,
+            "total_time": summary.total_time.get_raw(),
+            "skipped": summary
+                .skipped
+                .iter()
+                .map(|r| json!({ "offset": r.offset, "len": r.len }))
+                .collect::<Vec<_>>(),
+        },
+        "contexts": contexts,
+        "event_types": report.event_types,
+        "events": events.0,
+    })
+}